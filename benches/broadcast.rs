@@ -0,0 +1,132 @@
+//! Measures broadcast throughput: the time for messages to reach 100 connected clients,
+//! from a single sender and from 10 or 50 senders at once. With many senders the
+//! client list is read concurrently by every connection, which only takes a shared
+//! lock.
+//!
+//! Run with `cargo bench --bench broadcast`.
+
+use chat_app::server::{ChatServer, ServerConfig};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::time::{Duration, Instant};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    runtime::Runtime,
+    task::JoinSet,
+};
+
+/// The number of simulated clients receiving each broadcast.
+const CLIENTS: usize = 100;
+
+/// The numbers of clients broadcasting at the same time in the concurrent benchmarks.
+const SENDERS: [usize; 2] = [10, 50];
+
+fn broadcast_throughput(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+
+    // Lift the per-client limits so the sender is never throttled
+    let config = ServerConfig {
+        rate_limit_per_sec: u32::MAX,
+        rate_limit_burst: u32::MAX,
+        flood_limit: usize::MAX,
+        // Every client connects from localhost
+        max_connections_per_ip: None,
+        // Pings would be read as broadcasts, and the reaping of idle clients would
+        // cut long runs short
+        heartbeat_interval: None,
+        idle_timeout: None,
+        ..ServerConfig::default()
+    };
+    let server = rt
+        .block_on(ChatServer::bind_with_config("127.0.0.1:0", config))
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let server = std::sync::Arc::new(server);
+    rt.spawn({
+        let server = server.clone();
+        async move { server.run().await }
+    });
+
+    // Each client's write half is separate, so senders can be moved into their own tasks
+    let (mut clients, mut writers) = rt.block_on(async {
+        let mut clients: Vec<BufReader<OwnedReadHalf>> = Vec::with_capacity(CLIENTS);
+        let mut writers: Vec<OwnedWriteHalf> = Vec::with_capacity(CLIENTS);
+        for _ in 0..CLIENTS {
+            let (reader, writer) = TcpStream::connect(addr).await.unwrap().into_split();
+            let mut client = BufReader::new(reader);
+            let mut id_line = String::new();
+            client.read_line(&mut id_line).await.unwrap();
+            // Everyone already connected hears the newcomer join
+            for earlier in clients.iter_mut() {
+                let mut notice = String::new();
+                earlier.read_line(&mut notice).await.unwrap();
+            }
+            clients.push(client);
+            writers.push(writer);
+        }
+        (clients, writers)
+    });
+
+    c.bench_function("broadcast_to_100_clients", |b| {
+        b.iter_custom(|iters| {
+            rt.block_on(async {
+                let mut elapsed = Duration::ZERO;
+                let mut line = String::new();
+                for _ in 0..iters {
+                    let start = Instant::now();
+                    writers[0].write_all(b"benchmark\n").await.unwrap();
+                    for client in clients.iter_mut() {
+                        line.clear();
+                        client.read_line(&mut line).await.unwrap();
+                    }
+                    elapsed += start.elapsed();
+                }
+                elapsed
+            })
+        })
+    });
+
+    for senders in SENDERS {
+        c.bench_with_input(
+            BenchmarkId::new("concurrent_broadcast_to_100_clients", senders),
+            &senders,
+            |b, &senders| {
+                b.iter_custom(|iters| {
+                    rt.block_on(async {
+                        let mut elapsed = Duration::ZERO;
+                        let mut line = String::new();
+                        for _ in 0..iters {
+                            let start = Instant::now();
+                            // Every sender writes from its own task, so the writes overlap
+                            let mut sends = JoinSet::new();
+                            for mut writer in writers.drain(..senders) {
+                                sends.spawn(async move {
+                                    writer.write_all(b"benchmark\n").await.unwrap();
+                                    writer
+                                });
+                            }
+                            for client in clients.iter_mut() {
+                                for _ in 0..senders {
+                                    line.clear();
+                                    client.read_line(&mut line).await.unwrap();
+                                }
+                            }
+                            elapsed += start.elapsed();
+                            // Any write half can send the next round
+                            writers.splice(..0, sends.join_all().await);
+                        }
+                        elapsed
+                    })
+                })
+            },
+        );
+    }
+
+    server.shutdown();
+}
+
+criterion_group!(benches, broadcast_throughput);
+criterion_main!(benches);
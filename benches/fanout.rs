@@ -0,0 +1,74 @@
+//! Compares the two ways of handing one broadcast to many client queues: copying the
+//! encoded bytes for every recipient, or sharing a single `Arc<[u8]>`.
+//!
+//! Each iteration fans one message out to 500 fake clients (bounded queues, like the
+//! server's per-client writer queues) and drains them again.
+//!
+//! Run with `cargo bench --bench fanout`.
+
+use chat_app::protocol::{Protocol, ServerMessage};
+use chat_app::server::SEND_QUEUE_CAPACITY;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// The number of fake clients receiving each broadcast.
+const CLIENTS: usize = 500;
+
+fn fanout(c: &mut Criterion) {
+    let message = ServerMessage::Broadcast {
+        from: 1,
+        nick: None,
+        body: "The quick brown fox jumps over the lazy dog".to_string(),
+        timestamp: None,
+    };
+
+    let mut group = c.benchmark_group("fanout_to_500_clients");
+    group.throughput(Throughput::Elements(1));
+
+    for protocol in [Protocol::Text, Protocol::Json] {
+        let encoded = protocol.encode(&message);
+
+        let queues: Vec<_> = (0..CLIENTS)
+            .map(|_| mpsc::channel::<Vec<u8>>(SEND_QUEUE_CAPACITY))
+            .collect();
+        let (senders, mut receivers): (Vec<_>, Vec<_>) = queues.into_iter().unzip();
+        group.bench_function(
+            BenchmarkId::new("copy_per_client", format!("{:?}", protocol)),
+            |b| {
+                b.iter(|| {
+                    for sender in &senders {
+                        sender.try_send(encoded.clone()).unwrap();
+                    }
+                    for receiver in receivers.iter_mut() {
+                        receiver.try_recv().unwrap();
+                    }
+                })
+            },
+        );
+
+        let queues: Vec<_> = (0..CLIENTS)
+            .map(|_| mpsc::channel::<Arc<[u8]>>(SEND_QUEUE_CAPACITY))
+            .collect();
+        let (senders, mut receivers): (Vec<_>, Vec<_>) = queues.into_iter().unzip();
+        group.bench_function(
+            BenchmarkId::new("shared_arc", format!("{:?}", protocol)),
+            |b| {
+                b.iter(|| {
+                    let shared: Arc<[u8]> = encoded.as_slice().into();
+                    for sender in &senders {
+                        sender.try_send(shared.clone()).unwrap();
+                    }
+                    for receiver in receivers.iter_mut() {
+                        receiver.try_recv().unwrap();
+                    }
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, fanout);
+criterion_main!(benches);
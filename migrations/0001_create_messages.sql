@@ -0,0 +1,7 @@
+-- Chat messages broadcast by the server, replayed to clients on join and by /history
+CREATE TABLE IF NOT EXISTS messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    sender_id INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    timestamp TEXT NOT NULL
+);
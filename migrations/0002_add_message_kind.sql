@@ -0,0 +1,2 @@
+-- Whether each message was sent as text or as a /me action; older rows are text
+ALTER TABLE messages ADD COLUMN kind TEXT NOT NULL DEFAULT 'chat';
@@ -3,107 +3,1714 @@
 //! ## Overview
 //! This module establishes a connection to the chat server, sends user input as messages,
 //! and displays messages received from the server. It handles both broadcast and private
-//! messages and tags the client's own messages with `(Me)` for clarity.
+//! messages, and shows the client's own messages as `You: ...` the moment they are sent
+//! rather than when the server sends them back.
 //!
 //! ## Key Features
 //! - Connects to the server and identifies as a unique client.
 //! - Sends user input to the server for broadcasting or private messaging.
-//! - Displays incoming messages in real-time, distinguishing private messages and self-messages.
+//! - Displays incoming messages in real-time, distinguishing private messages from the room's.
+//! - Echoes the user's own messages locally and skips the server's copies of them.
+//! - Understands both the plain text and the JSON [`Protocol`], detected from the server's greeting.
+//! - Answers the server's heartbeat pings without displaying them.
+//! - Colors private messages, server notices, errors and its own messages on a terminal.
+//! - Lets the user pick a nickname or log in when the server asks for it.
+//! - Hides the messages of users muted with `/ignore` (see [`crate::ignore`]).
+//! - Encrypts private messages end to end, so the server only relays ciphertext (see [`crate::e2e`]).
+//! - Answers `/help` itself, listing the server's commands and its own ([`CLIENT_COMMANDS`]).
+//! - Recalls earlier lines with the arrow keys, also across sessions (see [`crate::input`]).
+//! - Disconnects cleanly when the user types `/quit` or closes standard input.
+//! - Reconnects with exponential backoff when the server goes away (see [`ClientOptions`]).
+//! - Encrypts the connection with TLS when the address starts with [`TLS_SCHEME`].
+//! - Connects through a SOCKS5 proxy, such as Tor's, when one is set (see [`crate::proxy`]).
+//! - Chats over WebSocket with `run_ws_client`, for servers that accept [`WEBSOCKET_SCHEME`] URLs.
 
+use crate::color::{Colorize, MessageType};
+use crate::command::{find_command, parse_command, Command, CommandInfo, COMMANDS};
+use crate::e2e::{is_envelope, parse_public_key, ClientKeys};
+use crate::error::ChatError;
+use crate::ignore::{IgnoreList, LOCAL_PREFIX};
+use crate::input::{
+    default_history_file, forget_next_line, read_input, restore_terminal, show, show_error,
+    show_prompt,
+};
+use crate::protocol::{
+    parse_ping, parse_public_key_line, pong, read_message, split_timestamp, Protocol, ServerMessage,
+};
+use crate::proxy::{connect_via_proxy, ProxyConfig};
+use crate::server::{
+    ALREADY_LOGGED_IN_NOTICE, ANNOUNCEMENT_PREFIX, AUTHENTICATION_FAILED_NOTICE, ECHO_OFF_NOTICE,
+};
+use crate::socket::SocketOptions;
+use chrono::{SecondsFormat, Utc};
+use std::{
+    collections::VecDeque,
+    io::IsTerminal,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+#[cfg(unix)]
+use tokio::net::UnixStream;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
     net::TcpStream,
 };
+#[cfg(feature = "tls")]
+use tokio_rustls::{rustls::pki_types::ServerName, TlsConnector};
+use x25519_dalek::PublicKey;
 
-/// Starts the client and connects to the server.
+/// The prefix of an address the client connects to with TLS, as in
+/// `tls://chat.example.com:8443`.
+pub const TLS_SCHEME: &str = "tls://";
+
+/// The prefix of a WebSocket URL, as in `ws://chat.example.com:8080`.
+pub const WEBSOCKET_SCHEME: &str = "ws://";
+
+/// The commands the client answers itself, which never reach the server.
 ///
-/// This function establishes a connection to the server, reads the assigned client ID,
-/// and spawns tasks to handle reading and writing messages. It facilitates interaction
-/// between the user and the server.
+/// `/help` lists them after the server's [`COMMANDS`].
+pub const CLIENT_COMMANDS: &[CommandInfo] = &[
+    CommandInfo {
+        name: "/ignore",
+        usage: "/ignore <nickname>",
+        description: "Hide someone's messages",
+        details: "Nobody is told, and the list lasts until the client exits. See /ignorelist.",
+    },
+    CommandInfo {
+        name: "/unignore",
+        usage: "/unignore <nickname>",
+        description: "Show someone's messages again",
+        details: "Messages sent while they were ignored are not shown.",
+    },
+    CommandInfo {
+        name: "/ignorelist",
+        usage: "/ignorelist",
+        description: "Show whose messages you are hiding",
+        details: "The list is kept by this client and never sent to the server.",
+    },
+    CommandInfo {
+        name: "/quit",
+        usage: "/quit",
+        description: "Leave the chat",
+        details: "Pressing Ctrl-C or closing standard input with Ctrl-D does the same.",
+    },
+];
+
+/// How the client connects to the server, and what it does when the connection is lost.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientOptions {
+    /// Whether to reconnect after the connection is lost or cannot be made, instead of
+    /// exiting. Scripts usually want this off.
+    pub reconnect: bool,
+    /// How many attempts in a row may fail before the client gives up, or `None` to
+    /// keep trying forever.
+    pub max_retries: Option<u32>,
+    /// The wait before the first attempt, doubled after every failed one.
+    pub initial_backoff: Duration,
+    /// The longest wait between two attempts.
+    pub max_backoff: Duration,
+    /// The TCP options set on the connection to the server.
+    pub socket_options: SocketOptions,
+    /// Whether to show the time the server stamped each chat message with.
+    pub show_timestamps: bool,
+    /// Whether to color messages by their [`MessageType`]. Colors are only ever
+    /// written to a terminal, never to a pipe or a file.
+    pub color: bool,
+    /// Where to keep the lines typed at a terminal, so the arrow keys can recall them
+    /// in later sessions, or `None` to forget them on exit. `~/.chat_history` by
+    /// default.
+    pub history_file: Option<PathBuf>,
+    /// A proxy to reach the TCP address through, or `None` to connect directly. It
+    /// is not used for a Unix domain socket or a WebSocket URL.
+    pub proxy: Option<ProxyConfig>,
+    /// A Unix domain socket to connect to instead of the TCP address.
+    #[cfg(unix)]
+    pub unix_socket: Option<PathBuf>,
+    /// A PEM file of certificate authorities to trust for [`TLS_SCHEME`] addresses,
+    /// such as a self-signed server's certificate, instead of the well-known ones.
+    #[cfg(feature = "tls")]
+    pub tls_ca: Option<PathBuf>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            reconnect: true,
+            max_retries: Some(10),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            socket_options: SocketOptions::default(),
+            show_timestamps: true,
+            color: true,
+            history_file: default_history_file(),
+            proxy: None,
+            #[cfg(unix)]
+            unix_socket: None,
+            #[cfg(feature = "tls")]
+            tls_ca: None,
+        }
+    }
+}
+
+impl ClientOptions {
+    /// Returns how long to wait before the attempt that follows `failures` failed ones:
+    /// `initial_backoff`, doubled for each failure, capped at `max_backoff`.
+    pub fn backoff(&self, failures: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(failures))
+            .min(self.max_backoff)
+    }
+}
+
+/// The handshake to perform on a connection to a [`TLS_SCHEME`] address.
+#[cfg(feature = "tls")]
+struct TlsTarget {
+    connector: TlsConnector,
+    server_name: ServerName<'static>,
+}
+
+/// Without the `tls` feature, no address asks for a handshake.
+#[cfg(not(feature = "tls"))]
+enum TlsTarget {}
+
+/// Splits [`TLS_SCHEME`] off `address`, preparing the TLS handshake it asks for.
+///
+/// # Returns
+/// The address to connect to, and the handshake to perform if it had the prefix.
+///
+/// # Errors
+/// Returns [`ChatError::Config`] if the address has the prefix but its host is not a
+/// valid server name, the CA file in `options` cannot be loaded, or TLS support is not
+/// compiled in.
+fn parse_address<'a>(
+    address: &'a str,
+    options: &ClientOptions,
+) -> Result<(&'a str, Option<TlsTarget>), ChatError> {
+    let Some(address) = address.strip_prefix(TLS_SCHEME) else {
+        return Ok((address, None));
+    };
+
+    #[cfg(feature = "tls")]
+    {
+        // The certificate is checked against the host, without the port or the
+        // brackets around an IPv6 address
+        let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let server_name = ServerName::try_from(host.to_string())
+            .map_err(|e| ChatError::Config(format!("invalid server name {}: {}", host, e)))?;
+        let config = crate::tls::load_client_config(options.tls_ca.as_deref())
+            .map_err(|e| ChatError::Config(format!("failed to load TLS roots: {}", e)))?;
+        Ok((
+            address,
+            Some(TlsTarget {
+                connector: TlsConnector::from(config),
+                server_name,
+            }),
+        ))
+    }
+    #[cfg(not(feature = "tls"))]
+    {
+        let _ = (address, options);
+        Err(ChatError::Config(
+            "TLS support is not enabled in this build".to_string(),
+        ))
+    }
+}
+
+/// How a connection to the server ended.
+enum Session {
+    /// The user typed `/quit` or closed standard input.
+    Quit,
+    /// The server closed the connection, after admitting the client or before.
+    Lost { admitted: bool },
+}
+
+/// Starts the client and connects to the server, reconnecting with the default
+/// [`ClientOptions`] if the connection is lost.
 ///
 /// # Arguments
 /// * `address` - A string slice representing the server address (e.g., "127.0.0.1:8080").
 ///
 /// # Errors
-/// Returns an error if the connection to the server fails or if message processing encounters an issue.
+/// Returns an error if the client gives up reconnecting to the server.
 ///
 /// # Example
 /// ```no_run
-/// use my_client::run_client;
+/// use chat_app::client::run_client;
 ///
 /// #[tokio::main]
 /// async fn main() {
 ///     run_client("127.0.0.1:8080").await.unwrap();
 /// }
 /// ```
-pub async fn run_client(address: &str) -> std::io::Result<()> {
+pub async fn run_client(address: &str) -> Result<(), ChatError> {
+    run_client_with_options(address, ClientOptions::default()).await
+}
+
+/// Starts the client and connects to the server.
+///
+/// This function establishes a connection to the server, answers the server's nickname
+/// and login prompts with lines typed by the user, reads the assigned client ID, and
+/// spawns tasks to handle reading and writing messages. It facilitates interaction
+/// between the user and the server.
+///
+/// If the connection is lost or cannot be made and `options.reconnect` is set, the
+/// client prints `[Reconnecting] Attempt N/max in ...` to standard error, waits for
+/// [`ClientOptions::backoff`] and tries again, printing `Reconnected as Client N` once
+/// the server admits it. The nickname the user picked is sent again without asking
+/// when the server prompts for one. Lines typed while the client is disconnected are
+/// dropped rather than sent after reconnecting, as they were written for a
+/// conversation that has moved on; `/quit` still exits.
+///
+/// # Arguments
+/// * `address` - A string slice representing the server address (e.g., "127.0.0.1:8080"),
+///   prefixed with [`TLS_SCHEME`] to encrypt the connection.
+/// * `options` - Whether and how to reconnect, and the TCP options to connect with.
+///
+/// # Errors
+/// - [`ChatError::Config`] if a `tls://` address cannot be used.
+/// - [`ChatError::Auth`] if the server refuses the login, whether or not
+///   `options.reconnect` is set.
+/// - [`ChatError::Io`] or [`ChatError::Protocol`] if the connection fails or the
+///   server's greeting cannot be understood, and `options.reconnect` is off.
+/// - [`ChatError::Io`] once `options.max_retries` attempts in a row have failed.
+pub async fn run_client_with_options(
+    address: &str,
+    options: ClientOptions,
+) -> Result<(), ChatError> {
+    let (address, tls) = parse_address(address, &options)?;
+    let mut rx = read_input(options.history_file.clone());
+    let result = stay_connected(address, tls.as_ref(), &options, &mut rx).await;
+    restore_terminal();
+    result
+}
+
+/// Runs sessions with the server until the user quits, reconnecting as `options` allow
+/// (see [`run_client_with_options`]).
+///
+/// # Errors
+/// Returns an error if a session fails and `options.reconnect` is off, if the server
+/// refuses the login, or once `options.max_retries` attempts in a row have failed.
+async fn stay_connected(
+    address: &str,
+    tls: Option<&TlsTarget>,
+    options: &ClientOptions,
+    rx: &mut tokio::sync::mpsc::Receiver<String>,
+) -> Result<(), ChatError> {
+    let mut admitted_before = false;
+    let mut kept = Kept::new();
+    let mut failures = 0;
+    loop {
+        let session = run_session(address, tls, options, rx, admitted_before, &mut kept);
+        match session.await {
+            Ok(Session::Quit) => return Ok(()),
+            // A server that closes the connection has said why, such as being full
+            Ok(Session::Lost { .. }) if !options.reconnect => return Ok(()),
+            Ok(Session::Lost { admitted }) => {
+                if admitted {
+                    admitted_before = true;
+                    failures = 0;
+                }
+                show("Connection to the server lost");
+            }
+            // Trying again would only be refused again
+            Err(e @ ChatError::Auth(_)) => return Err(e),
+            Err(e) if !options.reconnect => return Err(e),
+            Err(e) => show_error(format!("Failed to connect to {}: {}", address, e)),
+        }
+
+        if options.max_retries.is_some_and(|max| failures >= max) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                format!("gave up after {} attempts to reconnect", failures),
+            )
+            .into());
+        }
+        let delay = options.backoff(failures);
+        failures += 1;
+        match options.max_retries {
+            Some(max) => show_error(format!(
+                "[Reconnecting] Attempt {}/{} in {:?}...",
+                failures, max, delay
+            )),
+            None => show_error(format!(
+                "[Reconnecting] Attempt {} in {:?}...",
+                failures, delay
+            )),
+        }
+        if !wait_to_reconnect(rx, delay).await {
+            return Ok(());
+        }
+    }
+}
+
+/// Starts the client over WebSocket and chats until the user quits or the server
+/// goes away.
+///
+/// The server's messages arrive one per WebSocket message, in whichever protocol it
+/// speaks, and are displayed as they would be over TCP. The client does not reconnect.
+///
+/// # Arguments
+/// * `url` - The server's URL, starting with [`WEBSOCKET_SCHEME`] (e.g., "ws://127.0.0.1:8080").
+///
+/// # Errors
+/// Returns an error if the WebSocket cannot be opened or the connection fails before
+/// the client is admitted.
+///
+/// # Example
+/// ```no_run
+/// use chat_app::client::run_ws_client;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     run_ws_client("ws://127.0.0.1:8080").await.unwrap();
+/// }
+/// ```
+#[cfg(feature = "websocket")]
+pub async fn run_ws_client(url: &str) -> Result<(), ChatError> {
+    let mut rx = read_input(default_history_file());
+    let session = run_ws_session(url, &mut rx).await;
+    restore_terminal();
+    if let Session::Lost { .. } = session? {
+        show("Connection to the server lost");
+    }
+    Ok(())
+}
+
+/// Opens a WebSocket to `url` and chats over it once (see [`run_ws_client`]).
+///
+/// # Errors
+/// Returns an error if the WebSocket cannot be opened or fails before the client is
+/// admitted.
+#[cfg(feature = "websocket")]
+async fn run_ws_session(
+    url: &str,
+    rx: &mut tokio::sync::mpsc::Receiver<String>,
+) -> Result<Session, ChatError> {
+    use crate::codec::{detect_protocol, Codec};
+    use crate::websocket::{bridge, BRIDGE_BUFFER};
+    use futures_util::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let (mut websocket, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .map_err(std::io::Error::other)?;
+
+    // The protocol is only known once the server has sent something
+    let first = loop {
+        match websocket.next().await {
+            Some(Ok(Message::Text(text))) => break text.to_string(),
+            Some(Ok(Message::Close(_))) | None => return Ok(Session::Lost { admitted: false }),
+            Some(Err(e)) => return Err(std::io::Error::other(e).into()),
+            Some(Ok(_)) => continue,
+        }
+    };
+    let codec = Codec::new(detect_protocol(&first));
+
+    // Chat over an in-memory pipe, as if the WebSocket were a socket
+    let (stream, mut far_end) = tokio::io::duplex(BRIDGE_BUFFER);
+    far_end.write_all(&codec.encode(&first)?).await?;
+    let bridging = tokio::spawn(bridge(websocket, far_end, codec));
+    let (reader, writer) = tokio::io::split(stream);
+    let rendering = Rendering::new(&ClientOptions::default());
+    let session = chat(reader, writer, rx, false, &mut Kept::new(), rendering).await;
+    let _ = bridging.await;
+    session
+}
+
+/// Waits `delay` before the next connection attempt, dropping the lines typed meanwhile.
+///
+/// # Returns
+/// `false` if the user typed `/quit` or closed standard input instead.
+async fn wait_to_reconnect(rx: &mut tokio::sync::mpsc::Receiver<String>, delay: Duration) -> bool {
+    let wait = tokio::time::sleep(delay);
+    tokio::pin!(wait);
+    loop {
+        tokio::select! {
+            _ = &mut wait => return true,
+            line = rx.recv() => match line {
+                Some(line) if line.trim() != "/quit" => {
+                    show(format!("Not connected, message dropped: {}", line));
+                }
+                _ => return false,
+            },
+        }
+    }
+}
+
+/// What the client keeps from one connection to the next.
+#[derive(Debug)]
+struct Kept {
+    /// The nickname an earlier connection was admitted with, sent again when the
+    /// server prompts for one.
+    nickname: Option<String>,
+    /// The users whose messages are not displayed, changed by `/ignore` and `/unignore`.
+    ignored: IgnoreList,
+    /// The keys private messages are encrypted with. They stay the same, so messages
+    /// sealed for this client while it was away still open.
+    keys: Arc<ClientKeys>,
+}
+
+impl Kept {
+    /// Starts with no nickname, nobody ignored and new keys.
+    fn new() -> Self {
+        Self {
+            nickname: None,
+            ignored: IgnoreList::new(),
+            keys: Arc::new(ClientKeys::generate()),
+        }
+    }
+}
+
+/// Connects to the server once and chats until the user quits or the connection ends.
+///
+/// # Arguments
+/// * `address` - The server address.
+/// * `tls` - The TLS handshake to perform once connected, if any.
+/// * `options` - The TCP options to connect with.
+/// * `rx` - The lines typed by the user.
+/// * `reconnecting` - Whether an earlier connection was admitted; if so, lines typed
+///   before this connection was made are dropped and the ID is announced as a reconnection.
+/// * `kept` - What earlier connections left behind, such as the nickname to send
+///   again when the server prompts for one; updated once this connection is admitted.
+///
+/// # Errors
+/// Returns an error if the connection cannot be made or fails before the client is
+/// admitted.
+async fn run_session(
+    address: &str,
+    tls: Option<&TlsTarget>,
+    options: &ClientOptions,
+    rx: &mut tokio::sync::mpsc::Receiver<String>,
+    reconnecting: bool,
+    kept: &mut Kept,
+) -> Result<Session, ChatError> {
     // Establish a connection to the server
-    let socket = TcpStream::connect(address).await?;
-    let (reader, mut writer) = socket.into_split();
+    #[cfg(unix)]
+    if let Some(path) = &options.unix_socket {
+        let (reader, writer) = UnixStream::connect(path).await?.into_split();
+        return chat(
+            reader,
+            writer,
+            rx,
+            reconnecting,
+            kept,
+            Rendering::new(options),
+        )
+        .await;
+    }
+    let socket = match &options.proxy {
+        Some(proxy) => connect_via_proxy(proxy, address).await?,
+        None => TcpStream::connect(address).await?,
+    };
+    options.socket_options.apply(&socket)?;
+    // A TLS stream cannot be split into owned halves like a socket, so it is split
+    // through a lock instead
+    #[cfg(feature = "tls")]
+    if let Some(tls) = tls {
+        let stream = tls
+            .connector
+            .connect(tls.server_name.clone(), socket)
+            .await?;
+        let (reader, writer) = tokio::io::split(stream);
+        return chat(
+            reader,
+            writer,
+            rx,
+            reconnecting,
+            kept,
+            Rendering::new(options),
+        )
+        .await;
+    }
+    #[cfg(not(feature = "tls"))]
+    if let Some(never) = tls {
+        match *never {}
+    }
+    let (reader, writer) = socket.into_split();
+    chat(
+        reader,
+        writer,
+        rx,
+        reconnecting,
+        kept,
+        Rendering::new(options),
+    )
+    .await
+}
+
+/// Chats over an established connection until the user quits or the connection ends.
+///
+/// # Arguments
+/// * `reader` - The read half of the connection.
+/// * `writer` - The write half of the connection.
+/// * `rx` - The lines typed by the user.
+/// * `reconnecting` - Whether an earlier connection was admitted (see [`run_session`]).
+/// * `kept` - The nickname to claim again, the users whose messages are not
+///   displayed and the keys private messages are encrypted with (see [`Kept`]).
+/// * `rendering` - How to show the messages received.
+///
+/// # Errors
+/// Returns an error if the connection fails before the client is admitted, or
+/// [`ChatError::Auth`] if the server turns its login away.
+async fn chat<R, W>(
+    reader: R,
+    mut writer: W,
+    rx: &mut tokio::sync::mpsc::Receiver<String>,
+    reconnecting: bool,
+    kept: &mut Kept,
+    rendering: Rendering,
+) -> Result<Session, ChatError>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin,
+{
     let mut buf_reader = BufReader::new(reader);
 
-    // Create a communication channel between tasks
-    let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(10);
-
-    // Read and parse the client ID sent by the server
-    let mut id_line = String::new();
-    buf_reader.read_line(&mut id_line).await?;
-    let my_id: usize = id_line
-        .trim()
-        .strip_prefix("Your ID: ")
-        .unwrap()
-        .parse()
-        .unwrap();
+    // Lines typed while disconnected must not answer the server's prompts
+    if reconnecting {
+        while let Ok(line) = rx.try_recv() {
+            if line.trim() == "/quit" {
+                return Ok(Session::Quit);
+            }
+        }
+    }
+
+    // The read task asks the main loop to answer heartbeat pings through this channel,
+    // and hands over the public keys the server sends through the other
+    let (pong_tx, mut pong_rx) = tokio::sync::mpsc::channel::<u64>(1);
+    let (key_tx, mut key_rx) = tokio::sync::mpsc::channel::<ServerMessage>(16);
 
-    println!("Connected as Client {}", my_id);
+    // Detect the server's protocol from its greeting. A frame starts with a length
+    // prefix whose first byte is zero, which a text line never does.
+    let protocol = match buf_reader.fill_buf().await?.first() {
+        Some(0) => Protocol::Json,
+        _ => Protocol::Text,
+    };
+
+    // Answer nickname and login prompts until the server sends the client ID
+    let mut my_nick = None;
+    let mut resend = kept.nickname.clone();
+    let mut claimed = None;
+    let my_id = loop {
+        match read_greeting(&mut buf_reader, protocol).await? {
+            Some(ServerMessage::Welcome { id }) => break id,
+            Some(
+                prompt @ (ServerMessage::NicknamePrompt
+                | ServerMessage::UsernamePrompt
+                | ServerMessage::PasswordPrompt),
+            ) => {
+                // The remembered nickname is only sent once: if the server asks again,
+                // someone took it while the client was away
+                let answer = match resend.take() {
+                    Some(nick) if prompt == ServerMessage::NicknamePrompt => nick,
+                    _ => {
+                        if prompt == ServerMessage::PasswordPrompt {
+                            forget_next_line();
+                        }
+                        show_prompt(&prompt)?;
+                        let Some(answer) =
+                            rx.recv().await.filter(|answer| answer.trim() != "/quit")
+                        else {
+                            return Ok(Session::Quit);
+                        };
+                        answer
+                    }
+                };
+                writer.write_all(&protocol.encode_input(&answer)?).await?;
+                if prompt == ServerMessage::NicknamePrompt {
+                    claimed = Some(answer.trim().to_string());
+                }
+                // Logged-in clients chat under their username
+                if prompt != ServerMessage::PasswordPrompt {
+                    my_nick = Some(answer.trim().to_string());
+                }
+            }
+            // The server disconnects right after saying so
+            Some(ServerMessage::System { text })
+                if text == AUTHENTICATION_FAILED_NOTICE || text == ALREADY_LOGGED_IN_NOTICE =>
+            {
+                return Err(ChatError::Auth(text));
+            }
+            Some(message) => show(message),
+            None => return Ok(Session::Lost { admitted: false }),
+        }
+    };
+
+    if claimed.is_some() {
+        kept.nickname = claimed;
+    }
+
+    let verb = if reconnecting {
+        "Reconnected"
+    } else {
+        "Connected"
+    };
+    match &my_nick {
+        Some(nick) => show(format!("{} as {} (Client {})", verb, nick, my_id)),
+        None => show(format!("{} as Client {}", verb, my_id)),
+    }
+    let encryption = Encryption::new(kept.keys.clone());
+    writer
+        .write_all(&protocol.encode_input(&encryption.register(my_id))?)
+        .await?;
+    // The user's own messages are shown as they are sent, so the server need not send
+    // them back; JSON messages say who sent them, so only text lines need this
+    if protocol == Protocol::Text {
+        writer
+            .write_all(&protocol.encode_input("/echo off")?)
+            .await?;
+    }
 
     // Task to handle incoming messages from the server
-    let read_task = tokio::spawn(async move {
-        let mut line = String::new();
-        while let Ok(bytes_read) = buf_reader.read_line(&mut line).await {
-            if bytes_read == 0 {
-                break; // Server connection closed
+    let muted = kept.ignored.clone();
+    let opener = encryption.clone();
+    let mut read_task = tokio::spawn(async move {
+        match protocol {
+            Protocol::Json => loop {
+                match read_message(&mut buf_reader).await {
+                    Ok(Some(ServerMessage::Ping { nonce })) => {
+                        let _ = pong_tx.try_send(nonce);
+                    }
+                    // This client's own messages were shown when they were sent
+                    Ok(Some(
+                        ServerMessage::Broadcast { from, .. } | ServerMessage::Action { from, .. },
+                    )) if from == my_id => {}
+                    Ok(Some(message @ ServerMessage::PublicKey { .. })) => {
+                        let _ = key_tx.send(message).await;
+                    }
+                    Ok(Some(message)) if muted.hides_message(&message) => {}
+                    Ok(Some(message)) => {
+                        show(render_message(opener.open_message(message), rendering))
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                        show_error(format!("Ignoring malformed message from server: {}", e))
+                    }
+                    // Server connection closed
+                    Ok(None) | Err(_) => break,
+                }
+            },
+            Protocol::Text => {
+                let mut line = String::new();
+                let mut confirming_echo_off = true;
+                while let Ok(bytes_read) = buf_reader.read_line(&mut line).await {
+                    if bytes_read == 0 {
+                        break; // Server connection closed
+                    }
+                    if let Some(nonce) = parse_ping(&line) {
+                        let _ = pong_tx.try_send(nonce);
+                    } else if let Some(key) = parse_public_key_line(&line) {
+                        let _ = key_tx.send(key).await;
+                    } else if confirming_echo_off && line.trim_end() == ECHO_OFF_NOTICE {
+                        confirming_echo_off = false;
+                    } else if !muted.hides_text_line(&line) {
+                        show(render_text_line(&opener.open_text_line(&line), rendering));
+                    }
+                    line.clear();
+                }
             }
+        }
+    });
+
+    // Main loop to send user messages and heartbeat replies to the server, until
+    // `/quit`, the end of input, or the server going away
+    let session = loop {
+        // Only what the user typed is echoed, not the lines sent on their behalf
+        let (line, sent) = tokio::select! {
+            message = rx.recv() => match message {
+                Some(message) if message.trim() != "/quit" => {
+                    match prepare_input(&message, &kept.ignored, &encryption, rendering) {
+                        Some(line) => (line, Sent::from_input(&message)),
+                        None => continue,
+                    }
+                }
+                _ => break Session::Quit,
+            },
+            Some(nonce) = pong_rx.recv() => (pong(nonce), None),
+            Some(key) = key_rx.recv() => match encryption.key_arrived(&key) {
+                Some(Outgoing::Send(line)) => (line, None),
+                Some(Outgoing::Unencrypted { line, notice }) => {
+                    show(rendering.paint(&notice, MessageType::System));
+                    (line, None)
+                }
+                Some(Outgoing::Notice(notice)) => {
+                    show(rendering.paint(&notice, MessageType::System));
+                    continue;
+                }
+                Some(Outgoing::Nothing) => continue,
+                // Asked for with /getkey
+                None => {
+                    show(render_message(key, rendering));
+                    continue;
+                }
+            },
+            _ = &mut read_task => break Session::Lost { admitted: true },
+        };
+        let Ok(bytes) = protocol.encode_input(&line) else {
+            show_error("Message too long to send");
+            continue;
+        };
+        if writer.write_all(&bytes).await.is_err() {
+            break Session::Lost { admitted: true };
+        }
+        if let Some(sent) = sent {
+            show(rendering.paint(&sent.to_echo(rendering), MessageType::Own));
+        }
+    };
+
+    // Close our side of the connection so the server sees a clean disconnect
+    let _ = writer.shutdown().await;
+    read_task.abort();
+    Ok(session)
+}
 
-            // Display private messages with a "[Private]" tag
-            if line.contains("[Private]") {
-                println!("{}", line.trim());
-            } 
-            // Tag the client's own messages with "(Me)"
-            else if line.contains(&format!("Client {}:", my_id)) {
-                print!("{} (Me)\n", line.trim());
-            } 
-            // Display all other messages as received
-            else {
-                print!("{}", line);
+/// Carries out a line typed by the user that is meant for this client alone, and
+/// seals the private messages in it (see [`Encryption::outgoing`]).
+///
+/// # Returns
+/// The line to send to the server, or `None` if there is nothing to send.
+fn prepare_input(
+    line: &str,
+    ignored: &IgnoreList,
+    encryption: &Encryption,
+    rendering: Rendering,
+) -> Option<String> {
+    // `/help`, `/ignore` and its kin are for this client alone
+    if let Command::Help(topic) = parse_command(line) {
+        match help_lines(topic.as_deref()) {
+            Ok(lines) => {
+                for line in lines {
+                    show(rendering.paint(&line, MessageType::System));
+                }
             }
+            Err(error) => show(rendering.paint(&error, MessageType::Error)),
+        }
+        return None;
+    }
+    if let Some(reply) = ignored.handle_command(line) {
+        show(rendering.paint(&reply, MessageType::System));
+        return None;
+    }
+    match encryption.outgoing(line) {
+        Outgoing::Send(line) => Some(line),
+        Outgoing::Unencrypted { line, notice } => {
+            show(rendering.paint(&notice, MessageType::System));
+            Some(line)
+        }
+        Outgoing::Notice(notice) => {
+            show(rendering.paint(&notice, MessageType::System));
+            None
+        }
+        Outgoing::Nothing => None,
+    }
+}
 
-            line.clear();
+/// Reads the next message the server sends before the client is admitted.
+///
+/// With the text protocol, the message type is recognized from the line: the nickname
+/// prompt and the `Your ID` line are understood, and any other line, such as a refusal,
+/// is returned as a [`ServerMessage::System`] notice.
+///
+/// # Returns
+/// `Ok(None)` if the server closed the connection.
+///
+/// # Errors
+/// Returns [`ChatError::Io`] if reading fails, or [`ChatError::Protocol`] if a JSON
+/// message cannot be parsed or the `Your ID` line carries no ID.
+async fn read_greeting<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    protocol: Protocol,
+) -> Result<Option<ServerMessage>, ChatError> {
+    match protocol {
+        Protocol::Json => read_message(reader).await.map_err(|e| match e.kind() {
+            std::io::ErrorKind::InvalidData => unexpected_handshake(e),
+            _ => e.into(),
+        }),
+        Protocol::Text => {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await? == 0 {
+                return Ok(None);
+            }
+            let line = line.trim_end();
+            for prompt in [
+                ServerMessage::NicknamePrompt,
+                ServerMessage::UsernamePrompt,
+                ServerMessage::PasswordPrompt,
+            ] {
+                if line == prompt.to_string().trim_end() {
+                    return Ok(Some(prompt));
+                }
+            }
+            Ok(Some(match line.strip_prefix("Your ID: ").map(str::parse) {
+                Some(Ok(id)) => ServerMessage::Welcome { id },
+                Some(Err(_)) => return Err(unexpected_handshake(line)),
+                None => ServerMessage::System {
+                    text: line.to_string(),
+                },
+            }))
         }
-    });
+    }
+}
 
-    // Task to handle user input from the terminal
-    let tx_clone = tx.clone();
-    let input_task = tokio::spawn(async move {
-        let stdin = tokio::io::stdin();
-        let mut lines = BufReader::new(stdin).lines();
+/// Builds the error for a greeting the client cannot make sense of.
+fn unexpected_handshake(what: impl std::fmt::Display) -> ChatError {
+    ChatError::Protocol(format!("unexpected handshake from server: {}", what))
+}
 
-        // Read user input line by line and send it to the server
-        while let Ok(Some(line)) = lines.next_line().await {
-            tx_clone.send(line).await.unwrap();
+/// How the client shows the messages it receives.
+#[derive(Debug, Clone, Copy)]
+struct Rendering {
+    /// Whether to keep the time a chat message was sent.
+    timestamps: bool,
+    /// Whether to color each message by its [`MessageType`].
+    color: bool,
+}
+
+impl Rendering {
+    /// Returns the rendering `options` ask for, with colors only if standard output is
+    /// a terminal.
+    fn new(options: &ClientOptions) -> Self {
+        Self {
+            timestamps: options.show_timestamps,
+            color: options.color && std::io::stdout().is_terminal(),
         }
-    });
+    }
 
-    // Main loop to send user messages to the server
-    while let Some(message) = rx.recv().await {
-        writer
-            .write_all(format!("{}\n", message).as_bytes())
-            .await?;
+    /// Returns `text` in the color of `msg_type`, if colors are on.
+    fn paint(&self, text: &str, msg_type: MessageType) -> String {
+        match self.color {
+            true => text.colored(msg_type),
+            false => text.to_string(),
+        }
     }
+}
 
-    // Await the completion of the read and input tasks
-    read_task.await.unwrap();
-    input_task.await.unwrap();
+/// Renders a structured message for display.
+///
+/// # Arguments
+/// * `message` - The message received from the server.
+/// * `rendering` - Whether to keep timestamps and add colors.
+fn render_message(mut message: ServerMessage, rendering: Rendering) -> String {
+    if let ServerMessage::Broadcast { timestamp, .. }
+    | ServerMessage::Action { timestamp, .. }
+    | ServerMessage::Private { timestamp, .. }
+    | ServerMessage::PrivateSent { timestamp, .. } = &mut message
+    {
+        if !rendering.timestamps {
+            *timestamp = None;
+        }
+    }
+    let msg_type = match &message {
+        ServerMessage::Private { .. } | ServerMessage::PrivateSent { .. } => MessageType::Private,
+        ServerMessage::System { .. } => MessageType::System,
+        ServerMessage::Error { .. } | ServerMessage::Rejected { .. } => MessageType::Error,
+        _ => MessageType::Chat,
+    };
+    rendering.paint(&message.to_string(), msg_type)
+}
 
-    Ok(())
-}
\ No newline at end of file
+/// Renders a line received from a server speaking the plain text protocol for display.
+///
+/// The message type can only be guessed from the line's content, so private messages
+/// are recognized by their `[Private]` tag and server notices by theirs.
+///
+/// # Arguments
+/// * `line` - The raw line received from the server.
+/// * `rendering` - Whether to keep timestamps and add colors.
+fn render_text_line(line: &str, rendering: Rendering) -> String {
+    // The tags below follow the timestamp, if the server sent one
+    let (timestamp, line) = split_timestamp(line.trim_end());
+    let stamp = match timestamp {
+        Some(timestamp) if rendering.timestamps => format!("[{}] ", timestamp),
+        _ => String::new(),
+    };
+    // Display the server's announcements as sent
+    let (line, msg_type) = if line.starts_with(ANNOUNCEMENT_PREFIX) {
+        (format!("{}{}", stamp, line), MessageType::System)
+    }
+    // Display private messages, received or sent, with their "[Private]" tag
+    else if line.starts_with("[Private") {
+        (format!("{}{}", stamp, line.trim()), MessageType::Private)
+    } else if line.starts_with("[Error]") || line.starts_with("[Rejected") {
+        (line.to_string(), MessageType::Error)
+    }
+    // Display all other messages as received
+    else {
+        (format!("{}{}", stamp, line), MessageType::Chat)
+    };
+    rendering.paint(&line, msg_type)
+}
+
+/// Returns what `/help` shows: a table of the server's commands and the client's own,
+/// or how to use the command named by `topic`.
+///
+/// # Errors
+/// Returns the line to show if `topic` names no command.
+fn help_lines(topic: Option<&str>) -> Result<Vec<String>, String> {
+    let commands = || COMMANDS.iter().chain(CLIENT_COMMANDS);
+    if let Some(name) = topic {
+        return find_command(COMMANDS, name)
+            .or_else(|| find_command(CLIENT_COMMANDS, name))
+            .map(CommandInfo::explain)
+            .ok_or_else(|| format!("No such command: {} — type /help", name));
+    }
+    let width = commands().map(|info| info.usage.len()).max().unwrap_or(0);
+    Ok(std::iter::once("Available commands:".to_string())
+        .chain(commands().map(|info| format!("  {:width$}  {}", info.usage, info.description)))
+        .chain(std::iter::once(
+            "Type /help <command> to learn more about one.".to_string(),
+        ))
+        .collect())
+}
+
+/// A message the user sent to their room, shown to the user as soon as it is sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Sent {
+    /// A chat message.
+    Chat(String),
+    /// A `/me` action.
+    Action(String),
+}
+
+impl Sent {
+    /// Returns the message a line typed by the user sends to the room, read as the
+    /// server reads it, or `None` if the line is a command.
+    fn from_input(line: &str) -> Option<Self> {
+        match parse_command(line) {
+            Command::Broadcast(body) => Some(Sent::Chat(body)),
+            Command::Action(action) => Some(Sent::Action(action)),
+            _ => None,
+        }
+    }
+
+    /// Returns the line shown for the message when it is sent, stamped with the
+    /// current time if timestamps are shown.
+    fn to_echo(&self, rendering: Rendering) -> String {
+        let stamp = match rendering.timestamps {
+            true => format!(
+                "[{}] ",
+                Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+            ),
+            false => String::new(),
+        };
+        match self {
+            Sent::Chat(body) => format!("{}You: {}", stamp, body),
+            Sent::Action(action) => format!("{}* You {}", stamp, action),
+        }
+    }
+}
+
+/// What to send the server in place of a line, once private messages are sealed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outgoing {
+    /// Send this line.
+    Send(String),
+    /// Send nothing, and show this `[Local]` notice instead.
+    Notice(String),
+    /// Send `line`, which is not encrypted, and show `notice` saying so.
+    Unencrypted { line: String, notice: String },
+    /// Send and show nothing.
+    Nothing,
+}
+
+/// A public key asked of the server, and what to do with it.
+#[derive(Debug, Clone)]
+struct KeyRequest {
+    /// The client whose key it is, as the user named it.
+    target: String,
+    /// The message to seal with the key, or `None` if the key is this client's own,
+    /// being registered.
+    body: Option<String>,
+}
+
+/// Seals the private messages the user sends and opens the ones received (see
+/// [`crate::e2e`]).
+///
+/// A `/msg` needs its recipient's public key, so it is held back while the key is
+/// asked for with `/getkey`, and sent once the server's answer arrives. Clones share
+/// the same state, so the task displaying messages sees what the user sends.
+#[derive(Debug, Clone)]
+struct Encryption {
+    /// This client's key pair.
+    keys: Arc<ClientKeys>,
+    /// The keys asked for, oldest first.
+    waiting: Arc<Mutex<VecDeque<KeyRequest>>>,
+    /// The public key of the client that sent the last private message, if it was
+    /// sealed, which `/reply` seals for.
+    last_sender: Arc<Mutex<Option<PublicKey>>>,
+}
+
+impl Encryption {
+    /// Starts sealing messages with `keys`.
+    fn new(keys: Arc<ClientKeys>) -> Self {
+        Self {
+            keys,
+            waiting: Arc::default(),
+            last_sender: Arc::default(),
+        }
+    }
+
+    /// Returns the line that registers this client's public key, and waits for the
+    /// server's answer to it so that it is not shown.
+    fn register(&self, my_id: usize) -> String {
+        self.waiting.lock().unwrap().push_back(KeyRequest {
+            target: my_id.to_string(),
+            body: None,
+        });
+        format!("/key {}", self.keys.public_hex())
+    }
+
+    /// Returns what to send in place of a line typed by the user: a `/msg` waits for
+    /// the keys of its recipients, a `/reply` is sealed for the last sender, and anything
+    /// else is sent as it is.
+    fn outgoing(&self, line: &str) -> Outgoing {
+        match parse_command(line) {
+            Command::Private { targets, body } => {
+                // Every recipient gets its own envelope, sealed once its key arrives
+                let targets: Vec<String> = targets.iter().map(ToString::to_string).collect();
+                let mut waiting = self.waiting.lock().unwrap();
+                for target in &targets {
+                    waiting.push_back(KeyRequest {
+                        target: target.clone(),
+                        body: Some(body.clone()),
+                    });
+                }
+                Outgoing::Send(format!("/getkey {}", targets.join(",")))
+            }
+            Command::Reply(body) => match *self.last_sender.lock().unwrap() {
+                Some(key) => Outgoing::Send(format!("/reply {}", self.keys.encrypt(&key, &body))),
+                // Its sender has no key to seal the answer with
+                None => Outgoing::Unencrypted {
+                    line: format!("/reply {}", body),
+                    notice: format!(
+                        "{} (unencrypted) The last private message you received was not encrypted, so neither is your reply",
+                        LOCAL_PREFIX
+                    ),
+                },
+            },
+            _ => Outgoing::Send(line.to_string()),
+        }
+    }
+
+    /// Seals the message waiting for the key in `reply`, a
+    /// [`ServerMessage::PublicKey`].
+    ///
+    /// # Returns
+    /// The sealed `/msg` to send, addressed as `#<id>` to the client the key belongs
+    /// to. A recipient without a key, such as someone chatting through `nc`, is sent
+    /// the message unencrypted, with a notice saying so. A notice instead of the
+    /// message if the recipient does not exist. `None` if no message was waiting, as when
+    /// the user asked for the key with `/getkey`.
+    fn key_arrived(&self, reply: &ServerMessage) -> Option<Outgoing> {
+        let ServerMessage::PublicKey { target, id, key } = reply else {
+            return None;
+        };
+        let body = {
+            let mut waiting = self.waiting.lock().unwrap();
+            let index = waiting
+                .iter()
+                .position(|request| request.target == *target)?;
+            waiting.remove(index)?.body
+        };
+        let Some(body) = body else {
+            return Some(Outgoing::Nothing);
+        };
+        Some(match (id, key.as_deref().and_then(parse_public_key)) {
+            // The client whose key sealed the message, whatever its name is by now
+            (Some(id), Some(key)) => {
+                Outgoing::Send(format!("/msg #{} {}", id, self.keys.encrypt(&key, &body)))
+            }
+            (Some(id), None) => Outgoing::Unencrypted {
+                line: format!("/msg #{} {}", id, body),
+                notice: format!(
+                    "{} (unencrypted) {} has no key, so your message to it is sent in the clear",
+                    LOCAL_PREFIX, target
+                ),
+            },
+            (None, _) => Outgoing::Notice(format!("{} No such user: {}", LOCAL_PREFIX, target)),
+        })
+    }
+
+    /// Returns `body` opened if it is an envelope, or a note that it could not be.
+    ///
+    /// # Arguments
+    /// * `body` - The body of a private message.
+    /// * `received` - Whether the message was sent to this client, rather than being
+    ///   the copy of one it sent, so that `/reply` answers its sender.
+    fn open(&self, body: &str, received: bool) -> String {
+        let opened = is_envelope(body).then(|| self.keys.decrypt(body));
+        if received {
+            *self.last_sender.lock().unwrap() = match &opened {
+                Some(Ok(opened)) => Some(opened.peer),
+                _ => None,
+            };
+        }
+        match opened {
+            None => body.to_string(),
+            Some(Ok(opened)) => opened.text,
+            Some(Err(e)) => format!("[Could not decrypt: {}]", e),
+        }
+    }
+
+    /// Returns `message` with its body opened if it is a private message.
+    fn open_message(&self, mut message: ServerMessage) -> ServerMessage {
+        match &mut message {
+            ServerMessage::Private { body, .. } => *body = self.open(body, true),
+            ServerMessage::PrivateSent { body, .. } => *body = self.open(body, false),
+            _ => {}
+        }
+        message
+    }
+
+    /// Returns a line of the text protocol with its body opened if it is a private
+    /// message.
+    fn open_text_line(&self, line: &str) -> String {
+        let line = line.trim_end();
+        let (_, rest) = split_timestamp(line);
+        let received = rest.starts_with("[Private] ");
+        if !received && !rest.starts_with("[Private →") {
+            return line.to_string();
+        }
+        // The body comes last, and an envelope holds no spaces
+        match line.rsplit_once(' ') {
+            Some((head, body)) => format!("{} {}", head, self.open(body, received)),
+            None => line.to_string(),
+        }
+    }
+}
+
+/// Tests for the client module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    /// Shows messages with their timestamps and without colors.
+    const PLAIN: Rendering = Rendering {
+        timestamps: true,
+        color: false,
+    };
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        let options = ClientOptions::default();
+        let waits: Vec<u64> = (0..7).map(|n| options.backoff(n).as_secs()).collect();
+        assert_eq!(waits, [1, 2, 4, 8, 16, 30, 30]);
+
+        // Huge failure counts saturate instead of overflowing
+        assert_eq!(options.backoff(u32::MAX), options.max_backoff);
+    }
+
+    #[test]
+    fn test_own_messages_are_echoed_locally() {
+        let quiet = Rendering {
+            timestamps: false,
+            color: false,
+        };
+        let sent = Sent::from_input("Client 3: hi").unwrap();
+        assert_eq!(sent.to_echo(quiet), "You: Client 3: hi");
+        let action = Sent::from_input("/me waves").unwrap();
+        assert_eq!(action.to_echo(quiet), "* You waves");
+        assert!(split_timestamp(&action.to_echo(PLAIN)).0.is_some());
+        assert_eq!(
+            Sent::from_input("//shrug"),
+            Some(Sent::Chat("/shrug".to_string()))
+        );
+        assert_eq!(Sent::from_input("/msg 2 hi"), None);
+        assert_eq!(Sent::from_input("/ignore bob"), None);
+    }
+
+    #[test]
+    fn test_render_broadcast() {
+        let message = ServerMessage::Broadcast {
+            from: 2,
+            nick: None,
+            body: "hi".to_string(),
+            timestamp: Some("2024-01-15T14:32:10Z".to_string()),
+        };
+        assert_eq!(
+            render_message(message.clone(), PLAIN),
+            "[2024-01-15T14:32:10Z] Client 2: hi"
+        );
+        let quiet = Rendering {
+            timestamps: false,
+            color: false,
+        };
+        assert_eq!(render_message(message, quiet), "Client 2: hi");
+    }
+
+    #[test]
+    fn test_text_lines_are_colored_by_type() {
+        let colored = Rendering {
+            timestamps: false,
+            color: true,
+        };
+        let render = |line| render_text_line(line, colored);
+        assert_eq!(render("alice: hi\n"), "alice: hi");
+        assert_eq!(render("[2024-01-15T14:32:10Z] bob: hi\n"), "bob: hi");
+        assert_eq!(
+            render("[Private] alice: psst\n"),
+            "[Private] alice: psst".colored(MessageType::Private)
+        );
+        assert_eq!(
+            render("[Private → alice] psst back\n"),
+            "[Private → alice] psst back".colored(MessageType::Private)
+        );
+        assert_eq!(
+            render("alice: [Private] not really\n"),
+            "alice: [Private] not really"
+        );
+        assert_eq!(
+            render("[Server] Restarting soon\n"),
+            "[Server] Restarting soon".colored(MessageType::System)
+        );
+        assert_eq!(
+            render("[Error] No such user: carol\n"),
+            "[Error] No such user: carol".colored(MessageType::Error)
+        );
+
+        // Without colors, lines keep their timestamp and tags
+        assert_eq!(
+            render_text_line("[2024-01-15T14:32:10Z] bob: hi\n", PLAIN),
+            "[2024-01-15T14:32:10Z] bob: hi"
+        );
+    }
+
+    #[test]
+    fn test_tls_scheme_is_split_off() {
+        let options = ClientOptions::default();
+        let (address, tls) = parse_address("127.0.0.1:8080", &options).unwrap();
+        assert_eq!(address, "127.0.0.1:8080");
+        assert!(tls.is_none());
+
+        #[cfg(feature = "tls")]
+        {
+            let (address, tls) = parse_address("tls://[::1]:8443", &options).unwrap();
+            assert_eq!(address, "[::1]:8443");
+            assert_eq!(
+                tls.unwrap().server_name.to_str(),
+                ServerName::try_from("::1").unwrap().to_str()
+            );
+            let error = parse_address("tls://bad name:8443", &options)
+                .err()
+                .unwrap();
+            assert!(matches!(error, ChatError::Config(_)));
+        }
+        #[cfg(not(feature = "tls"))]
+        assert!(parse_address("tls://localhost:8443", &options).is_err());
+    }
+
+    #[test]
+    fn test_help_lists_server_and_client_commands() {
+        let lines = help_lines(None).unwrap();
+        assert_eq!(lines.len(), COMMANDS.len() + CLIENT_COMMANDS.len() + 2);
+        // The descriptions line up in a column
+        let column = lines[1].find("Show this list").unwrap();
+        for info in COMMANDS.iter().chain(CLIENT_COMMANDS) {
+            let line = lines.iter().find(|line| line.contains(info.usage)).unwrap();
+            assert_eq!(line.find(info.description), Some(column), "{}", line);
+        }
+
+        let ignore = help_lines(Some("ignore")).unwrap();
+        assert_eq!(ignore[0], "Usage: /ignore <nickname>");
+        assert_eq!(help_lines(Some("/msg")).unwrap().len(), 3);
+        assert_eq!(
+            help_lines(Some("dance")).unwrap_err(),
+            "No such command: dance — type /help"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_local_commands_are_not_sent() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        server.write_all(b"Your ID: 1\n").await.unwrap();
+        let (reader, writer) = tokio::io::split(client);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(5);
+        for line in ["/help", "/help msg", "/ignore bob", "/ignorelist", "hi"] {
+            tx.send(line.to_string()).await.unwrap();
+        }
+        drop(tx);
+
+        let mut kept = Kept::new();
+        let session = chat(reader, writer, &mut rx, false, &mut kept, PLAIN)
+            .await
+            .unwrap();
+        assert!(matches!(session, Session::Quit));
+
+        // Only the public key, the request to leave out echoes and the chat message
+        // reached the server
+        let mut sent = String::new();
+        server.read_to_string(&mut sent).await.unwrap();
+        assert_eq!(
+            sent,
+            format!("/key {}\n/echo off\nhi\n", kept.keys.public_hex())
+        );
+    }
+
+    #[test]
+    fn test_private_messages_are_sealed_end_to_end() {
+        let alice = Encryption::new(Arc::new(ClientKeys::generate()));
+        let bob = Encryption::new(Arc::new(ClientKeys::generate()));
+        assert_eq!(bob.register(2), format!("/key {}", bob.keys.public_hex()));
+        let bobs_key = ServerMessage::PublicKey {
+            target: "2".to_string(),
+            id: Some(2),
+            key: Some(bob.keys.public_hex()),
+        };
+        assert_eq!(bob.key_arrived(&bobs_key), Some(Outgoing::Nothing));
+
+        // The message waits for bob's key, and only its envelope goes to the server
+        assert_eq!(
+            alice.outgoing("/msg bob meet at noon"),
+            Outgoing::Send("/getkey bob".to_string())
+        );
+        let reply = ServerMessage::PublicKey {
+            target: "bob".to_string(),
+            id: Some(2),
+            key: Some(bob.keys.public_hex()),
+        };
+        let Some(Outgoing::Send(sealed)) = alice.key_arrived(&reply) else {
+            panic!("the message was not sealed");
+        };
+        let envelope = sealed.strip_prefix("/msg #2 ").unwrap();
+        assert!(!envelope.contains("meet at noon"));
+        assert_eq!(alice.key_arrived(&reply), None);
+
+        // Both ends read it, and bob's reply is sealed for alice without asking
+        assert_eq!(
+            bob.open_text_line(&format!("[Private] alice: {}\n", envelope)),
+            "[Private] alice: meet at noon"
+        );
+        assert_eq!(
+            alice.open_text_line(&format!("[Private → bob] {}", envelope)),
+            "[Private → bob] meet at noon"
+        );
+        let Outgoing::Send(answer) = bob.outgoing("/r see you") else {
+            panic!("the reply was not sent");
+        };
+        let message = ServerMessage::Private {
+            from: 2,
+            nick: Some("bob".to_string()),
+            body: answer.strip_prefix("/reply ").unwrap().to_string(),
+            timestamp: None,
+        };
+        assert_eq!(
+            alice.open_message(message).to_string(),
+            "[Private] bob: see you"
+        );
+
+        // Plain private messages are answered in the clear, and clients without a key
+        // are sent them, both marked as unencrypted
+        bob.open_text_line("[Private] carol: hi");
+        let Outgoing::Unencrypted { line, notice } = bob.outgoing("/r hi") else {
+            panic!("the reply was not sent unencrypted");
+        };
+        assert_eq!(line, "/reply hi");
+        assert!(notice.contains("(unencrypted)"));
+        alice.outgoing("/msg carol hi");
+        let no_key = ServerMessage::PublicKey {
+            target: "carol".to_string(),
+            id: Some(3),
+            key: None,
+        };
+        let Some(Outgoing::Unencrypted { line, notice }) = alice.key_arrived(&no_key) else {
+            panic!("the message was not sent unencrypted");
+        };
+        assert_eq!(line, "/msg #3 hi");
+        assert!(notice.contains("(unencrypted) carol"));
+        alice.outgoing("/msg dave hi");
+        let missing = ServerMessage::PublicKey {
+            target: "dave".to_string(),
+            id: None,
+            key: None,
+        };
+        assert!(matches!(
+            alice.key_arrived(&missing),
+            Some(Outgoing::Notice(_))
+        ));
+
+        // Each of several recipients gets its own envelope
+        assert_eq!(
+            alice.outgoing("/msg bob, 3 hi both"),
+            Outgoing::Send("/getkey bob,3".to_string())
+        );
+        let Some(Outgoing::Send(to_bob)) = alice.key_arrived(&reply) else {
+            panic!("the message to bob was not sealed");
+        };
+        let carols_key = ServerMessage::PublicKey {
+            target: "3".to_string(),
+            id: Some(3),
+            key: Some(ClientKeys::generate().public_hex()),
+        };
+        let Some(Outgoing::Send(to_carol)) = alice.key_arrived(&carols_key) else {
+            panic!("the message to client 3 was not sealed");
+        };
+        assert!(to_bob.starts_with("/msg #2 e2e1:"));
+        assert!(to_carol.starts_with("/msg #3 e2e1:"));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_id_line_is_an_error() {
+        for greeting in [&b"Your ID: abc\n"[..], &[0, 0, 0, 3, b'{', b'}', b'!']] {
+            let (client, mut server) = tokio::io::duplex(1024);
+            server.write_all(greeting).await.unwrap();
+            let (reader, writer) = tokio::io::split(client);
+            let (_tx, mut rx) = tokio::sync::mpsc::channel(1);
+
+            let error = chat(reader, writer, &mut rx, false, &mut Kept::new(), PLAIN)
+                .await
+                .err()
+                .unwrap();
+            let ChatError::Protocol(what) = error else {
+                panic!("expected a protocol error, got {:?}", error);
+            };
+            assert!(what.starts_with("unexpected handshake from server: "));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refused_login_is_an_auth_error() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let greeting = format!(
+            "[Error] Invalid password\n{}\n",
+            AUTHENTICATION_FAILED_NOTICE
+        );
+        server.write_all(greeting.as_bytes()).await.unwrap();
+        drop(server);
+        let (reader, writer) = tokio::io::split(client);
+        let (_tx, mut rx) = tokio::sync::mpsc::channel(1);
+
+        let error = chat(reader, writer, &mut rx, false, &mut Kept::new(), PLAIN)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(error, ChatError::Auth(_)));
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn test_session_over_tls() {
+        use crate::events::ServerEvent;
+        use crate::server::ChatServer;
+        use std::sync::Arc;
+
+        // Serve with a self-signed certificate, which the client is told to trust
+        let dir = std::env::temp_dir().join(format!("chat-client-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(dir.join("cert.pem"), cert.cert.pem()).unwrap();
+        std::fs::write(dir.join("key.pem"), cert.key_pair.serialize_pem()).unwrap();
+        let server_tls =
+            crate::tls::load_config(dir.join("cert.pem"), dir.join("key.pem")).unwrap();
+        let options = ClientOptions {
+            tls_ca: Some(dir.join("cert.pem")),
+            ..ClientOptions::default()
+        };
+        let server = Arc::new(
+            ChatServer::builder()
+                .address("127.0.0.1:0")
+                .tls(server_tls)
+                .build()
+                .await
+                .unwrap(),
+        );
+        let mut events = server.subscribe();
+        let running = tokio::spawn({
+            let server = server.clone();
+            async move { server.run().await }
+        });
+
+        let address = format!("tls://localhost:{}", server.local_addr().unwrap().port());
+        let (address, tls) = parse_address(&address, &options).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        tx.send("hello over tls".to_string()).await.unwrap();
+        drop(tx);
+        let session = run_session(
+            address,
+            tls.as_ref(),
+            &options,
+            &mut rx,
+            false,
+            &mut Kept::new(),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(session, Session::Quit));
+
+        // The message made it through the handshake to the server
+        while let Some(event) = events.recv().await {
+            if let ServerEvent::MessageBroadcast { from, text } = event {
+                assert_eq!((from, text.as_str()), (1, "hello over tls"));
+                break;
+            }
+        }
+
+        server.shutdown();
+        running.await.unwrap().unwrap();
+    }
+
+    #[cfg(feature = "websocket")]
+    #[tokio::test]
+    async fn test_session_over_websocket() {
+        use crate::events::ServerEvent;
+        use crate::server::{ChatServer, Transport};
+        use std::sync::Arc;
+
+        let server = Arc::new(
+            ChatServer::builder()
+                .address("127.0.0.1:0")
+                .transport(Transport::WebSocket)
+                .build()
+                .await
+                .unwrap(),
+        );
+        let mut events = server.subscribe();
+        let running = tokio::spawn({
+            let server = server.clone();
+            async move { server.run().await }
+        });
+
+        let url = format!("{}{}", WEBSOCKET_SCHEME, server.local_addr().unwrap());
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        tx.send("hello over websocket".to_string()).await.unwrap();
+        drop(tx);
+        let session = run_ws_session(&url, &mut rx).await.unwrap();
+        assert!(matches!(session, Session::Quit));
+
+        while let Some(event) = events.recv().await {
+            if let ServerEvent::MessageBroadcast { from, text } = event {
+                assert_eq!((from, text.as_str()), (1, "hello over websocket"));
+                break;
+            }
+        }
+
+        server.shutdown();
+        running.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_with_the_same_nickname() {
+        use crate::events::ServerEvent;
+        use crate::server::ChatServer;
+        use std::sync::Arc;
+
+        async fn start(address: &str) -> (Arc<ChatServer>, tokio::task::JoinHandle<()>) {
+            let server = Arc::new(
+                ChatServer::builder()
+                    .address(address)
+                    .require_nickname()
+                    .build()
+                    .await
+                    .unwrap(),
+            );
+            let running = tokio::spawn({
+                let server = server.clone();
+                async move { server.run().await.unwrap() }
+            });
+            (server, running)
+        }
+
+        let (server, running) = start("127.0.0.1:0").await;
+        let address = server.local_addr().unwrap().to_string();
+        let mut events = server.subscribe();
+        let options = ClientOptions {
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_millis(200),
+            max_retries: Some(50),
+            ..ClientOptions::default()
+        };
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+        let client = tokio::spawn({
+            let address = address.clone();
+            async move { stay_connected(&address, None, &options, &mut rx).await }
+        });
+        async fn said(events: &mut tokio::sync::mpsc::Receiver<ServerEvent>, expected: &str) {
+            while let Some(event) = events.recv().await {
+                if let ServerEvent::MessageBroadcast { text, .. } = event {
+                    assert_eq!(text, expected);
+                    return;
+                }
+            }
+        }
+        tx.send("alice".to_string()).await.unwrap();
+        tx.send("hello".to_string()).await.unwrap();
+        said(&mut events, "hello").await;
+
+        // The server restarts on the same port while the client backs off
+        server.shutdown();
+        running.await.unwrap();
+        drop(server);
+        let (server, running) = start(&address).await;
+        let mut events = server.subscribe();
+        while let Some(event) = events.recv().await {
+            if let ServerEvent::ClientConnected { .. } = event {
+                break;
+            }
+        }
+
+        // The client answers the prompt itself, so the next line is chat rather
+        // than a nickname
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        tx.send("hello again".to_string()).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), said(&mut events, "hello again"))
+            .await
+            .unwrap();
+        drop(tx);
+        client.await.unwrap().unwrap();
+        server.shutdown();
+        running.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_plain_clients_still_receive_private_messages() {
+        use crate::server::ChatServer;
+        use std::sync::Arc;
+
+        let server = Arc::new(
+            ChatServer::builder()
+                .address("127.0.0.1:0")
+                .timestamps(false)
+                .build()
+                .await
+                .unwrap(),
+        );
+        let running = tokio::spawn({
+            let server = server.clone();
+            async move { server.run().await.unwrap() }
+        });
+        let address = server.local_addr().unwrap().to_string();
+
+        // Client 1 chats through a bare socket, as with nc, and never registers a key
+        let mut plain = BufReader::new(TcpStream::connect(&address).await.unwrap());
+        let mut line = String::new();
+        plain.read_line(&mut line).await.unwrap();
+        assert_eq!(line.trim_end(), "Your ID: 1");
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        let client = tokio::spawn(async move {
+            run_session(
+                &address,
+                None,
+                &ClientOptions::default(),
+                &mut rx,
+                false,
+                &mut Kept::new(),
+            )
+            .await
+        });
+        line.clear();
+        plain.read_line(&mut line).await.unwrap();
+        assert_eq!(line.trim_end(), "Client 2 joined");
+        tx.send("/msg 1 hello nc".to_string()).await.unwrap();
+        line.clear();
+        plain.read_line(&mut line).await.unwrap();
+        assert_eq!(line.trim_end(), "[Private] Client 2: hello nc");
+
+        drop(tx);
+        assert!(matches!(client.await.unwrap().unwrap(), Session::Quit));
+        server.shutdown();
+        running.await.unwrap();
+    }
+}
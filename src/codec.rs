@@ -0,0 +1,191 @@
+//! The codec module splits the chat's byte stream into whole messages and back.
+//!
+//! ## Overview
+//! Over TCP, the server and client write the [`Protocol`]'s bytes straight to the
+//! socket. Message-based transports such as WebSocket instead carry one message per
+//! transport message, so a [`Codec`] sits between the two: it cuts the bytes one side
+//! writes into messages, and turns each message received into the bytes the other side
+//! reads. Both sides keep speaking their usual protocol, whatever the transport.
+//!
+//! ## Key Features
+//! - **Both Protocols**: A text line loses its newline, and a JSON frame loses its
+//!   length header, so each transport message is a readable line or a JSON envelope.
+//! - **Both Directions**: The same [`Codec`] serves the server (whose output is split
+//!   and whose input is encoded) and the client (the other way around).
+//! - **Detection**: [`detect_protocol`] recognizes the protocol from the first message
+//!   a server sends.
+
+use crate::framing::{HEADER_LEN, MAX_FRAME_LEN};
+use crate::protocol::Protocol;
+use std::io;
+
+/// Converts between a [`Protocol`] byte stream and the messages it carries.
+#[derive(Debug, Default)]
+pub struct Codec {
+    protocol: Protocol,
+    /// Bytes pushed that do not make up a whole message yet.
+    pending: Vec<u8>,
+}
+
+impl Codec {
+    /// Creates a codec for a stream written in `protocol`.
+    pub fn new(protocol: Protocol) -> Self {
+        Self {
+            protocol,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Returns the protocol the codec converts.
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Encodes one received message into the bytes of the stream.
+    ///
+    /// # Errors
+    /// Returns an error if the message is too large to fit in a frame.
+    pub fn encode(&self, message: &str) -> io::Result<Vec<u8>> {
+        self.protocol.encode_input(message)
+    }
+
+    /// Adds bytes read from the stream, to be taken out by [`Codec::next_message`].
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+    }
+
+    /// Takes the next whole message out of the bytes pushed so far.
+    ///
+    /// Invalid UTF-8 is replaced rather than refused, as the bytes come from the chat's
+    /// own writer.
+    ///
+    /// # Returns
+    /// `None` until a whole line or frame has been pushed.
+    ///
+    /// # Errors
+    /// Returns an [`io::ErrorKind::InvalidData`] error if a frame is larger than
+    /// [`MAX_FRAME_LEN`], after which the stream cannot be followed.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_app::codec::Codec;
+    /// use chat_app::protocol::Protocol;
+    ///
+    /// let mut codec = Codec::new(Protocol::Text);
+    /// codec.push(b"Your ID: 1\nClient 2: h");
+    /// assert_eq!(codec.next_message().unwrap().as_deref(), Some("Your ID: 1"));
+    /// assert_eq!(codec.next_message().unwrap(), None);
+    /// codec.push(b"i\n");
+    /// assert_eq!(codec.next_message().unwrap().as_deref(), Some("Client 2: hi"));
+    /// ```
+    pub fn next_message(&mut self) -> io::Result<Option<String>> {
+        let (start, end) = match self.protocol {
+            Protocol::Text => match self.pending.iter().position(|&byte| byte == b'\n') {
+                Some(newline) => (0, newline),
+                None => return Ok(None),
+            },
+            Protocol::Json => {
+                let Some(header) = self.pending.get(..HEADER_LEN) else {
+                    return Ok(None);
+                };
+                let len = u32::from_be_bytes(header.try_into().expect("header is 4 bytes"));
+                let len = len as usize;
+                if len > MAX_FRAME_LEN {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("frame of {} bytes exceeds the limit", len),
+                    ));
+                }
+                if self.pending.len() < HEADER_LEN + len {
+                    return Ok(None);
+                }
+                (HEADER_LEN, HEADER_LEN + len)
+            }
+        };
+
+        let message = String::from_utf8_lossy(&self.pending[start..end]).into_owned();
+        // A text line's newline is consumed along with it
+        let consumed = match self.protocol {
+            Protocol::Text => end + 1,
+            Protocol::Json => end,
+        };
+        self.pending.drain(..consumed);
+        Ok(Some(message))
+    }
+}
+
+/// Recognizes a server's protocol from the first message it sends.
+///
+/// A JSON envelope starts with `{`, which no line a server greets with does.
+///
+/// # Example
+/// ```
+/// use chat_app::codec::detect_protocol;
+/// use chat_app::protocol::{Protocol, ServerMessage};
+///
+/// let welcome = ServerMessage::Welcome { id: 1 };
+/// assert_eq!(detect_protocol(&welcome.to_json()), Protocol::Json);
+/// assert_eq!(detect_protocol(&welcome.to_string()), Protocol::Text);
+/// ```
+pub fn detect_protocol(first_message: &str) -> Protocol {
+    if first_message.starts_with('{') {
+        Protocol::Json
+    } else {
+        Protocol::Text
+    }
+}
+
+/// Tests for the codec module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framing::encode_frame;
+    use crate::protocol::ServerMessage;
+
+    #[test]
+    fn test_messages_survive_both_protocols() {
+        let messages = [
+            ServerMessage::Welcome { id: 1 },
+            ServerMessage::System {
+                text: "Be nice.".to_string(),
+            },
+        ];
+        for protocol in [Protocol::Text, Protocol::Json] {
+            let mut codec = Codec::new(protocol);
+            for message in &messages {
+                codec.push(&protocol.encode(message));
+            }
+            let mut received = Vec::new();
+            while let Some(message) = codec.next_message().unwrap() {
+                received.push(message);
+            }
+            let expected: Vec<String> = match protocol {
+                Protocol::Text => messages.iter().map(ToString::to_string).collect(),
+                Protocol::Json => messages.iter().map(ServerMessage::to_json).collect(),
+            };
+            assert_eq!(received, expected);
+            assert_eq!(detect_protocol(&received[0]), protocol);
+
+            // Encoding a message gives back the bytes it was split from
+            assert_eq!(
+                codec.encode(&received[1]).unwrap(),
+                protocol.encode(&messages[1])
+            );
+        }
+    }
+
+    #[test]
+    fn test_frames_arrive_in_pieces() {
+        let frame = encode_frame("two\nlines".as_bytes()).unwrap();
+        let mut codec = Codec::new(Protocol::Json);
+        for byte in &frame[..frame.len() - 1] {
+            codec.push(&[*byte]);
+            assert_eq!(codec.next_message().unwrap(), None);
+        }
+        codec.push(&frame[frame.len() - 1..]);
+        assert_eq!(codec.next_message().unwrap().as_deref(), Some("two\nlines"));
+
+        codec.push(&(MAX_FRAME_LEN as u32 + 1).to_be_bytes());
+        assert!(codec.next_message().is_err());
+    }
+}
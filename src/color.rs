@@ -0,0 +1,91 @@
+//! The color module picks the terminal colors the client shows messages in.
+//!
+//! ## Overview
+//! The client sorts every line it displays into a [`MessageType`] and, when standard
+//! output is a terminal, wraps it in that type's ANSI color with [`Colorize::colored`],
+//! so private messages, server notices, errors and the user's own messages stand out
+//! from the chat.
+//!
+//! ## Key Features
+//! - **Testable**: Coloring is a plain string transformation, so it can be checked
+//!   without a terminal.
+//! - **Unobtrusive**: Chat from others keeps the terminal's default color.
+
+/// The kinds of lines the client tells apart by color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    /// A message someone else sent to the room, in the default color.
+    Chat,
+    /// A message sent to this client alone with `/msg`, in yellow.
+    Private,
+    /// A notice from the server itself, such as an announcement, in cyan.
+    System,
+    /// A message this client sent, shown as `You: ...`, in green.
+    Own,
+    /// An error or a rejected message, in red.
+    Error,
+}
+
+impl MessageType {
+    /// Returns the ANSI escape that starts this type's color, or `None` for the
+    /// terminal's default.
+    pub fn ansi_code(self) -> Option<&'static str> {
+        match self {
+            MessageType::Chat => None,
+            MessageType::Private => Some("\x1b[33m"),
+            MessageType::System => Some("\x1b[36m"),
+            MessageType::Own => Some("\x1b[32m"),
+            MessageType::Error => Some("\x1b[31m"),
+        }
+    }
+}
+
+/// The ANSI escape that restores the terminal's default color.
+pub const RESET: &str = "\x1b[0m";
+
+/// Text that can be shown in the color of a [`MessageType`].
+///
+/// # Example
+/// ```
+/// use chat_app::color::{Colorize, MessageType};
+///
+/// assert_eq!("bob: hi".colored(MessageType::Chat), "bob: hi");
+/// assert_eq!(
+///     "[Private] bob: hi".colored(MessageType::Private),
+///     "\x1b[33m[Private] bob: hi\x1b[0m"
+/// );
+/// ```
+pub trait Colorize {
+    /// Returns the text wrapped in the color of `msg_type`, reset at the end.
+    fn colored(&self, msg_type: MessageType) -> String;
+}
+
+impl Colorize for str {
+    fn colored(&self, msg_type: MessageType) -> String {
+        match msg_type.ansi_code() {
+            Some(code) => format!("{}{}{}", code, self, RESET),
+            None => self.to_string(),
+        }
+    }
+}
+
+/// Tests for the color module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_color_is_reset() {
+        for msg_type in [
+            MessageType::Private,
+            MessageType::System,
+            MessageType::Own,
+            MessageType::Error,
+        ] {
+            let line = "hi".colored(msg_type);
+            assert!(line.starts_with(msg_type.ansi_code().unwrap()));
+            assert!(line.ends_with(RESET));
+        }
+        assert_eq!("hi".colored(MessageType::Chat), "hi");
+    }
+}
@@ -0,0 +1,723 @@
+//! The command module turns a line typed by a client into a [`Command`].
+//!
+//! ## Overview
+//! Lines starting with `/` are commands; everything else is a chat message for all
+//! clients. [`parse_command`] recognizes every command the server understands and
+//! reports malformed ones, so the server's connection loop only has to dispatch on
+//! the resulting [`Command`].
+//!
+//! ## Key Features
+//! - **Single Source of Truth**: [`COMMANDS`] lists each command's syntax and purpose,
+//!   and is used both for `/help` and for usage errors.
+//! - **Malformed Input**: Commands with missing or invalid arguments are reported as
+//!   [`Command::Invalid`] rather than being sent to the chat.
+
+use crate::e2e::parse_public_key;
+use crate::nicknames::is_valid_nickname;
+use crate::rooms::is_valid_room_name;
+
+/// A line sent by a client, as understood by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// A chat message for every client in the sender's room.
+    Broadcast(String),
+    /// `/me <action>`: an action for every client in the sender's room.
+    Action(String),
+    /// `/msg <client_id|nickname>[,...] <message>`: a message for one or more clients,
+    /// each named once.
+    Private {
+        targets: Vec<Recipient>,
+        body: String,
+    },
+    /// `/key <public_key>`: the public key, in hex, that private messages to the
+    /// sender are to be encrypted with (see [`crate::e2e`]).
+    Key(String),
+    /// `/getkey <client_id|nickname>[,...]`: a request for the public keys of one or
+    /// more clients.
+    GetKey(Vec<Recipient>),
+    /// `/reply <message>` or `/r <message>`: a message for the client that last sent
+    /// the sender a private message.
+    Reply(String),
+    /// `/echo on|off`: whether the sender's own chat messages and actions are sent back
+    /// to it.
+    Echo(bool),
+    /// `/nick <name>` or `/rename <name>`: a request to change the client's nickname.
+    Nick(String),
+    /// `/list` or `/who`: a request for every connected client, grouped by room.
+    List,
+    /// `/whoami`: a request for the sender's own ID, nickname, address and time connected.
+    WhoAmI,
+    /// `/help [command]`: a request for the list of commands, or for how to use one.
+    Help(Option<String>),
+    /// `/motd`: a request for the message of the day.
+    Motd,
+    /// `/auth <password>`: an attempt to become an admin.
+    Auth(String),
+    /// `/kick <client_id>`: an admin's request to disconnect a client.
+    Kick(usize),
+    /// `/stats`: an admin's request for the server's counters.
+    Stats,
+    /// `/filter add <word>`: an admin's request to mask a word in broadcasts.
+    FilterAdd(String),
+    /// `/filter remove <word>`: an admin's request to stop masking a word.
+    FilterRemove(String),
+    /// `/history <n>`: a request for the last `n` chat messages.
+    History(usize),
+    /// `/join <room>`: a request to move to another room.
+    Join(String),
+    /// `/leave [room]`: a request to return to the default room, optionally naming
+    /// the room being left.
+    Leave(Option<String>),
+    /// `/rooms`: a request for the open rooms and their member counts.
+    Rooms,
+    /// A known command with missing or malformed arguments, named by its command word.
+    Invalid(&'static str),
+    /// A line starting with `/` that is not a known command, named by its first word.
+    Unknown(String),
+}
+
+/// The client a private message is addressed to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recipient {
+    /// A client named by its ID, or by a nickname made of the same digits, which the
+    /// server looks for first.
+    Id(usize),
+    /// A client named by `#<id>`, which is always its ID and never a nickname.
+    ClientId(usize),
+    /// A client named by its nickname.
+    Nick(String),
+}
+
+impl std::fmt::Display for Recipient {
+    /// Renders the recipient as it is typed after `/msg`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Recipient::Id(id) => write!(f, "{}", id),
+            Recipient::ClientId(id) => write!(f, "#{}", id),
+            Recipient::Nick(nick) => write!(f, "{}", nick),
+        }
+    }
+}
+
+/// A command understood by the server, as listed by `/help`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandInfo {
+    /// The command word, including the leading slash.
+    pub name: &'static str,
+    /// How to invoke the command.
+    pub usage: &'static str,
+    /// What the command does, in a few words.
+    pub description: &'static str,
+    /// What else to know about the command, shown by `/help <command>`.
+    pub details: &'static str,
+}
+
+impl CommandInfo {
+    /// Returns the lines `/help <command>` shows for this command.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_app::command::{find_command, COMMANDS};
+    ///
+    /// let lines = find_command(COMMANDS, "stats").unwrap().explain();
+    /// assert_eq!(lines[0], "Usage: /stats");
+    /// ```
+    pub fn explain(&self) -> Vec<String> {
+        vec![
+            format!("Usage: {}", self.usage),
+            format!("  {}", self.description),
+            format!("  {}", self.details),
+        ]
+    }
+}
+
+/// Every command the server understands.
+///
+/// This list is the single source for `/help` and for usage errors, so a new
+/// command only needs an entry here, a [`Command`] variant, and a case in
+/// [`parse_command`].
+pub const COMMANDS: &[CommandInfo] = &[
+    CommandInfo {
+        name: "/help",
+        usage: "/help [command]",
+        description: "Show this list of commands, or explain one",
+        details: "Without a command, lists every command. With one, as in /help msg, explains how to use it.",
+    },
+    CommandInfo {
+        name: "/list",
+        usage: "/list",
+        description: "Show who is connected, room by room, and for how long (also /who)",
+        details: "After a line counting clients and rooms, each room is shown with its member count, then each of its clients with its ID, its nickname if it has one, and how long it has been connected. Rooms are in order of name and clients in order of ID.",
+    },
+    CommandInfo {
+        name: "/whoami",
+        usage: "/whoami",
+        description: "Show your ID, nickname, address and how long you have been connected",
+        details: "Only you see the reply, which also shows the address the server sees you connecting from.",
+    },
+    CommandInfo {
+        name: "/msg",
+        usage: "/msg <client_id|nickname>[,...] <message>",
+        description: "Send a private message to one client, or to several at once",
+        details: "The message reaches the clients named alone, whatever room they are in. Separate several with commas, as in /msg 2,alice hi; you are told which were not found. A target made of digits is the client holding that nickname if there is one, and the client with that ID otherwise; #3 is always client 3.",
+    },
+    CommandInfo {
+        name: "/reply",
+        usage: "/reply <message>",
+        description: "Answer the last client who sent you a private message (also /r)",
+        details: "The reply goes to that client's ID, even if it has changed its nickname since. If it has left, you are told so.",
+    },
+    CommandInfo {
+        name: "/getkey",
+        usage: "/getkey <client_id|nickname>[,...]",
+        description: "Show the public key private messages to a client are encrypted with",
+        details: "The bundled client asks for it on its own before every /msg, and encrypts the message so the server cannot read it.",
+    },
+    CommandInfo {
+        name: "/key",
+        usage: "/key <public_key>",
+        description: "Register the public key others encrypt private messages to you with",
+        details: "The key is 64 hex digits. The bundled client registers a new one each time it starts.",
+    },
+    CommandInfo {
+        name: "//",
+        usage: "//<message>",
+        description: "Send a message that starts with a slash, as in //shrug for /shrug",
+        details: "Only the first slash is removed, and the rest of the line is sent to your room as it is.",
+    },
+    CommandInfo {
+        name: "/me",
+        usage: "/me <action>",
+        description: "Describe what you are doing to your room, as in /me waves",
+        details: "Your room sees * <your name> <action> instead of a chat line. Actions cannot be sent privately.",
+    },
+    CommandInfo {
+        name: "/echo",
+        usage: "/echo on|off",
+        description: "Choose whether your own messages are sent back to you",
+        details: "Echo is on when you connect. The bundled client turns it off and shows what you send as You: <message> instead.",
+    },
+    CommandInfo {
+        name: "/nick",
+        usage: "/nick <name>",
+        description: "Change the name you chat under (also /rename)",
+        details: "Names are up to 24 letters, digits, - and _, and cannot be one someone else holds, in any case. Servers that require logins keep you under your username.",
+    },
+    CommandInfo {
+        name: "/motd",
+        usage: "/motd",
+        description: "Show the message of the day",
+        details: "This is the greeting the server shows when you connect, if it has one.",
+    },
+    CommandInfo {
+        name: "/join",
+        usage: "/join <room>",
+        description: "Move to a room, creating it if needed",
+        details: "Room names are up to 32 letters, digits, - and _. Only the clients in a room see the messages sent to it.",
+    },
+    CommandInfo {
+        name: "/leave",
+        usage: "/leave [room]",
+        description: "Leave your room and return to general",
+        details: "Naming the room is optional, and only guards against leaving a room you are not in.",
+    },
+    CommandInfo {
+        name: "/rooms",
+        usage: "/rooms",
+        description: "Show the open rooms and how many clients are in each",
+        details: "Every room with clients in it is listed with its member count.",
+    },
+    CommandInfo {
+        name: "/history",
+        usage: "/history <n>",
+        description: "Show the last n chat messages",
+        details: "Only messages sent to general are kept, and only on servers that store them.",
+    },
+    CommandInfo {
+        name: "/auth",
+        usage: "/auth <password>",
+        description: "Authenticate as an admin",
+        details: "Admins can use /kick, /stats and /filter. The password is set by whoever runs the server.",
+    },
+    CommandInfo {
+        name: "/kick",
+        usage: "/kick <client_id>",
+        description: "Disconnect a client (admin only)",
+        details: "The client is told it was kicked before it is disconnected. Requires /auth first.",
+    },
+    CommandInfo {
+        name: "/stats",
+        usage: "/stats",
+        description: "Show uptime, message and connection counts (admin only)",
+        details: "Requires /auth first.",
+    },
+    CommandInfo {
+        name: "/filter",
+        usage: "/filter add|remove <word>",
+        description: "Mask a word in messages with asterisks, or stop masking it (admin only)",
+        details: "Words are matched in any case, and the change is saved to the server's words file if it has one. Requires /auth first.",
+    },
+];
+
+/// Parses a line sent by a client.
+///
+/// Surrounding whitespace is ignored. A line starting with `//` is a broadcast of
+/// itself without the first slash, so messages can start with one.
+///
+/// # Example
+/// ```
+/// use chat_app::command::{parse_command, Command, Recipient};
+///
+/// assert_eq!(
+///     parse_command("/msg 2 Hello!"),
+///     Command::Private { targets: vec![Recipient::Id(2)], body: "Hello!".to_string() }
+/// );
+/// assert_eq!(parse_command("/msg 2"), Command::Invalid("/msg"));
+/// assert_eq!(parse_command("hi all"), Command::Broadcast("hi all".to_string()));
+/// assert_eq!(parse_command("//shrug"), Command::Broadcast("/shrug".to_string()));
+/// ```
+pub fn parse_command(line: &str) -> Command {
+    let line = line.trim();
+    if !line.starts_with('/') {
+        return Command::Broadcast(line.to_string());
+    }
+    if let Some(escaped) = line.strip_prefix('/').filter(|rest| rest.starts_with('/')) {
+        return Command::Broadcast(escaped.to_string());
+    }
+
+    let (command, args) = split_command(line);
+    match command {
+        "/help" if args.is_empty() => Command::Help(None),
+        "/help" => Command::Help(Some(args.to_string())),
+        "/list" | "/who" => Command::List,
+        "/whoami" => Command::WhoAmI,
+        "/motd" => Command::Motd,
+        "/rooms" => Command::Rooms,
+        "/stats" => Command::Stats,
+        "/join" if is_valid_room_name(args) => Command::Join(args.to_string()),
+        "/join" => Command::Invalid("/join"),
+        "/leave" if args.is_empty() => Command::Leave(None),
+        "/leave" if is_valid_room_name(args) => Command::Leave(Some(args.to_string())),
+        "/leave" => Command::Invalid("/leave"),
+        "/msg" => match parse_private_message(line) {
+            Some((targets, body)) => Command::Private {
+                targets,
+                body: body.to_string(),
+            },
+            None => Command::Invalid("/msg"),
+        },
+        "/key" => match parse_public_key(args) {
+            Some(key) => Command::Key(hex::encode(key.as_bytes())),
+            None => Command::Invalid("/key"),
+        },
+        "/getkey" => match parse_recipients(args) {
+            Some((targets, "")) => Command::GetKey(targets),
+            _ => Command::Invalid("/getkey"),
+        },
+        "/reply" | "/r" if !args.is_empty() => Command::Reply(args.to_string()),
+        "/reply" | "/r" => Command::Invalid("/reply"),
+        "/me" if !args.is_empty() => Command::Action(args.to_string()),
+        "/me" => Command::Invalid("/me"),
+        "/echo" => match args {
+            "on" => Command::Echo(true),
+            "off" => Command::Echo(false),
+            _ => Command::Invalid("/echo"),
+        },
+        "/nick" | "/rename" if !args.is_empty() => Command::Nick(args.to_string()),
+        "/nick" | "/rename" => Command::Invalid("/nick"),
+        "/auth" => Command::Auth(args.to_string()),
+        "/kick" => match args.parse() {
+            Ok(target) => Command::Kick(target),
+            Err(_) => Command::Invalid("/kick"),
+        },
+        "/filter" => match args.split_once(' ') {
+            Some((action, word)) if !word.trim().contains(char::is_whitespace) => match action {
+                "add" => Command::FilterAdd(word.trim().to_string()),
+                "remove" => Command::FilterRemove(word.trim().to_string()),
+                _ => Command::Invalid("/filter"),
+            },
+            _ => Command::Invalid("/filter"),
+        },
+        "/history" => match args.parse() {
+            Ok(count) if count > 0 => Command::History(count),
+            _ => Command::Invalid("/history"),
+        },
+        other => Command::Unknown(other.to_string()),
+    }
+}
+
+/// Returns the entry for `name` in `commands`, which may leave out the leading slash.
+///
+/// # Example
+/// ```
+/// use chat_app::command::{find_command, COMMANDS};
+///
+/// assert_eq!(find_command(COMMANDS, "msg"), find_command(COMMANDS, "/msg"));
+/// assert_eq!(find_command(COMMANDS, "/dance"), None);
+/// ```
+pub fn find_command<'a>(commands: &'a [CommandInfo], name: &str) -> Option<&'a CommandInfo> {
+    commands
+        .iter()
+        .find(|info| info.name == name || info.name.strip_prefix('/') == Some(name))
+}
+
+/// Returns the usage line for `command` as listed in [`COMMANDS`].
+///
+/// # Example
+/// ```
+/// use chat_app::command::usage;
+///
+/// assert_eq!(usage("/kick"), "Usage: /kick <client_id>");
+/// ```
+pub fn usage(command: &str) -> String {
+    let usage = COMMANDS
+        .iter()
+        .find(|info| info.name == command)
+        .map_or(command, |info| info.usage);
+    format!("Usage: {}", usage)
+}
+
+/// Splits a line into its first word and the trimmed rest.
+fn split_command(line: &str) -> (&str, &str) {
+    match line.split_once(char::is_whitespace) {
+        Some((command, args)) => (command, args.trim()),
+        None => (line, ""),
+    }
+}
+
+/// Parses a private message command.
+///
+/// This function interprets a message with the `/msg` command format.
+/// Valid commands are of the format `/msg <client_id|nickname>[,...] <message>` (see
+/// [`parse_recipients`]); a target that is a number written without leading zeros is
+/// a client ID.
+///
+/// # Arguments
+/// - `input`: The command string to parse.
+///
+/// # Returns
+/// - `Some((recipients, message))` if the input is valid.
+/// - `None` if the input is invalid.
+fn parse_private_message(input: &str) -> Option<(Vec<Recipient>, &str)> {
+    let (targets, body) = parse_recipients(input.strip_prefix("/msg ")?)?;
+    (!body.is_empty()).then_some((targets, body))
+}
+
+/// Parses the comma-separated list of clients at the start of `input`.
+///
+/// Whitespace around the commas is ignored, so a comma always adds the next word to
+/// the list. A client named twice is only listed once.
+///
+/// # Returns
+/// The clients, in the order first named, and the rest of `input` after the list
+/// with its leading whitespace removed. `None` if a target is missing or invalid.
+fn parse_recipients(input: &str) -> Option<(Vec<Recipient>, &str)> {
+    let mut targets = Vec::new();
+    let mut rest = input;
+    loop {
+        let word = rest.trim_start();
+        let end = word
+            .find(|c: char| c == ',' || c.is_whitespace())
+            .unwrap_or(word.len());
+        let target = parse_recipient(&word[..end])?;
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+        let after = word[end..].trim_start();
+        match after.strip_prefix(',') {
+            Some(next) => rest = next,
+            None => return Some((targets, after)),
+        }
+    }
+}
+
+/// Parses the client named by a command: by ID if `word` is a number written without
+/// leading zeros, only by ID if that number follows a `#`, and by nickname otherwise.
+fn parse_recipient(word: &str) -> Option<Recipient> {
+    if let Some(digits) = word.strip_prefix('#') {
+        return match digits.parse::<usize>() {
+            Ok(id) if id.to_string() == digits => Some(Recipient::ClientId(id)),
+            _ => None,
+        };
+    }
+    match word.parse::<usize>() {
+        Ok(id) if id.to_string() == word => Some(Recipient::Id(id)),
+        _ => is_valid_nickname(word).then(|| Recipient::Nick(word.to_string())),
+    }
+}
+
+/// Tests for the command module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_parse_private_message() {
+        // Valid private message
+        let input = "/msg 2 Hello, Client 2!";
+        let result = parse_private_message(input);
+        assert_eq!(result, Some((vec![Recipient::Id(2)], "Hello, Client 2!")));
+
+        // Valid private message to a nickname
+        let input = "/msg alice Hi Alice";
+        let result = parse_private_message(input);
+        assert_eq!(
+            result,
+            Some((vec![Recipient::Nick("alice".to_string())], "Hi Alice"))
+        );
+
+        // Digits that are not written as an ID can only be a nickname
+        assert_eq!(
+            parse_private_message("/msg 007 licensed"),
+            Some((vec![Recipient::Nick("007".to_string())], "licensed"))
+        );
+
+        // Invalid private message (missing client ID)
+        let invalid_input = "/msg Hello, Client!";
+        let invalid_result = parse_private_message(invalid_input);
+        assert_eq!(invalid_result, None);
+
+        // Invalid private message (missing command prefix)
+        let invalid_input = "msg 2 Hello!";
+        let invalid_result = parse_private_message(invalid_input);
+        assert_eq!(invalid_result, None);
+    }
+
+    #[test]
+    fn test_parse_broadcast() {
+        assert_eq!(
+            parse_command("  hello there  "),
+            Command::Broadcast("hello there".to_string())
+        );
+        // A slash later in the line does not make a command
+        assert_eq!(
+            parse_command("and/or"),
+            Command::Broadcast("and/or".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_simple_commands() {
+        assert_eq!(parse_command("/help"), Command::Help(None));
+        assert_eq!(
+            parse_command("/help  msg "),
+            Command::Help(Some("msg".to_string()))
+        );
+        assert_eq!(parse_command("/list"), Command::List);
+        assert_eq!(parse_command("/who"), Command::List);
+        assert_eq!(parse_command("/whoami"), Command::WhoAmI);
+        assert_eq!(parse_command("  /whoami  "), Command::WhoAmI);
+        assert_eq!(parse_command("/motd"), Command::Motd);
+        assert_eq!(parse_command("/kick 3"), Command::Kick(3));
+        assert_eq!(parse_command("/stats"), Command::Stats);
+        assert_eq!(
+            parse_command("/filter add darn"),
+            Command::FilterAdd("darn".to_string())
+        );
+        assert_eq!(
+            parse_command("/filter remove  Darn "),
+            Command::FilterRemove("Darn".to_string())
+        );
+        assert_eq!(parse_command("/history 20"), Command::History(20));
+        assert_eq!(parse_command("/rooms"), Command::Rooms);
+        assert_eq!(
+            parse_command("/join rust-help"),
+            Command::Join("rust-help".to_string())
+        );
+        assert_eq!(parse_command("/leave"), Command::Leave(None));
+        assert_eq!(
+            parse_command("/leave rust-help"),
+            Command::Leave(Some("rust-help".to_string()))
+        );
+        assert_eq!(
+            parse_command("/auth  secret "),
+            Command::Auth("secret".to_string())
+        );
+        assert_eq!(
+            parse_command("/nick alice"),
+            Command::Nick("alice".to_string())
+        );
+        assert_eq!(
+            parse_command("/rename alice"),
+            Command::Nick("alice".to_string())
+        );
+        assert_eq!(parse_command("/rename"), Command::Invalid("/nick"));
+        assert_eq!(parse_command("/echo off"), Command::Echo(false));
+        assert_eq!(parse_command("/echo  on "), Command::Echo(true));
+        assert_eq!(
+            parse_command("/me waves hello"),
+            Command::Action("waves hello".to_string())
+        );
+        // Not a private message, nor a broadcast of the raw line
+        assert_eq!(parse_private_message("/me waves hello"), None);
+        assert_eq!(parse_command("/me"), Command::Invalid("/me"));
+        assert_eq!(parse_command("/me   "), Command::Invalid("/me"));
+    }
+
+    #[test]
+    fn test_double_slash_escapes_one_slash() {
+        assert_eq!(
+            parse_command("//msg 2 hi"),
+            Command::Broadcast("/msg 2 hi".to_string())
+        );
+        assert_eq!(parse_command("///x"), Command::Broadcast("//x".to_string()));
+        assert_eq!(parse_command("//"), Command::Broadcast("/".to_string()));
+    }
+
+    #[test]
+    fn test_every_listed_command_is_parsed() {
+        // /help lists exactly what the dispatcher understands
+        for info in COMMANDS {
+            assert!(
+                !matches!(parse_command(info.name), Command::Unknown(_)),
+                "{} is listed but not parsed",
+                info.name
+            );
+            assert!(info.usage.starts_with(info.name));
+        }
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        let key = "AB".repeat(32);
+        assert_eq!(
+            parse_command(&format!("/key {}", key)),
+            Command::Key(key.to_lowercase())
+        );
+        assert_eq!(parse_command("/key abc"), Command::Invalid("/key"));
+        assert_eq!(parse_command("/key"), Command::Invalid("/key"));
+        assert_eq!(
+            parse_command("/getkey bob"),
+            Command::GetKey(vec![Recipient::Nick("bob".to_string())])
+        );
+        assert_eq!(
+            parse_command("/getkey 2, bob,2"),
+            Command::GetKey(vec![Recipient::Id(2), Recipient::Nick("bob".to_string())])
+        );
+        assert_eq!(parse_command("/getkey"), Command::Invalid("/getkey"));
+        assert_eq!(parse_command("/getkey 2 bob"), Command::Invalid("/getkey"));
+        assert_eq!(Recipient::Id(2).to_string(), "2");
+        assert_eq!(
+            parse_command("/getkey #2,bob"),
+            Command::GetKey(vec![
+                Recipient::ClientId(2),
+                Recipient::Nick("bob".to_string())
+            ])
+        );
+        assert_eq!(Recipient::ClientId(2).to_string(), "#2");
+        assert_eq!(parse_command("/getkey #02"), Command::Invalid("/getkey"));
+        assert_eq!(parse_command("/getkey #bob"), Command::Invalid("/getkey"));
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        assert_eq!(
+            parse_command("/reply on my way"),
+            Command::Reply("on my way".to_string())
+        );
+        assert_eq!(parse_command("/r  ok "), Command::Reply("ok".to_string()));
+        assert_eq!(parse_command("/r"), Command::Invalid("/reply"));
+        assert_eq!(parse_command("/reply   "), Command::Invalid("/reply"));
+        assert_eq!(usage("/reply"), "Usage: /reply <message>");
+    }
+
+    #[test]
+    fn test_parse_several_recipients() {
+        let both = vec![Recipient::Id(2), Recipient::Nick("alice".to_string())];
+        for input in [
+            "/msg 2,alice standup in 5",
+            "/msg 2 , alice standup in 5",
+            "/msg 2,  alice   standup in 5",
+            "/msg 2,alice,2,alice standup in 5",
+        ] {
+            assert_eq!(
+                parse_private_message(input),
+                Some((both.clone(), "standup in 5")),
+                "{}",
+                input
+            );
+        }
+
+        // A trailing comma takes the next word as a target
+        assert_eq!(
+            parse_private_message("/msg 2, alice, standup in 5"),
+            Some((
+                vec![
+                    Recipient::Id(2),
+                    Recipient::Nick("alice".to_string()),
+                    Recipient::Nick("standup".to_string())
+                ],
+                "in 5"
+            ))
+        );
+        assert_eq!(parse_private_message("/msg 2,alice, standup"), None);
+        assert_eq!(parse_private_message("/msg 2, hi"), None);
+
+        // Every target must be there
+        assert_eq!(parse_private_message("/msg , hi"), None);
+        assert_eq!(parse_private_message("/msg ,, hi"), None);
+        assert_eq!(parse_private_message("/msg 2,,5 hi"), None);
+        assert_eq!(parse_private_message("/msg  hi"), None);
+    }
+
+    #[test]
+    fn test_parse_malformed_msg() {
+        assert_eq!(parse_command("/msg"), Command::Invalid("/msg"));
+        assert_eq!(parse_command("/msg 2"), Command::Invalid("/msg"));
+        assert_eq!(parse_command("/msg /x hi"), Command::Invalid("/msg"));
+        assert_eq!(parse_command("/msg bob: hi"), Command::Invalid("/msg"));
+    }
+
+    #[test]
+    fn test_parse_malformed_commands() {
+        assert_eq!(parse_command("/nick"), Command::Invalid("/nick"));
+        assert_eq!(parse_command("/echo"), Command::Invalid("/echo"));
+        assert_eq!(parse_command("/echo maybe"), Command::Invalid("/echo"));
+        assert_eq!(parse_command("/kick"), Command::Invalid("/kick"));
+        assert_eq!(parse_command("/kick me"), Command::Invalid("/kick"));
+        assert_eq!(parse_command("/filter"), Command::Invalid("/filter"));
+        assert_eq!(parse_command("/filter add"), Command::Invalid("/filter"));
+        assert_eq!(
+            parse_command("/filter ban darn"),
+            Command::Invalid("/filter")
+        );
+        assert_eq!(
+            parse_command("/filter add a b"),
+            Command::Invalid("/filter")
+        );
+        assert_eq!(parse_command("/history"), Command::Invalid("/history"));
+        assert_eq!(parse_command("/history 0"), Command::Invalid("/history"));
+        assert_eq!(parse_command("/join"), Command::Invalid("/join"));
+        assert_eq!(parse_command("/join two rooms"), Command::Invalid("/join"));
+        assert_eq!(parse_command("/leave #rust"), Command::Invalid("/leave"));
+
+        // A space after the slash is not a command name
+        assert_eq!(parse_command("/ nick"), Command::Unknown("/".to_string()));
+        assert_eq!(parse_command("/"), Command::Unknown("/".to_string()));
+        assert_eq!(
+            parse_command("/dance now"),
+            Command::Unknown("/dance".to_string())
+        );
+        // Commands are case-sensitive
+        assert_eq!(
+            parse_command("/HELP"),
+            Command::Unknown("/HELP".to_string())
+        );
+    }
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(split_command("/help"), ("/help", ""));
+        assert_eq!(split_command("/kick  2 "), ("/kick", "2"));
+        assert_eq!(split_command("hello there"), ("hello", "there"));
+    }
+
+    #[test]
+    fn test_usage() {
+        assert_eq!(
+            usage("/msg"),
+            "Usage: /msg <client_id|nickname>[,...] <message>"
+        );
+        assert_eq!(usage("/nick"), "Usage: /nick <name>");
+    }
+}
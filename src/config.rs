@@ -0,0 +1,441 @@
+//! The config module reads the server's settings from a TOML file.
+//!
+//! ## Overview
+//! A [`ConfigFile`] holds every setting the `server` binary accepts, in a form that
+//! can be written by hand: plain numbers of seconds instead of durations, and paths
+//! instead of open stores. The binary loads one with `--config <path>`, lets
+//! command-line flags override it, and turns it into a [`ServerConfig`] with
+//! [`ConfigFile::server_config`]. The file can also name the [`ConfigFile::mode`] to
+//! run in, so a deployment needs nothing but `--config` on the command line. See
+//! `chat.example.toml` for a commented example.
+//!
+//! ## Key Features
+//! - **Partial**: Every setting is optional; missing ones keep their defaults.
+//! - **Validated**: Unknown keys and out-of-range values are reported with the name
+//!   of the offending setting instead of being ignored.
+
+use std::{
+    fmt::Display,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::ChatError;
+use crate::filter::WordFilter;
+use crate::history::MemoryStore;
+use crate::protocol::Protocol;
+use crate::server::{DuplicateLoginPolicy, ServerConfig, Transport};
+use crate::socket::SocketOptions;
+use crate::users::UserStore;
+
+/// The address the server binds to when neither the file nor the command line gives one.
+pub const DEFAULT_ADDRESS: &str = "0.0.0.0:8080";
+
+/// The values [`ConfigFile::mode`] accepts.
+pub const MODES: &[&str] = &["server", "client"];
+
+/// The server settings read from a configuration file.
+///
+/// Limits and timeouts set to `0` are turned off. There is no `auth_enabled` switch:
+/// clients must log in when `users_file` is set, and `/auth` works when
+/// `admin_password` is.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    /// What the binary runs when the command line names no mode: `server`, or
+    /// `client` to connect to the first of `address`.
+    pub mode: String,
+    /// The addresses to listen on, separated by commas.
+    pub address: String,
+    /// A Unix domain socket to accept local connections on as well, on Unix systems.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen_unix: Option<PathBuf>,
+    /// The address to serve Prometheus metrics on, separate from the chat.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_address: Option<String>,
+    /// The wire format, `text` or `framed-json`.
+    pub protocol: String,
+    /// How clients connect, `tcp` or `websocket`.
+    pub transport: String,
+    /// The maximum number of clients connected at once.
+    pub max_clients: usize,
+    /// The maximum number of connections from a single IP address.
+    pub max_per_ip: usize,
+    /// The maximum size of a single message in bytes, also read as `max_message_len`.
+    #[serde(alias = "max_message_len")]
+    pub max_message_bytes: usize,
+    /// How many seconds a client may stay silent before it is disconnected.
+    pub idle_timeout_secs: u64,
+    /// How many seconds pass between heartbeat pings.
+    pub heartbeat_secs: u64,
+    /// How many pings in a row a client may leave unanswered.
+    pub heartbeat_max_missed: u32,
+    /// Whether to send small writes right away instead of batching them (`TCP_NODELAY`).
+    pub tcp_nodelay: bool,
+    /// How many seconds a connection may sit idle before TCP keepalive probes are sent.
+    pub tcp_keepalive_secs: u64,
+    /// The name substituted for `{server_name}` in the MOTD.
+    pub server_name: String,
+    /// The message of the day sent to each client right after its ID.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub motd: Option<String>,
+    /// The password that grants admin access through `/auth`, which is refused
+    /// without one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_password: Option<String>,
+    /// The rotating JSON log file of server activity.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_file: Option<PathBuf>,
+    /// The SQLite database that keeps the message history.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history_db: Option<PathBuf>,
+    /// How many recent messages to keep in memory when there is no `history_db`.
+    pub history_size: usize,
+    /// The users file clients log in with (see [`crate::users`]); without one, no
+    /// client is asked to log in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub users_file: Option<PathBuf>,
+    /// The words file whose words are masked in broadcasts, one per line (see
+    /// [`crate::filter`]).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub word_filter_file: Option<PathBuf>,
+    /// Whether filtered words are only masked when they are not part of a longer word.
+    pub word_filter_whole_words: bool,
+    /// What happens when a logged-in account logs in again: `reject` the new
+    /// connection or `replace` the old one.
+    pub duplicate_login: String,
+    /// Whether clients must pick a nickname before they can chat.
+    pub require_nickname: bool,
+    /// Whether chat messages start with the time the server sent them.
+    pub timestamps: bool,
+    /// The PEM certificate chain that encrypts connections, given with `tls_key`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_cert: Option<PathBuf>,
+    /// The PEM private key for `tls_cert`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_key: Option<PathBuf>,
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        let defaults = ServerConfig::default();
+        let seconds = |duration: Option<Duration>| duration.map_or(0, |d| d.as_secs());
+        Self {
+            mode: "server".to_string(),
+            address: DEFAULT_ADDRESS.to_string(),
+            listen_unix: None,
+            metrics_address: None,
+            protocol: "text".to_string(),
+            transport: "tcp".to_string(),
+            max_clients: defaults.max_clients.unwrap_or(0),
+            max_per_ip: defaults.max_connections_per_ip.unwrap_or(0),
+            max_message_bytes: defaults.max_message_bytes,
+            idle_timeout_secs: seconds(defaults.idle_timeout),
+            heartbeat_secs: seconds(defaults.heartbeat_interval),
+            heartbeat_max_missed: defaults.heartbeat_max_missed,
+            tcp_nodelay: defaults.socket_options.nodelay,
+            tcp_keepalive_secs: seconds(defaults.socket_options.keepalive),
+            server_name: defaults.server_name,
+            motd: None,
+            admin_password: None,
+            log_file: None,
+            history_db: None,
+            history_size: 0,
+            users_file: None,
+            word_filter_file: None,
+            word_filter_whole_words: defaults.word_filter.whole_words(),
+            duplicate_login: "reject".to_string(),
+            require_nickname: false,
+            timestamps: defaults.timestamps,
+            tls_cert: None,
+            tls_key: None,
+        }
+    }
+}
+
+impl ConfigFile {
+    /// Reads and validates the configuration file at `path`.
+    ///
+    /// # Errors
+    /// Returns [`ChatError::Io`] if the file cannot be read, or fails for the same
+    /// reasons as [`ConfigFile::from_toml`].
+    pub fn from_file(path: &Path) -> Result<Self, ChatError> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parses and validates the contents of a configuration file.
+    ///
+    /// # Errors
+    /// Returns [`ChatError::Config`] naming the problem if `text` is not valid TOML,
+    /// has an unknown key or a value of the wrong type, or fails
+    /// [`ConfigFile::validate`].
+    ///
+    /// # Example
+    /// ```
+    /// use chat_app::config::ConfigFile;
+    ///
+    /// let config = ConfigFile::from_toml("max_clients = 50").unwrap();
+    /// assert_eq!(config.max_clients, 50);
+    /// assert_eq!(config.address, "0.0.0.0:8080");
+    ///
+    /// assert!(ConfigFile::from_toml("max_message_bytes = 0").is_err());
+    /// ```
+    pub fn from_toml(text: &str) -> Result<Self, ChatError> {
+        let config: Self = toml::from_str(text).map_err(invalid)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Returns the address a client started from this file connects to: the first of
+    /// [`ConfigFile::address`].
+    ///
+    /// # Example
+    /// ```
+    /// use chat_app::config::ConfigFile;
+    ///
+    /// let config = ConfigFile::from_toml("mode = \"client\"\naddress = \"chat.lan:8080, [::1]:8080\"").unwrap();
+    /// assert_eq!(config.client_address(), "chat.lan:8080");
+    /// ```
+    pub fn client_address(&self) -> &str {
+        self.address.split(',').next().unwrap_or_default().trim()
+    }
+
+    /// Writes the settings as TOML that [`ConfigFile::from_toml`] reads back unchanged.
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("every setting has a TOML representation")
+    }
+
+    /// Checks that every setting is in range.
+    ///
+    /// # Errors
+    /// Returns [`ChatError::Config`] naming the first setting that is empty, out of
+    /// range or incomplete.
+    pub fn validate(&self) -> Result<(), ChatError> {
+        if !MODES.contains(&self.mode.as_str()) {
+            return Err(invalid(format!(
+                "mode must be one of {}, not {:?}",
+                MODES.join(", "),
+                self.mode
+            )));
+        }
+        if self.address.trim().is_empty() {
+            return Err(invalid("address must not be empty"));
+        }
+        self.protocol.parse::<Protocol>().map_err(invalid)?;
+        self.transport.parse::<Transport>().map_err(invalid)?;
+        self.duplicate_login
+            .parse::<DuplicateLoginPolicy>()
+            .map_err(invalid)?;
+        if self.max_message_bytes == 0 {
+            return Err(invalid("max_message_bytes must be at least 1"));
+        }
+        if self.heartbeat_max_missed == 0 {
+            return Err(invalid("heartbeat_max_missed must be at least 1"));
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err(invalid("tls_cert and tls_key must be given together"));
+        }
+        Ok(())
+    }
+
+    /// Builds the [`ServerConfig`] described by the file, opening the history
+    /// database, the users file, the words file and the TLS certificate it names.
+    ///
+    /// # Errors
+    /// Returns [`ChatError::Config`] if the settings are invalid (see
+    /// [`ConfigFile::validate`]), if a named file cannot be opened, or if it needs a
+    /// feature this build lacks.
+    pub async fn server_config(&self) -> Result<ServerConfig, ChatError> {
+        self.validate()?;
+        let limit = |value: usize| (value > 0).then_some(value);
+        let seconds = |value: u64| (value > 0).then(|| Duration::from_secs(value));
+
+        let mut config = ServerConfig {
+            protocol: self.protocol.parse().map_err(invalid)?,
+            transport: self.transport.parse().map_err(invalid)?,
+            max_clients: limit(self.max_clients),
+            max_connections_per_ip: limit(self.max_per_ip),
+            max_message_bytes: self.max_message_bytes,
+            idle_timeout: seconds(self.idle_timeout_secs),
+            heartbeat_interval: seconds(self.heartbeat_secs),
+            heartbeat_max_missed: self.heartbeat_max_missed,
+            socket_options: SocketOptions {
+                nodelay: self.tcp_nodelay,
+                keepalive: seconds(self.tcp_keepalive_secs),
+            },
+            server_name: self.server_name.clone(),
+            motd: self.motd.clone().filter(|motd| !motd.is_empty()),
+            admin_password: self.admin_password.clone(),
+            log_path: self.log_file.clone(),
+            require_nickname: self.require_nickname,
+            duplicate_login: self.duplicate_login.parse().map_err(invalid)?,
+            timestamps: self.timestamps,
+            ..ServerConfig::default()
+        };
+
+        if let Some(path) = &self.users_file {
+            // A users file that does not exist yet means guest mode
+            let users = UserStore::load(path).map_err(|e| context(e, "users file", path))?;
+            config.users = Some(Arc::new(users));
+        }
+        if let Some(path) = &self.word_filter_file {
+            config.word_filter = WordFilter::load(path, self.word_filter_whole_words)
+                .map_err(|e| context(e, "words file", path))?;
+        }
+
+        #[cfg(feature = "sqlite")]
+        if let Some(path) = &self.history_db {
+            let store = crate::history::SqliteStore::open(path)
+                .await
+                .map_err(|e| context(e, "history database", path))?;
+            config.message_store = Some(Arc::new(store));
+        }
+        #[cfg(not(feature = "sqlite"))]
+        if let Some(path) = &self.history_db {
+            return Err(unsupported("history_db", "sqlite", path));
+        }
+        if self.history_db.is_none() && self.history_size > 0 {
+            config.message_store = Some(Arc::new(MemoryStore::new(self.history_size)));
+        }
+
+        #[cfg(feature = "tls")]
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            let tls = crate::tls::load_config(cert, key)
+                .map_err(|e| context(e, "TLS certificate", cert))?;
+            config.tls = Some(tls);
+        }
+        #[cfg(not(feature = "tls"))]
+        if let Some(cert) = &self.tls_cert {
+            return Err(unsupported("tls_cert", "tls", cert));
+        }
+
+        Ok(config)
+    }
+}
+
+/// Wraps `error` as a [`ChatError::Config`].
+fn invalid(error: impl Display) -> ChatError {
+    ChatError::Config(error.to_string())
+}
+
+/// Prefixes `error` with the file it happened on.
+fn context(error: io::Error, what: &str, path: &Path) -> ChatError {
+    ChatError::Config(format!(
+        "failed to load {} {}: {}",
+        what,
+        path.display(),
+        error
+    ))
+}
+
+/// The error for a setting that needs a feature this build was compiled without.
+#[cfg(not(all(feature = "sqlite", feature = "tls")))]
+fn unsupported(setting: &str, feature: &str, path: &Path) -> ChatError {
+    ChatError::Config(format!(
+        "{} = {} needs the `{}` feature",
+        setting,
+        path.display(),
+        feature
+    ))
+}
+
+/// Tests for the config module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let default = ConfigFile::default();
+        assert_eq!(ConfigFile::from_toml(&default.to_toml()).unwrap(), default);
+
+        let custom = ConfigFile {
+            mode: "client".to_string(),
+            address: "127.0.0.1:9000,[::1]:9000".to_string(),
+            listen_unix: Some(PathBuf::from("/run/chat.sock")),
+            metrics_address: Some("127.0.0.1:9090".to_string()),
+            protocol: "json".to_string(),
+            max_clients: 0,
+            idle_timeout_secs: 0,
+            motd: Some("Welcome to {server_name}!\nBe nice.".to_string()),
+            admin_password: Some("hunter2".to_string()),
+            log_file: Some(PathBuf::from("chat.log")),
+            require_nickname: true,
+            timestamps: false,
+            tls_cert: Some(PathBuf::from("cert.pem")),
+            tls_key: Some(PathBuf::from("key.pem")),
+            ..ConfigFile::default()
+        };
+        assert_eq!(ConfigFile::from_toml(&custom.to_toml()).unwrap(), custom);
+    }
+
+    #[tokio::test]
+    async fn test_missing_settings_keep_their_defaults() {
+        let config = ConfigFile::from_toml(
+            "max_clients = 0\nidle_timeout_secs = 60\ntcp_keepalive_secs = 0\n",
+        )
+        .unwrap();
+        let server = config.server_config().await.unwrap();
+        let defaults = ServerConfig::default();
+
+        assert_eq!(server.max_clients, None);
+        assert_eq!(server.transport, Transport::Tcp);
+        assert_eq!(server.idle_timeout, Some(Duration::from_secs(60)));
+        assert_eq!(server.socket_options.keepalive, None);
+        assert!(server.message_store.is_none());
+        assert_eq!(
+            server.socket_options.nodelay,
+            defaults.socket_options.nodelay
+        );
+        assert_eq!(
+            server.max_connections_per_ip,
+            defaults.max_connections_per_ip
+        );
+        assert_eq!(server.heartbeat_interval, defaults.heartbeat_interval);
+        assert_eq!(server.server_name, defaults.server_name);
+    }
+
+    #[tokio::test]
+    async fn test_history_size_keeps_history_in_memory() {
+        let config = ConfigFile::from_toml("history_size = 20").unwrap();
+        let store = config.server_config().await.unwrap().message_store.unwrap();
+        let message = crate::history::ChatMessage::new(1, "hello");
+        store.save(&message).await.unwrap();
+        assert_eq!(store.recent(10).await.unwrap()[0].content, "hello");
+    }
+
+    #[test]
+    fn test_invalid_settings_are_named() {
+        let error = |text: &str| ConfigFile::from_toml(text).unwrap_err().to_string();
+
+        assert!(error("max_message_bytes = 0").contains("max_message_bytes"));
+        assert!(error("address = \"\"").contains("address"));
+        assert!(error("heartbeat_max_missed = 0").contains("heartbeat_max_missed"));
+        assert!(error("protocol = \"xml\"").contains("xml"));
+        assert!(error("transport = \"carrier-pigeon\"").contains("carrier-pigeon"));
+        assert!(error("duplicate_login = \"both\"").contains("both"));
+        assert!(error("mode = \"relay\"").contains("mode"));
+        assert!(error("mode = 1").contains("mode"));
+        assert!(error("tls_cert = \"cert.pem\"").contains("tls_key"));
+        assert!(error("max_clients = -1").contains("max_clients"));
+        assert!(error("max_clinets = 5").contains("max_clinets"));
+        assert!(error("auth_enabled = true").contains("auth_enabled"));
+    }
+
+    #[test]
+    fn test_max_message_len_is_an_alias() {
+        let config = ConfigFile::from_toml("max_message_len = 512").unwrap();
+        assert_eq!(config.max_message_bytes, 512);
+        assert!(config.to_toml().contains("max_message_bytes = 512"));
+    }
+
+    #[test]
+    fn test_example_file_is_valid() {
+        let example = include_str!("../chat.example.toml");
+        assert!(ConfigFile::from_toml(example).is_ok());
+    }
+}
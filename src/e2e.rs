@@ -0,0 +1,240 @@
+//! The e2e module encrypts private messages so that only the two clients can read them.
+//!
+//! ## Overview
+//! Every client makes a [`ClientKeys`] pair when it starts and registers the public
+//! half with the server through `/key <hex>`. To send `/msg bob hi`, the client first
+//! asks the server for bob's public key with `/getkey bob`, derives a key shared with
+//! bob by X25519, and sends the message sealed with ChaCha20-Poly1305 as an
+//! [`ENVELOPE_PREFIX`]ed hex string instead of the text. The server relays the
+//! envelope like any other private message, without being able to read it, and bob's
+//! client opens it with its own private key.
+//!
+//! ## Key Features
+//! - **Both Ends**: An envelope names both public keys, so the sender can also open
+//!   the copy the server confirms delivery with.
+//! - **Tamper-Evident**: A changed envelope fails to open instead of showing garbage.
+//! - **Trust**: Public keys come from the server, which is trusted to hand out the
+//!   right one; keys are not verified between users.
+
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Nonce,
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// The start of every encrypted message body.
+pub const ENVELOPE_PREFIX: &str = "e2e1:";
+
+/// Binds the derived keys to this use, so they are never reused elsewhere.
+const KEY_INFO: &[u8] = b"chat_app e2e v1";
+
+/// The key pair a client encrypts and decrypts private messages with.
+///
+/// # Example
+/// ```
+/// use chat_app::e2e::ClientKeys;
+///
+/// let (alice, bob) = (ClientKeys::generate(), ClientKeys::generate());
+/// let envelope = alice.encrypt(&bob.public, "hi bob");
+/// assert!(!envelope.contains("hi bob"));
+/// assert_eq!(bob.decrypt(&envelope).unwrap().text, "hi bob");
+/// ```
+pub struct ClientKeys {
+    /// The half that never leaves the client.
+    pub private: StaticSecret,
+    /// The half registered with the server and handed out to senders.
+    pub public: PublicKey,
+}
+
+impl std::fmt::Debug for ClientKeys {
+    /// Shows only the public key, so the private one never ends up in a log.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientKeys")
+            .field("public", &hex::encode(self.public.as_bytes()))
+            .finish_non_exhaustive()
+    }
+}
+
+/// A message taken out of its envelope.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Opened {
+    /// The public key of the other client: the sender, or the recipient if this
+    /// client sent the message.
+    pub peer: PublicKey,
+    /// The message as it was typed.
+    pub text: String,
+}
+
+/// Why an envelope could not be opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum DecryptError {
+    /// The body is not an envelope made by [`ClientKeys::encrypt`].
+    #[error("not an encrypted message")]
+    Malformed,
+    /// The envelope was sealed for two other clients.
+    #[error("encrypted for someone else")]
+    NotForUs,
+    /// The envelope was changed on the way, or sealed with other keys than it names.
+    #[error("message was tampered with")]
+    Tampered,
+}
+
+impl ClientKeys {
+    /// Makes a new random key pair.
+    pub fn generate() -> Self {
+        let private = StaticSecret::random();
+        let public = PublicKey::from(&private);
+        Self { private, public }
+    }
+
+    /// Returns the public key as the hex string sent with `/key`.
+    pub fn public_hex(&self) -> String {
+        hex::encode(self.public.as_bytes())
+    }
+
+    /// Seals `text` so that only this client and the owner of `recipient` can read it.
+    ///
+    /// # Returns
+    /// The envelope: [`ENVELOPE_PREFIX`] followed by the sender's and recipient's
+    /// public keys, the nonce and the ciphertext, in hex and separated by `:`.
+    pub fn encrypt(&self, recipient: &PublicKey, text: &str) -> String {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher(recipient)
+            .encrypt(&nonce, text.as_bytes())
+            .expect("messages are far below the cipher's size limit");
+        format!(
+            "{}{}:{}:{}:{}",
+            ENVELOPE_PREFIX,
+            hex::encode(self.public.as_bytes()),
+            hex::encode(recipient.as_bytes()),
+            hex::encode(nonce),
+            hex::encode(ciphertext)
+        )
+    }
+
+    /// Opens an envelope made by [`ClientKeys::encrypt`], sent by or to this client.
+    ///
+    /// # Errors
+    /// Returns a [`DecryptError`] if `envelope` is not one, names neither of this
+    /// client's keys, or fails to authenticate.
+    pub fn decrypt(&self, envelope: &str) -> Result<Opened, DecryptError> {
+        let fields: Vec<&str> = envelope
+            .trim()
+            .strip_prefix(ENVELOPE_PREFIX)
+            .ok_or(DecryptError::Malformed)?
+            .split(':')
+            .collect();
+        let [sender, recipient, nonce, ciphertext] = fields[..] else {
+            return Err(DecryptError::Malformed);
+        };
+        let sender = parse_public_key(sender).ok_or(DecryptError::Malformed)?;
+        let recipient = parse_public_key(recipient).ok_or(DecryptError::Malformed)?;
+        let nonce = hex::decode(nonce).map_err(|_| DecryptError::Malformed)?;
+        let ciphertext = hex::decode(ciphertext).map_err(|_| DecryptError::Malformed)?;
+        if nonce.len() != 12 {
+            return Err(DecryptError::Malformed);
+        }
+
+        let peer = if recipient == self.public {
+            sender
+        } else if sender == self.public {
+            recipient
+        } else {
+            return Err(DecryptError::NotForUs);
+        };
+        let plaintext = self
+            .cipher(&peer)
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| DecryptError::Tampered)?;
+        let text = String::from_utf8(plaintext).map_err(|_| DecryptError::Tampered)?;
+        Ok(Opened { peer, text })
+    }
+
+    /// Returns the cipher for messages between this client and the owner of `peer`.
+    ///
+    /// Both sides get the same one: X25519 gives both the same shared secret, and the
+    /// two public keys are fed to HKDF in a fixed order.
+    fn cipher(&self, peer: &PublicKey) -> ChaCha20Poly1305 {
+        let shared = self.private.diffie_hellman(peer);
+        let (low, high) = match self.public.as_bytes() < peer.as_bytes() {
+            true => (&self.public, peer),
+            false => (peer, &self.public),
+        };
+        let info = [KEY_INFO, low.as_bytes(), high.as_bytes()].concat();
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(None, shared.as_bytes())
+            .expand(&info, &mut key)
+            .expect("32 bytes is a valid HKDF output length");
+        ChaCha20Poly1305::new(&key.into())
+    }
+}
+
+/// Parses a public key sent as 64 hex digits, as by `/key`.
+///
+/// # Example
+/// ```
+/// use chat_app::e2e::{parse_public_key, ClientKeys};
+///
+/// let keys = ClientKeys::generate();
+/// assert_eq!(parse_public_key(&keys.public_hex()), Some(keys.public));
+/// assert_eq!(parse_public_key("abc"), None);
+/// ```
+pub fn parse_public_key(hex: &str) -> Option<PublicKey> {
+    let mut bytes = [0u8; 32];
+    hex::decode_to_slice(hex, &mut bytes).ok()?;
+    Some(PublicKey::from(bytes))
+}
+
+/// Returns whether a private message body is an envelope to open.
+pub fn is_envelope(body: &str) -> bool {
+    body.trim_start().starts_with(ENVELOPE_PREFIX)
+}
+
+/// Tests for the e2e module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_the_two_clients_can_open_a_message() {
+        let (alice, bob, eve) = (
+            ClientKeys::generate(),
+            ClientKeys::generate(),
+            ClientKeys::generate(),
+        );
+        let envelope = alice.encrypt(&bob.public, "meet at noon");
+        assert!(is_envelope(&envelope));
+        assert!(!envelope.contains("meet at noon"));
+
+        let opened = bob.decrypt(&envelope).unwrap();
+        assert_eq!(opened.text, "meet at noon");
+        assert_eq!(opened.peer, alice.public);
+        // The sender can read its own copy back
+        assert_eq!(alice.decrypt(&envelope).unwrap().peer, bob.public);
+        assert_eq!(eve.decrypt(&envelope), Err(DecryptError::NotForUs));
+
+        // The same text never seals to the same envelope twice
+        assert_ne!(alice.encrypt(&bob.public, "meet at noon"), envelope);
+    }
+
+    #[test]
+    fn test_changed_envelopes_do_not_open() {
+        let (alice, bob) = (ClientKeys::generate(), ClientKeys::generate());
+        let envelope = alice.encrypt(&bob.public, "hi");
+
+        let mut flipped = envelope.clone();
+        let last = if flipped.ends_with('0') { "1" } else { "0" };
+        flipped.replace_range(flipped.len() - 1.., last);
+        assert_eq!(bob.decrypt(&flipped), Err(DecryptError::Tampered));
+
+        assert_eq!(bob.decrypt("hi"), Err(DecryptError::Malformed));
+        assert_eq!(bob.decrypt("e2e1:abc"), Err(DecryptError::Malformed));
+        assert_eq!(
+            bob.decrypt(&envelope.replace(':', ";")),
+            Err(DecryptError::Malformed)
+        );
+    }
+}
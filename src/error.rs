@@ -0,0 +1,31 @@
+//! The error module names the ways the server and client can fail.
+//!
+//! ## Overview
+//! The entry points that run a server or a client, and the functions that load a
+//! [`crate::config::ConfigFile`], return a [`ChatError`] rather than an
+//! [`io::Error`], so that a program embedding the chat can tell a lost connection
+//! from a server that broke the protocol, a refused login or a bad setting.
+//!
+//! ## Key Features
+//! - **Distinguishable**: One variant per kind of failure, to match on.
+//! - **Compatible**: Any [`io::Error`] converts into [`ChatError::Io`] with `?`.
+
+use std::io;
+
+/// Why the chat server or client stopped, or could not start.
+#[derive(Debug, thiserror::Error)]
+pub enum ChatError {
+    /// Binding, connecting, reading or writing failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The other side sent something that does not follow the chat protocol, such
+    /// as a greeting the client cannot make sense of.
+    #[error("protocol error: {0}")]
+    Protocol(String),
+    /// The server turned the client's login away.
+    #[error("authentication failed: {0}")]
+    Auth(String),
+    /// A setting is invalid, or a file it names cannot be used.
+    #[error("invalid configuration: {0}")]
+    Config(String),
+}
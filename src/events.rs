@@ -0,0 +1,302 @@
+//! The events module lets embedders observe what happens on a running server.
+//!
+//! ## Overview
+//! The server reports connections, disconnections, and delivered messages as
+//! [`ServerEvent`]s instead of printing them directly. Any number of consumers can
+//! subscribe with [`crate::server::ChatServer::subscribe`], such as [`log_events`],
+//! which prints them. Every event is also recorded with `tracing` at its
+//! [`ServerEvent::level`], inside the span of the connection it concerns, which is how
+//! the binary logs them.
+//!
+//! ## Key Features
+//! - **Typed Events**: Every notable server action is a [`ServerEvent`] variant.
+//! - **Multiple Subscribers**: Each subscriber gets its own copy of every event.
+//! - **Non-blocking**: A subscriber that falls behind misses events rather than
+//!   stalling the server.
+//! - **Leveled**: Joins and leaves are `info`, messages `debug`, and refusals and
+//!   failed deliveries `warn`, so `RUST_LOG` can filter them.
+
+use crate::protocol::RejectionReason;
+use serde::Serialize;
+use std::{
+    fmt,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::Level;
+
+/// The number of events buffered per subscriber before new events are dropped.
+pub const EVENT_BUFFER: usize = 1024;
+
+/// Something that happened on the server.
+///
+/// Events serialize to JSON objects named by an `event` field, such as
+/// `{"event":"client_disconnected","id":1}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ServerEvent {
+    /// A client connected and was assigned `id`.
+    ClientConnected { id: usize, addr: SocketAddr },
+    /// A client connected over the server's Unix domain socket at `path`.
+    LocalClientConnected { id: usize, path: PathBuf },
+    /// A client disconnected or was dropped by the server.
+    ClientDisconnected { id: usize },
+    /// A connection was turned away because the server was full.
+    ConnectionRefused { addr: SocketAddr },
+    /// A connection on the Unix domain socket at `path` was turned away because the
+    /// server was full.
+    LocalConnectionRefused { path: PathBuf },
+    /// A connection was turned away because its address had too many connections open.
+    TooManyConnections { addr: SocketAddr },
+    /// A client's TLS handshake failed or timed out, so it never joined.
+    TlsHandshakeFailed { id: usize, reason: String },
+    /// A client's connection was upgraded to a WebSocket.
+    WebSocketUpgraded { id: usize },
+    /// A client's WebSocket handshake failed or timed out, so it never joined.
+    WebSocketHandshakeFailed { id: usize, reason: String },
+    /// A client changed its nickname with `/nick`.
+    NicknameChanged { id: usize, nick: String },
+    /// A client gave a wrong password or an unknown or already logged-in username.
+    LoginFailed { id: usize, username: String },
+    /// A client's message was sent to every connected client.
+    MessageBroadcast { from: usize, text: String },
+    /// A client sent a private message to another client.
+    PrivateMessage {
+        from: usize,
+        to: usize,
+        text: String,
+    },
+    /// A client's message was refused.
+    MessageRejected { id: usize, reason: RejectionReason },
+    /// A client authenticated as an admin.
+    AdminGranted { id: usize },
+    /// An admin disconnected a client with `/kick`.
+    ClientKicked { id: usize, by: usize },
+    /// A client was disconnected for staying silent too long.
+    ClientTimedOut { id: usize },
+    /// A message was dropped for a client whose send queue was full.
+    MessageDropped { id: usize },
+    /// A client was disconnected because its send queue was full.
+    ClientTooSlow { id: usize },
+    /// A client was muted for flooding.
+    ClientMuted { id: usize },
+    /// A client's mute expired.
+    ClientUnmuted { id: usize },
+    /// The server stopped accepting connections and is disconnecting its clients.
+    ShuttingDown,
+    /// Every client has been disconnected and the server has stopped.
+    Stopped,
+}
+
+impl ServerEvent {
+    /// Returns the level the event is recorded at with `tracing`.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_app::events::ServerEvent;
+    /// use tracing::Level;
+    ///
+    /// assert_eq!(ServerEvent::ClientDisconnected { id: 1 }.level(), Level::INFO);
+    /// assert_eq!(ServerEvent::MessageDropped { id: 1 }.level(), Level::WARN);
+    /// ```
+    pub fn level(&self) -> Level {
+        match self {
+            ServerEvent::MessageBroadcast { .. } | ServerEvent::PrivateMessage { .. } => {
+                Level::DEBUG
+            }
+            ServerEvent::ConnectionRefused { .. }
+            | ServerEvent::LocalConnectionRefused { .. }
+            | ServerEvent::TooManyConnections { .. }
+            | ServerEvent::TlsHandshakeFailed { .. }
+            | ServerEvent::WebSocketHandshakeFailed { .. }
+            | ServerEvent::LoginFailed { .. }
+            | ServerEvent::MessageRejected { .. }
+            | ServerEvent::MessageDropped { .. }
+            | ServerEvent::ClientTooSlow { .. }
+            | ServerEvent::ClientMuted { .. } => Level::WARN,
+            _ => Level::INFO,
+        }
+    }
+
+    /// Records the event with `tracing` at its [`ServerEvent::level`].
+    fn trace(&self) {
+        match self.level() {
+            Level::DEBUG => tracing::debug!("{}", self),
+            Level::WARN => tracing::warn!("{}", self),
+            _ => tracing::info!("{}", self),
+        }
+    }
+}
+
+impl fmt::Display for ServerEvent {
+    /// Renders the event as a line of the server's console log.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerEvent::ClientConnected { id, addr } => {
+                write!(f, "New connection: {} (Client {})", addr, id)
+            }
+            ServerEvent::LocalClientConnected { id, path } => {
+                write!(f, "New connection: {} (Client {})", path.display(), id)
+            }
+            ServerEvent::ClientDisconnected { id } => write!(f, "Client {} disconnected.", id),
+            ServerEvent::ConnectionRefused { addr } => {
+                write!(f, "Rejected connection from {}: server full", addr)
+            }
+            ServerEvent::LocalConnectionRefused { path } => {
+                write!(f, "Rejected connection on {}: server full", path.display())
+            }
+            ServerEvent::TooManyConnections { addr } => write!(
+                f,
+                "Rejected connection from {}: too many connections from this address",
+                addr
+            ),
+            ServerEvent::TlsHandshakeFailed { id, reason } => {
+                write!(f, "TLS handshake with Client {} failed: {}", id, reason)
+            }
+            ServerEvent::WebSocketUpgraded { id } => {
+                write!(f, "Client {} connected over WebSocket", id)
+            }
+            ServerEvent::WebSocketHandshakeFailed { id, reason } => {
+                write!(
+                    f,
+                    "WebSocket handshake with Client {} failed: {}",
+                    id, reason
+                )
+            }
+            ServerEvent::NicknameChanged { id, nick } => {
+                write!(f, "Client {} is now known as {}", id, nick)
+            }
+            ServerEvent::LoginFailed { id, username } => {
+                write!(f, "Client {} failed to log in as {}", id, username)
+            }
+            ServerEvent::MessageBroadcast { from, text } => write!(f, "Client {}: {}", from, text),
+            ServerEvent::PrivateMessage { from, to, text } => write!(
+                f,
+                "Private message from Client {} to Client {}: {}",
+                from, to, text
+            ),
+            ServerEvent::MessageRejected { id, reason } => {
+                write!(f, "Rejected message from Client {}: {}", id, reason.code())
+            }
+            ServerEvent::AdminGranted { id } => write!(f, "Client {} is now an admin.", id),
+            ServerEvent::ClientKicked { id, by } => {
+                write!(f, "Client {} was kicked by Client {}.", id, by)
+            }
+            ServerEvent::ClientTimedOut { id } => write!(f, "Client {} timed out.", id),
+            ServerEvent::MessageDropped { id } => {
+                write!(f, "Dropped a message for Client {}: send queue full.", id)
+            }
+            ServerEvent::ClientTooSlow { id } => {
+                write!(f, "Client {} disconnected for not keeping up.", id)
+            }
+            ServerEvent::ClientMuted { id } => write!(f, "Client {} muted for flooding.", id),
+            ServerEvent::ClientUnmuted { id } => write!(f, "Client {} unmuted.", id),
+            ServerEvent::ShuttingDown => write!(f, "Shutting down server..."),
+            ServerEvent::Stopped => write!(f, "Server stopped."),
+        }
+    }
+}
+
+/// Fans server events out to every subscriber.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EventBus {
+    subscribers: Arc<Mutex<Vec<mpsc::Sender<ServerEvent>>>>,
+}
+
+impl EventBus {
+    /// Registers a new subscriber that receives every event emitted from now on.
+    pub(crate) fn subscribe(&self) -> mpsc::Receiver<ServerEvent> {
+        let (tx, rx) = mpsc::channel(EVENT_BUFFER);
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Records `event` with `tracing`, then sends it to every subscriber, forgetting
+    /// those that have gone away.
+    pub(crate) fn emit(&self, event: ServerEvent) {
+        event.trace();
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| match tx.try_send(event.clone()) {
+                Ok(()) | Err(TrySendError::Full(_)) => true,
+                Err(TrySendError::Closed(_)) => false,
+            });
+    }
+}
+
+/// Prints every event to the console until the server drops its side of the stream.
+///
+/// This is the console log of the `server` binary.
+///
+/// # Example
+/// ```no_run
+/// use chat_app::{error::ChatError, events::log_events, server::ChatServer};
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), ChatError> {
+///     let server = ChatServer::bind("127.0.0.1:8080").await?;
+///     tokio::spawn(log_events(server.subscribe()));
+///     server.run().await
+/// }
+/// ```
+pub async fn log_events(mut events: mpsc::Receiver<ServerEvent>) {
+    while let Some(event) = events.recv().await {
+        println!("{}", event);
+    }
+}
+
+/// Tests for the events module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_every_subscriber_gets_each_event() {
+        let bus = EventBus::default();
+        let mut first = bus.subscribe();
+        let mut second = bus.subscribe();
+
+        bus.emit(ServerEvent::ClientDisconnected { id: 1 });
+        assert_eq!(
+            first.recv().await,
+            Some(ServerEvent::ClientDisconnected { id: 1 })
+        );
+        assert_eq!(
+            second.recv().await,
+            Some(ServerEvent::ClientDisconnected { id: 1 })
+        );
+
+        // Closed subscribers are dropped on the next event
+        drop(second);
+        bus.emit(ServerEvent::Stopped);
+        assert_eq!(bus.subscribers.lock().unwrap().len(), 1);
+        assert_eq!(first.recv().await, Some(ServerEvent::Stopped));
+    }
+
+    #[test]
+    fn test_levels() {
+        let message = ServerEvent::MessageBroadcast {
+            from: 1,
+            text: "hi".to_string(),
+        };
+        assert_eq!(message.level(), Level::DEBUG);
+        assert_eq!(ServerEvent::ClientTooSlow { id: 1 }.level(), Level::WARN);
+        assert_eq!(ServerEvent::Stopped.level(), Level::INFO);
+    }
+
+    #[test]
+    fn test_console_rendering() {
+        let event = ServerEvent::PrivateMessage {
+            from: 1,
+            to: 2,
+            text: "hi".to_string(),
+        };
+        assert_eq!(
+            event.to_string(),
+            "Private message from Client 1 to Client 2: hi"
+        );
+    }
+}
@@ -0,0 +1,287 @@
+//! The filter module masks banned words in the messages clients broadcast.
+//!
+//! ## Overview
+//! A [`WordFilter`] holds the words the server does not let through. Before a
+//! broadcast or action is sent to the room, every banned word in it is replaced with
+//! as many `*` as it has characters, so `darn it` becomes `**** it`. The list is
+//! loaded from a words file with one word per line (see
+//! [`crate::server::ServerConfig::word_filter`]), and admins can change it while the
+//! server runs with `/filter add <word>` and `/filter remove <word>`.
+//!
+//! ## Key Features
+//! - **Case-Insensitive**: `Darn` and `DARN` are masked as well as `darn`.
+//! - **Whole Words**: By default only whole words are masked, so banning `ass` leaves
+//!   `class` alone. Turning this off masks the word wherever it appears.
+//! - **Persistent**: Words added or removed by admins are written back to the file.
+
+use std::{
+    fs, io,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::task::spawn_blocking;
+
+/// The words masked in broadcast messages.
+///
+/// Clones share the same list, so a word added by one connection is masked for all.
+/// Changes are made one at a time, and the words file is written without holding up
+/// [`apply`](WordFilter::apply).
+///
+/// # Example
+/// ```
+/// use chat_app::filter::WordFilter;
+///
+/// let filter = WordFilter::new(["darn"], true);
+/// assert_eq!(filter.apply("Darn it, darnation"), "**** it, darnation");
+/// ```
+#[derive(Debug, Clone)]
+pub struct WordFilter {
+    /// The banned words, in lowercase and in the order they were added.
+    words: Arc<Mutex<Vec<String>>>,
+    /// Whether a word is only masked when it is not part of a longer word.
+    whole_words: bool,
+    /// The words file that changes are written to, if any.
+    path: Option<PathBuf>,
+    /// Held while a word is added or removed, so changes and their writes to the
+    /// file happen in order.
+    changing: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Default for WordFilter {
+    fn default() -> Self {
+        Self::new(Vec::<String>::new(), true)
+    }
+}
+
+impl WordFilter {
+    /// Creates a filter for `words` that is not backed by a file.
+    ///
+    /// # Arguments
+    /// * `words` - The banned words. Blank ones are skipped.
+    /// * `whole_words` - Whether to leave words alone when they are part of a longer
+    ///   word.
+    pub fn new(words: impl IntoIterator<Item = impl AsRef<str>>, whole_words: bool) -> Self {
+        let mut list = Vec::new();
+        for word in words {
+            if let Some(word) = normalize(word.as_ref()) {
+                if !list.contains(&word) {
+                    list.push(word);
+                }
+            }
+        }
+        Self {
+            words: Arc::new(Mutex::new(list)),
+            whole_words,
+            path: None,
+            changing: Arc::default(),
+        }
+    }
+
+    /// Loads the words file at `path`, which later changes are written back to.
+    ///
+    /// The file has one word per line. Blank lines and lines starting with `#` are
+    /// skipped.
+    ///
+    /// # Returns
+    /// An empty filter if the file does not exist.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read.
+    pub fn load(path: impl AsRef<Path>, whole_words: bool) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let words = text
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'));
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            ..Self::new(words, whole_words)
+        })
+    }
+
+    /// Returns the banned words, in lowercase and in the order they were added.
+    pub fn words(&self) -> Vec<String> {
+        self.words.lock().unwrap().clone()
+    }
+
+    /// Returns whether words are only masked when they are not part of a longer word.
+    pub fn whole_words(&self) -> bool {
+        self.whole_words
+    }
+
+    /// Bans `word`, appending it to the words file if there is one.
+    ///
+    /// # Returns
+    /// `false` if it was banned already.
+    ///
+    /// # Errors
+    /// Returns an error if `word` is blank or contains whitespace, or if the file
+    /// cannot be written. The word is not banned then.
+    pub async fn add(&self, word: &str) -> io::Result<bool> {
+        let word = valid_word(word)?;
+        let _changing = self.changing.lock().await;
+        if self.words.lock().unwrap().contains(&word) {
+            return Ok(false);
+        }
+        if let Some(path) = self.path.clone() {
+            let line = format!("{}\n", word);
+            write_file(move || {
+                let mut file = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?;
+                file.write_all(line.as_bytes())
+            })
+            .await?;
+        }
+        self.words.lock().unwrap().push(word);
+        Ok(true)
+    }
+
+    /// Lifts the ban on `word`, rewriting the words file if there is one.
+    ///
+    /// The file is written with the remaining words only, so comments in it are lost.
+    ///
+    /// # Returns
+    /// `false` if it was not banned.
+    ///
+    /// # Errors
+    /// Returns an error if `word` is blank or contains whitespace, or if the file
+    /// cannot be written. The word stays banned then.
+    pub async fn remove(&self, word: &str) -> io::Result<bool> {
+        let word = valid_word(word)?;
+        let _changing = self.changing.lock().await;
+        let remaining: Vec<String> = {
+            let words = self.words.lock().unwrap();
+            if !words.contains(&word) {
+                return Ok(false);
+            }
+            words
+                .iter()
+                .filter(|banned| **banned != word)
+                .cloned()
+                .collect()
+        };
+        if let Some(path) = self.path.clone() {
+            let text: String = remaining.iter().map(|word| format!("{}\n", word)).collect();
+            write_file(move || fs::write(path, text)).await?;
+        }
+        *self.words.lock().unwrap() = remaining;
+        Ok(true)
+    }
+
+    /// Returns `text` with every banned word in it replaced by `*`s, one per character.
+    pub fn apply(&self, text: &str) -> String {
+        let words = self.words.lock().unwrap();
+        if words.is_empty() {
+            return text.to_string();
+        }
+        // ASCII lowercasing keeps every character at the same byte offset
+        let lower = text.to_ascii_lowercase();
+        let mut masked = vec![false; text.len()];
+        for word in words.iter() {
+            let mut start = 0;
+            while let Some(found) = lower[start..].find(word.as_str()) {
+                let begin = start + found;
+                let end = begin + word.len();
+                if !self.whole_words || is_whole_word(text, begin, end) {
+                    masked[begin..end].fill(true);
+                }
+                // Step one character on, so overlapping matches are found as well
+                start = begin + lower[begin..].chars().next().map_or(1, char::len_utf8);
+            }
+        }
+        text.char_indices()
+            .map(|(i, c)| if masked[i] { '*' } else { c })
+            .collect()
+    }
+}
+
+/// Runs `write` on a thread where blocking is allowed, so a slow disk does not stall
+/// the connections sharing the runtime.
+async fn write_file(write: impl FnOnce() -> io::Result<()> + Send + 'static) -> io::Result<()> {
+    spawn_blocking(write).await.map_err(io::Error::other)?
+}
+
+/// Returns `word` in lowercase, or `None` if it is blank.
+fn normalize(word: &str) -> Option<String> {
+    let word = word.trim();
+    (!word.is_empty()).then(|| word.to_ascii_lowercase())
+}
+
+/// Returns `word` in lowercase if it can be banned.
+fn valid_word(word: &str) -> io::Result<String> {
+    match normalize(word) {
+        Some(word) if !word.contains(char::is_whitespace) => Ok(word),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid word: {:?}", word),
+        )),
+    }
+}
+
+/// Returns whether `text[begin..end]` is neither preceded nor followed by a letter
+/// or digit.
+fn is_whole_word(text: &str, begin: usize, end: usize) -> bool {
+    let before = text[..begin].chars().next_back();
+    let after = text[end..].chars().next();
+    !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+}
+
+/// Tests for the filter module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_banned_words_are_masked() {
+        let filter = WordFilter::new(["darn", " HECK ", ""], true);
+        assert_eq!(filter.words(), ["darn", "heck"]);
+        assert_eq!(filter.apply("Darn it!"), "**** it!");
+        assert_eq!(filter.apply("what the HECK, darn"), "what the ****, ****");
+        assert_eq!(filter.apply("héllo darn ünd"), "héllo **** ünd");
+        assert_eq!(filter.apply("nothing to see"), "nothing to see");
+    }
+
+    #[test]
+    fn test_partial_matches_depend_on_whole_words() {
+        let whole = WordFilter::new(["ass"], true);
+        assert!(whole.whole_words());
+        assert_eq!(whole.apply("first class"), "first class");
+        assert_eq!(whole.apply("ass, passes"), "***, passes");
+
+        let partial = WordFilter::new(["ass"], false);
+        assert_eq!(partial.apply("first class"), "first cl***");
+        assert_eq!(partial.apply("ass, passes"), "***, p***es");
+    }
+
+    #[tokio::test]
+    async fn test_changes_are_written_to_the_file() {
+        let path = std::env::temp_dir().join(format!("chat-words-{}", std::process::id()));
+        fs::write(&path, "# Banned words\ndarn\n\n").unwrap();
+
+        let filter = WordFilter::load(&path, true).unwrap();
+        assert_eq!(filter.words(), ["darn"]);
+        assert!(filter.add("Heck").await.unwrap());
+        assert!(!filter.add("heck").await.unwrap());
+        assert!(filter.add("two words").await.is_err());
+        assert_eq!(
+            WordFilter::load(&path, true).unwrap().words(),
+            ["darn", "heck"]
+        );
+
+        assert!(filter.remove("DARN").await.unwrap());
+        assert!(!filter.remove("darn").await.unwrap());
+        assert_eq!(filter.apply("darn heck"), "darn ****");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "heck\n");
+        fs::remove_file(&path).unwrap();
+
+        let missing = WordFilter::load(&path, true).unwrap();
+        assert!(missing.words().is_empty());
+    }
+}
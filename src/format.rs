@@ -0,0 +1,139 @@
+//! The format module decides how chat messages read on the text protocol.
+//!
+//! ## Overview
+//! With [`crate::protocol::Protocol::Text`], every broadcast and private message
+//! reaches clients as a line of text. A [`MessageFormatter`] renders that line from
+//! the parts of the message, so a deployment can pick `<alice> hi` over `alice: hi`
+//! without touching the server loop. Set one with
+//! [`crate::server::ChatServerBuilder::formatter`]; [`DefaultFormatter`] is used
+//! otherwise.
+//!
+//! ## Key Features
+//! - **Pluggable**: Any `Send + Sync` type implementing [`MessageFormatter`] will do.
+//! - **Text Only**: JSON clients receive the parts themselves and render them as they
+//!   like, so formatters do not apply to them.
+//! - **Compatible**: [`DefaultFormatter`] renders lines as the server always has, which
+//!   the bundled client relies on to tag private messages and its own.
+
+use crate::protocol::ServerMessage;
+use std::fmt::Debug;
+
+/// The parts of a chat message, for a [`MessageFormatter`] to render.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChatLine<'a> {
+    /// The sender's client ID.
+    pub id: usize,
+    /// The sender's nickname, if it has one.
+    pub nick: Option<&'a str>,
+    /// What the sender wrote.
+    pub body: &'a str,
+    /// The recipient's client ID for a private message, or `None` for a broadcast.
+    pub target: Option<usize>,
+}
+
+impl ChatLine<'_> {
+    /// Returns whether the message was sent to one client with `/msg`.
+    pub fn is_private(&self) -> bool {
+        self.target.is_some()
+    }
+
+    /// Returns the name the sender is shown under: its nickname, or `Client <id>`.
+    pub fn sender(&self) -> String {
+        match self.nick {
+            Some(nick) => nick.to_string(),
+            None => format!("Client {}", self.id),
+        }
+    }
+}
+
+/// Renders chat messages as lines of the text protocol.
+///
+/// # Example
+/// ```
+/// use chat_app::format::{ChatLine, MessageFormatter};
+///
+/// /// Renders messages the way IRC clients show them.
+/// #[derive(Debug)]
+/// struct IrcFormatter;
+///
+/// impl MessageFormatter for IrcFormatter {
+///     fn format(&self, line: &ChatLine) -> String {
+///         match line.is_private() {
+///             true => format!("*{}* {}", line.sender(), line.body),
+///             false => format!("<{}> {}", line.sender(), line.body),
+///         }
+///     }
+/// }
+///
+/// let line = ChatLine { id: 1, nick: Some("alice"), body: "hi", target: None };
+/// assert_eq!(IrcFormatter.format(&line), "<alice> hi");
+/// ```
+pub trait MessageFormatter: Debug + Send + Sync {
+    /// Returns the line for `line`, without a trailing newline.
+    ///
+    /// The line must not contain a newline, or clients will read it as several.
+    fn format(&self, line: &ChatLine) -> String;
+}
+
+/// Renders broadcasts as `alice: hi` and private messages as `[Private] alice: hi`,
+/// or with `Client <id>` for clients without a nickname.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultFormatter;
+
+impl MessageFormatter for DefaultFormatter {
+    fn format(&self, line: &ChatLine) -> String {
+        chat_message(line).to_string()
+    }
+}
+
+/// Returns the protocol message carrying `line`, without a timestamp.
+pub fn chat_message(line: &ChatLine) -> ServerMessage {
+    let (from, nick, body) = (
+        line.id,
+        line.nick.map(str::to_string),
+        line.body.to_string(),
+    );
+    match line.target {
+        Some(_) => ServerMessage::Private {
+            from,
+            nick,
+            body,
+            timestamp: None,
+        },
+        None => ServerMessage::Broadcast {
+            from,
+            nick,
+            body,
+            timestamp: None,
+        },
+    }
+}
+
+/// Tests for the format module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_formatter_matches_the_protocol() {
+        let broadcast = ChatLine {
+            id: 2,
+            nick: None,
+            body: "hi",
+            target: None,
+        };
+        assert_eq!(DefaultFormatter.format(&broadcast), "Client 2: hi");
+
+        let private = ChatLine {
+            nick: Some("bob"),
+            target: Some(3),
+            ..broadcast
+        };
+        assert!(private.is_private());
+        assert_eq!(DefaultFormatter.format(&private), "[Private] bob: hi");
+        assert_eq!(
+            DefaultFormatter.format(&private),
+            chat_message(&private).to_string()
+        );
+    }
+}
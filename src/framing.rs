@@ -0,0 +1,287 @@
+//! The framing module splits a byte stream into length-prefixed messages.
+//!
+//! ## Overview
+//! Each frame is a 4-byte big-endian length followed by exactly that many bytes of
+//! payload. Unlike newline-delimited lines, a frame can carry any payload, including
+//! text with embedded `\n` characters, and a reader always knows where a message ends.
+//!
+//! ## Key Features
+//! - [`encode_frame`] and [`write_frame`] prepend the length header to a payload.
+//! - [`read_frame`] reads one frame with `read_exact`, refusing to buffer payloads
+//!   larger than the caller's limit.
+//! - [`read_line_bounded`] reads one newline-delimited line for the text protocol with
+//!   the same limit, so an endless line cannot exhaust memory either.
+
+use std::io;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+};
+
+/// The size of the big-endian length header in bytes.
+pub const HEADER_LEN: usize = 4;
+
+/// The largest payload a frame can carry.
+pub const MAX_FRAME_LEN: usize = 1024 * 1024;
+
+/// A frame read from a stream.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Frame {
+    /// A complete payload.
+    Data(Vec<u8>),
+    /// A frame whose payload exceeded the reader's limit. The payload was consumed
+    /// and discarded so the stream stays in sync; only its length is kept.
+    TooLong(usize),
+}
+
+/// Encodes `payload` as a single frame.
+///
+/// # Errors
+/// Returns an `InvalidInput` error if the payload is larger than [`MAX_FRAME_LEN`].
+///
+/// # Example
+/// ```
+/// use chat_app::framing::encode_frame;
+///
+/// let frame = encode_frame(b"hi").unwrap();
+/// assert_eq!(frame, [0, 0, 0, 2, b'h', b'i']);
+/// ```
+pub fn encode_frame(payload: &[u8]) -> io::Result<Vec<u8>> {
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "frame of {} bytes exceeds the {}-byte limit",
+                payload.len(),
+                MAX_FRAME_LEN
+            ),
+        ));
+    }
+
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    Ok(frame)
+}
+
+/// Writes `payload` to `writer` as a single frame.
+///
+/// The header and payload are written with one `write_all`, so frames from
+/// different writers sharing a socket never interleave mid-frame.
+///
+/// # Errors
+/// Returns an error if the payload is too large or the write fails.
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    writer.write_all(&encode_frame(payload)?).await
+}
+
+/// Reads the next frame from `reader`.
+///
+/// Payloads longer than `max_len` are read in small chunks and dropped rather than
+/// buffered, and reported as [`Frame::TooLong`].
+///
+/// # Returns
+/// - `Ok(Some(frame))` when a frame was read.
+/// - `Ok(None)` if the stream ended cleanly before a new frame started.
+///
+/// # Errors
+/// Returns an `UnexpectedEof` error if the stream ends in the middle of a frame.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_len: usize,
+) -> io::Result<Option<Frame>> {
+    let mut header = [0u8; HEADER_LEN];
+
+    // A clean EOF is only possible before the first header byte
+    let first = reader.read(&mut header[..1]).await?;
+    if first == 0 {
+        return Ok(None);
+    }
+    reader.read_exact(&mut header[1..]).await?;
+
+    let len = u32::from_be_bytes(header) as usize;
+    if len > max_len {
+        let discarded =
+            tokio::io::copy(&mut reader.take(len as u64), &mut tokio::io::sink()).await?;
+        if discarded < len as u64 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        return Ok(Some(Frame::TooLong(len)));
+    }
+
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload).await?;
+    Ok(Some(Frame::Data(payload)))
+}
+
+/// Reads the next newline-delimited line from `reader`.
+///
+/// At most `max_len` bytes of the line are buffered; the rest of an oversized line
+/// is consumed and dropped, and the line is reported as [`Frame::TooLong`] with its
+/// full length. A trailing `\n` or `\r\n` is not part of the returned line.
+///
+/// # Returns
+/// - `Ok(Some(frame))` when a line was read. A final line without a newline is
+///   returned as-is.
+/// - `Ok(None)` if the stream ended before a new line started.
+///
+/// # Errors
+/// Returns an error if reading from the stream fails.
+pub async fn read_line_bounded<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    max_len: usize,
+) -> io::Result<Option<Frame>> {
+    // One extra byte leaves room for the `\r` of a `\r\n` terminator
+    let capacity = max_len.saturating_add(1);
+    let mut line = Vec::new();
+    let mut len = 0;
+    let mut started = false;
+
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            if !started {
+                return Ok(None);
+            }
+            break;
+        }
+        started = true;
+
+        let newline = available.iter().position(|&b| b == b'\n');
+        let chunk = &available[..newline.unwrap_or(available.len())];
+        let keep = chunk.len().min(capacity - line.len());
+        line.extend_from_slice(&chunk[..keep]);
+        len += chunk.len();
+
+        let consumed = chunk.len() + usize::from(newline.is_some());
+        reader.consume(consumed);
+        if newline.is_some() {
+            break;
+        }
+    }
+
+    if line.len() == len && line.last() == Some(&b'\r') {
+        line.pop();
+        len -= 1;
+    }
+    if len > max_len {
+        return Ok(Some(Frame::TooLong(len)));
+    }
+    Ok(Some(Frame::Data(line)))
+}
+
+/// Tests for the framing module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes `payload` and reads it back.
+    async fn round_trip(payload: &[u8]) -> Frame {
+        let encoded = encode_frame(payload).unwrap();
+        let mut reader = encoded.as_slice();
+        let frame = read_frame(&mut reader, MAX_FRAME_LEN)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(reader.is_empty());
+        frame
+    }
+
+    #[tokio::test]
+    async fn test_empty_frame() {
+        assert_eq!(encode_frame(b"").unwrap(), [0, 0, 0, 0]);
+        assert_eq!(round_trip(b"").await, Frame::Data(Vec::new()));
+    }
+
+    #[tokio::test]
+    async fn test_max_size_frame() {
+        let payload = vec![b'x'; MAX_FRAME_LEN];
+        assert_eq!(round_trip(&payload).await, Frame::Data(payload));
+
+        // One byte more cannot be encoded at all
+        let oversized = vec![b'x'; MAX_FRAME_LEN + 1];
+        let error = encode_frame(&oversized).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn test_embedded_newlines() {
+        let payload = "first line\nsecond line\n\nlast".as_bytes();
+        assert_eq!(round_trip(payload).await, Frame::Data(payload.to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_frame_over_limit_is_skipped() {
+        let mut stream = encode_frame(b"too long").unwrap();
+        stream.extend(encode_frame(b"ok").unwrap());
+        let mut reader = stream.as_slice();
+
+        // The long payload is discarded and the next frame is still readable
+        assert_eq!(
+            read_frame(&mut reader, 4).await.unwrap(),
+            Some(Frame::TooLong(8))
+        );
+        assert_eq!(
+            read_frame(&mut reader, 4).await.unwrap(),
+            Some(Frame::Data(b"ok".to_vec()))
+        );
+        assert_eq!(read_frame(&mut reader, 4).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_line_over_limit_is_skipped() {
+        let stream = format!("{}\nok\r\nlast", "a".repeat(5000));
+        // A tiny buffer makes the long line arrive in many chunks
+        let mut reader = tokio::io::BufReader::with_capacity(16, stream.as_bytes());
+
+        assert_eq!(
+            read_line_bounded(&mut reader, 4096).await.unwrap(),
+            Some(Frame::TooLong(5000))
+        );
+        assert_eq!(
+            read_line_bounded(&mut reader, 4096).await.unwrap(),
+            Some(Frame::Data(b"ok".to_vec()))
+        );
+        assert_eq!(
+            read_line_bounded(&mut reader, 4096).await.unwrap(),
+            Some(Frame::Data(b"last".to_vec()))
+        );
+        assert_eq!(read_line_bounded(&mut reader, 4096).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_line_at_limit() {
+        let mut reader = "abcd\r\nabcde\n".as_bytes();
+        assert_eq!(
+            read_line_bounded(&mut reader, 4).await.unwrap(),
+            Some(Frame::Data(b"abcd".to_vec()))
+        );
+        assert_eq!(
+            read_line_bounded(&mut reader, 4).await.unwrap(),
+            Some(Frame::TooLong(5))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_frame_arriving_in_pieces() {
+        // A one-byte pipe hands the reader the header and payload a byte at a time
+        let (mut reader, mut writer) = tokio::io::duplex(1);
+        let payload = b"split\nacross reads".to_vec();
+        let writing = tokio::spawn({
+            let payload = payload.clone();
+            async move { write_frame(&mut writer, &payload).await }
+        });
+        assert_eq!(
+            read_frame(&mut reader, MAX_FRAME_LEN).await.unwrap(),
+            Some(Frame::Data(payload))
+        );
+        writing.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_truncated_frame() {
+        let encoded = encode_frame(b"hello").unwrap();
+        let mut reader = &encoded[..encoded.len() - 1];
+        let error = read_frame(&mut reader, MAX_FRAME_LEN).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,368 @@
+//! The history module stores broadcast messages so they can be replayed later.
+//!
+//! ## Overview
+//! A [`MessageStore`] saves every broadcast [`ChatMessage`] and returns the most recent
+//! ones on request. When a store is configured (see
+//! [`crate::server::ServerConfig::message_store`]), the server replays recent messages
+//! to every client that joins and answers `/history <n>`.
+//!
+//! ## Key Features
+//! - **Pluggable Storage**: Any type implementing [`MessageStore`] can back the history.
+//! - **In Memory**: [`MemoryStore`] keeps the last few messages in a ring buffer, for
+//!   servers that want a backlog without a database.
+//! - **SQLite**: [`SqliteStore`] keeps the history in a SQLite database, so it survives
+//!   restarts. It is available with the `sqlite` feature, which is enabled by default.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    io,
+    sync::{Mutex, PoisonError},
+};
+
+/// A broadcast message as kept in the history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatMessage {
+    /// The position of the message in the store, assigned when it is saved.
+    pub id: i64,
+    /// The ID of the client that sent the message.
+    pub sender_id: usize,
+    /// Whether the message was sent as text or as a `/me` action.
+    pub kind: MessageKind,
+    /// The text of the message, or the action without `/me`.
+    pub content: String,
+    /// When the server received the message.
+    pub timestamp: DateTime<Utc>,
+}
+
+impl ChatMessage {
+    /// Creates a message from `sender_id` received now, not yet saved.
+    pub fn new(sender_id: usize, content: impl Into<String>) -> Self {
+        Self {
+            id: 0,
+            sender_id,
+            kind: MessageKind::Chat,
+            content: content.into(),
+            timestamp: Utc::now(),
+        }
+    }
+
+    /// Creates an action, as sent with `/me <action>`, from `sender_id` received now.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_app::history::{ChatMessage, MessageKind};
+    ///
+    /// let action = ChatMessage::action(1, "waves");
+    /// assert_eq!(action.kind, MessageKind::Action);
+    /// assert_eq!(action.content, "waves");
+    /// ```
+    pub fn action(sender_id: usize, content: impl Into<String>) -> Self {
+        Self {
+            kind: MessageKind::Action,
+            ..Self::new(sender_id, content)
+        }
+    }
+}
+
+/// How a [`ChatMessage`] was sent, so it is replayed the same way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MessageKind {
+    /// A line of text for the room.
+    #[default]
+    Chat,
+    /// A `/me` action, replayed as `* Client 1 waves`.
+    Action,
+}
+
+impl MessageKind {
+    /// Returns the name the kind is stored under.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MessageKind::Chat => "chat",
+            MessageKind::Action => "action",
+        }
+    }
+
+    /// Parses a stored name, taking anything unknown for [`MessageKind::Chat`].
+    pub fn from_stored(name: &str) -> Self {
+        match name {
+            "action" => MessageKind::Action,
+            _ => MessageKind::Chat,
+        }
+    }
+}
+
+/// Where the server keeps its message history.
+#[async_trait]
+pub trait MessageStore: Debug + Send + Sync {
+    /// Appends `msg` to the history. Its `id` is ignored; the store assigns one.
+    ///
+    /// # Errors
+    /// Returns an error if the message cannot be stored.
+    async fn save(&self, msg: &ChatMessage) -> io::Result<()>;
+
+    /// Returns up to `limit` of the most recent messages, oldest first.
+    ///
+    /// # Errors
+    /// Returns an error if the history cannot be read.
+    async fn recent(&self, limit: usize) -> io::Result<Vec<ChatMessage>>;
+}
+
+/// A [`MessageStore`] that keeps the most recent messages in memory.
+///
+/// Once `capacity` messages are stored, each new one evicts the oldest. The history
+/// is lost when the server stops.
+#[derive(Debug)]
+pub struct MemoryStore {
+    capacity: usize,
+    state: Mutex<MemoryState>,
+}
+
+/// The messages held by a [`MemoryStore`] and the ID to give the next one.
+#[derive(Debug, Default)]
+struct MemoryState {
+    messages: VecDeque<ChatMessage>,
+    next_id: i64,
+}
+
+impl MemoryStore {
+    /// Creates an empty store that keeps up to `capacity` messages.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_app::history::{ChatMessage, MemoryStore, MessageStore};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> std::io::Result<()> {
+    ///     let store = MemoryStore::new(2);
+    ///     for text in ["one", "two", "three"] {
+    ///         store.save(&ChatMessage::new(1, text)).await?;
+    ///     }
+    ///     let recent = store.recent(10).await?;
+    ///     assert_eq!(recent[0].content, "two");
+    ///     assert_eq!(recent[1].content, "three");
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Returns the most messages the store keeps.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[async_trait]
+impl MessageStore for MemoryStore {
+    async fn save(&self, msg: &ChatMessage) -> io::Result<()> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.next_id += 1;
+        let id = state.next_id;
+        if state.messages.len() == self.capacity {
+            state.messages.pop_front();
+        }
+        state.messages.push_back(ChatMessage { id, ..msg.clone() });
+        Ok(())
+    }
+
+    async fn recent(&self, limit: usize) -> io::Result<Vec<ChatMessage>> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let skip = state.messages.len().saturating_sub(limit);
+        Ok(state.messages.iter().skip(skip).cloned().collect())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{ChatMessage, MessageKind, MessageStore};
+    use async_trait::async_trait;
+    use sqlx::{
+        sqlite::{SqliteConnectOptions, SqlitePool},
+        Row,
+    };
+    use std::{io, path::Path};
+
+    /// A [`MessageStore`] backed by a SQLite database file.
+    #[derive(Debug, Clone)]
+    pub struct SqliteStore {
+        pool: SqlitePool,
+    }
+
+    impl SqliteStore {
+        /// Opens the database at `path`, creating it and its tables if needed.
+        ///
+        /// # Example
+        /// ```no_run
+        /// use chat_app::{error::ChatError, history::SqliteStore, server::ChatServer};
+        ///
+        /// #[tokio::main]
+        /// async fn main() -> Result<(), ChatError> {
+        ///     let store = SqliteStore::open("chat.db").await?;
+        ///     let server = ChatServer::builder().message_store(store).build().await?;
+        ///     server.run().await
+        /// }
+        /// ```
+        ///
+        /// # Errors
+        /// Returns an error if the database cannot be opened or migrated.
+        pub async fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+            let options = SqliteConnectOptions::new()
+                .filename(path)
+                .create_if_missing(true);
+            let pool = SqlitePool::connect_with(options)
+                .await
+                .map_err(io::Error::other)?;
+            sqlx::migrate!()
+                .run(&pool)
+                .await
+                .map_err(io::Error::other)?;
+            Ok(Self { pool })
+        }
+    }
+
+    #[async_trait]
+    impl MessageStore for SqliteStore {
+        async fn save(&self, msg: &ChatMessage) -> io::Result<()> {
+            sqlx::query(
+                "INSERT INTO messages (sender_id, kind, content, timestamp) VALUES (?, ?, ?, ?)",
+            )
+            .bind(msg.sender_id as i64)
+            .bind(msg.kind.as_str())
+            .bind(&msg.content)
+            .bind(msg.timestamp)
+            .execute(&self.pool)
+            .await
+            .map_err(io::Error::other)?;
+            Ok(())
+        }
+
+        async fn recent(&self, limit: usize) -> io::Result<Vec<ChatMessage>> {
+            let rows = sqlx::query(
+                "SELECT id, sender_id, kind, content, timestamp FROM messages ORDER BY id DESC LIMIT ?",
+            )
+            .bind(i64::try_from(limit).unwrap_or(i64::MAX))
+            .fetch_all(&self.pool)
+            .await
+            .map_err(io::Error::other)?;
+
+            let mut messages = rows
+                .iter()
+                .map(|row| {
+                    Ok(ChatMessage {
+                        id: row.try_get("id")?,
+                        sender_id: row.try_get::<i64, _>("sender_id")? as usize,
+                        kind: MessageKind::from_stored(row.try_get("kind")?),
+                        content: row.try_get("content")?,
+                        timestamp: row.try_get("timestamp")?,
+                    })
+                })
+                .collect::<Result<Vec<_>, sqlx::Error>>()
+                .map_err(io::Error::other)?;
+            messages.reverse();
+            Ok(messages)
+        }
+    }
+}
+
+/// Tests for the history module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "sqlite")]
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn test_memory_store_evicts_oldest_messages() {
+        let store = MemoryStore::new(3);
+        assert!(store.recent(10).await.unwrap().is_empty());
+
+        for i in 1..=5 {
+            store
+                .save(&ChatMessage::new(i, format!("message {}", i)))
+                .await
+                .unwrap();
+        }
+
+        let recent = store.recent(10).await.unwrap();
+        let contents: Vec<&str> = recent.iter().map(|msg| msg.content.as_str()).collect();
+        assert_eq!(contents, ["message 3", "message 4", "message 5"]);
+        assert_eq!(recent[0].id, 3);
+
+        let newest = store.recent(1).await.unwrap();
+        assert_eq!(newest[0].content, "message 5");
+
+        // A store without room keeps nothing
+        let empty = MemoryStore::new(0);
+        empty.save(&ChatMessage::new(1, "gone")).await.unwrap();
+        assert!(empty.recent(10).await.unwrap().is_empty());
+    }
+
+    /// Returns a path for a fresh database file unique to `name`.
+    #[cfg(feature = "sqlite")]
+    fn scratch_db(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("chat-history-{}-{}.db", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_recent_returns_newest_messages_oldest_first() {
+        let path = scratch_db("recent");
+        let store = SqliteStore::open(&path).await.unwrap();
+        assert!(store.recent(10).await.unwrap().is_empty());
+
+        for i in 1..=5 {
+            store
+                .save(&ChatMessage::new(i, format!("message {}", i)))
+                .await
+                .unwrap();
+        }
+
+        let recent = store.recent(3).await.unwrap();
+        let contents: Vec<&str> = recent.iter().map(|msg| msg.content.as_str()).collect();
+        assert_eq!(contents, ["message 3", "message 4", "message 5"]);
+        assert_eq!(recent[0].sender_id, 3);
+        assert!(recent[0].id < recent[2].id);
+        assert!(recent[0].timestamp <= recent[2].timestamp);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_history_survives_reopening() {
+        let path = scratch_db("reopen");
+        let original = ChatMessage::new(1, "hello");
+        let store = SqliteStore::open(&path).await.unwrap();
+        store.save(&original).await.unwrap();
+        store.save(&ChatMessage::action(2, "waves")).await.unwrap();
+        drop(store);
+
+        // Opening an existing database does not run its migrations again
+        let store = SqliteStore::open(&path).await.unwrap();
+        let recent = store.recent(50).await.unwrap();
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].content, "hello");
+        assert_eq!(recent[0].kind, MessageKind::Chat);
+        assert_eq!(recent[0].timestamp, original.timestamp);
+        assert_eq!(recent[1].kind, MessageKind::Action);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+//! The ignore module hides the messages of users the client has chosen to mute.
+//!
+//! ## Overview
+//! Typing `/ignore <nickname>` adds a user to the client's [`IgnoreList`], and their
+//! broadcasts, actions and private messages are no longer displayed. `/unignore
+//! <nickname>` shows them again and `/ignorelist` lists who is muted. These commands
+//! are answered by the client itself, with a `[Local]` line, and never reach the
+//! server: the muted user is still in the room and can still see everything.
+//!
+//! ## Key Features
+//! - **Private**: Nobody, including the muted user, learns who a client ignores.
+//! - **Case-Insensitive**: Nicknames are unique regardless of case, so `/ignore Bob`
+//!   also mutes `bob`.
+//! - **Lasting**: The list is kept across reconnections for as long as the client runs.
+
+use crate::nicknames::is_valid_nickname;
+use crate::protocol::{split_timestamp, ServerMessage};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+/// The tag of the lines the client answers local commands with.
+pub const LOCAL_PREFIX: &str = "[Local]";
+
+/// The nicknames whose messages the client does not display.
+///
+/// Clones share the same list, so the task displaying messages sees the changes made
+/// by the commands the user types.
+///
+/// # Example
+/// ```
+/// use chat_app::ignore::IgnoreList;
+///
+/// let ignored = IgnoreList::new();
+/// assert_eq!(
+///     ignored.handle_command("/ignore bob").as_deref(),
+///     Some("[Local] Now ignoring bob")
+/// );
+/// assert!(ignored.hides_text_line("bob: spam\n"));
+/// assert!(!ignored.hides_text_line("alice: hi\n"));
+/// assert_eq!(ignored.handle_command("hello"), None);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreList {
+    /// The muted nicknames, in lowercase.
+    nicks: Arc<Mutex<HashSet<String>>>,
+}
+
+impl IgnoreList {
+    /// Creates an empty list.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mutes `nick`.
+    ///
+    /// # Returns
+    /// `false` if it was muted already.
+    pub fn ignore(&self, nick: &str) -> bool {
+        self.nicks.lock().unwrap().insert(nick.to_lowercase())
+    }
+
+    /// Stops muting `nick`.
+    ///
+    /// # Returns
+    /// `false` if it was not muted.
+    pub fn unignore(&self, nick: &str) -> bool {
+        self.nicks.lock().unwrap().remove(&nick.to_lowercase())
+    }
+
+    /// Returns whether `nick` is muted.
+    pub fn is_ignored(&self, nick: &str) -> bool {
+        self.nicks.lock().unwrap().contains(&nick.to_lowercase())
+    }
+
+    /// Returns the muted nicknames, in alphabetical order.
+    pub fn nicks(&self) -> Vec<String> {
+        let mut nicks: Vec<String> = self.nicks.lock().unwrap().iter().cloned().collect();
+        nicks.sort();
+        nicks
+    }
+
+    /// Carries out `line` if it is `/ignore`, `/unignore` or `/ignorelist`.
+    ///
+    /// # Returns
+    /// The `[Local]` line to show the user, or `None` if `line` is not one of these
+    /// commands and should be sent to the server.
+    pub fn handle_command(&self, line: &str) -> Option<String> {
+        let line = line.trim();
+        let (command, nick) = line.split_once(' ').unwrap_or((line, ""));
+        let nick = nick.trim();
+        let reply = match command {
+            "/ignorelist" => match self.nicks().as_slice() {
+                [] => "Not ignoring anyone".to_string(),
+                nicks => format!("Ignoring: {}", nicks.join(", ")),
+            },
+            "/ignore" | "/unignore" if !is_valid_nickname(nick) => {
+                format!("Usage: {} <nickname>", command)
+            }
+            "/ignore" => {
+                self.ignore(nick);
+                format!("Now ignoring {}", nick)
+            }
+            "/unignore" => match self.unignore(nick) {
+                true => format!("No longer ignoring {}", nick),
+                false => format!("You are not ignoring {}", nick),
+            },
+            _ => return None,
+        };
+        Some(format!("{} {}", LOCAL_PREFIX, reply))
+    }
+
+    /// Returns whether `message` was sent by a muted user.
+    pub fn hides_message(&self, message: &ServerMessage) -> bool {
+        match message {
+            ServerMessage::Broadcast {
+                nick: Some(nick), ..
+            }
+            | ServerMessage::Action {
+                nick: Some(nick), ..
+            }
+            | ServerMessage::Private {
+                nick: Some(nick), ..
+            } => self.is_ignored(nick),
+            _ => false,
+        }
+    }
+
+    /// Returns whether `line`, received from a server speaking the plain text
+    /// protocol, was sent by a muted user (see [`text_line_sender`]).
+    pub fn hides_text_line(&self, line: &str) -> bool {
+        text_line_sender(line).is_some_and(|nick| self.is_ignored(nick))
+    }
+}
+
+/// Returns the nickname that sent a chat line of the plain text protocol.
+///
+/// Broadcasts read `alice: hi`, private messages `[Private] alice: hi` and actions
+/// `* alice waves`, each possibly after a timestamp. Lines that do not start with a
+/// valid nickname, such as those of clients without one or of the server, have no
+/// sender.
+///
+/// # Example
+/// ```
+/// use chat_app::ignore::text_line_sender;
+///
+/// assert_eq!(text_line_sender("[Private] bob: psst"), Some("bob"));
+/// assert_eq!(text_line_sender("* bob waves"), Some("bob"));
+/// assert_eq!(text_line_sender("Client 2: hi"), None);
+/// ```
+pub fn text_line_sender(line: &str) -> Option<&str> {
+    let (_, line) = split_timestamp(line.trim_end());
+    let line = line.strip_prefix("[Private] ").unwrap_or(line);
+    let sender = match line.strip_prefix("* ") {
+        Some(action) => action.split_once(' ')?.0,
+        None => line.split_once(": ")?.0,
+    };
+    is_valid_nickname(sender).then_some(sender)
+}
+
+/// Tests for the ignore module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignored_senders_are_suppressed() {
+        let ignored = IgnoreList::new();
+        assert!(ignored.ignore("Bob"));
+        assert!(!ignored.ignore("bob"));
+
+        for line in [
+            "bob: spam\n",
+            "[Private] bob: psst\n",
+            "* bob waves\n",
+            "[2026-10-16T09:30:00Z] BOB: spam again\n",
+        ] {
+            assert!(ignored.hides_text_line(line), "{line:?} was shown");
+        }
+        for line in [
+            "alice: bob: are you there?\n",
+            "[Private] alice: hi\n",
+            "Client 3: hi\n",
+            "[Server] bob: please stop\n",
+            "Client 1 is now known as bob\n",
+        ] {
+            assert!(!ignored.hides_text_line(line), "{line:?} was hidden");
+        }
+
+        let from = |nick: &str| ServerMessage::Broadcast {
+            from: 2,
+            nick: Some(nick.to_string()),
+            body: "hi".to_string(),
+            timestamp: None,
+        };
+        assert!(ignored.hides_message(&from("bob")));
+        assert!(!ignored.hides_message(&from("alice")));
+
+        assert!(ignored.unignore("BOB"));
+        assert!(!ignored.hides_text_line("bob: hello again\n"));
+    }
+
+    #[test]
+    fn test_local_commands_are_answered_locally() {
+        let ignored = IgnoreList::new();
+        let reply = |line| ignored.handle_command(line);
+        assert_eq!(
+            reply("/ignorelist").as_deref(),
+            Some("[Local] Not ignoring anyone")
+        );
+        assert_eq!(
+            reply("/ignore bob").as_deref(),
+            Some("[Local] Now ignoring bob")
+        );
+        reply("/ignore alice");
+        assert_eq!(
+            reply("/ignorelist").as_deref(),
+            Some("[Local] Ignoring: alice, bob")
+        );
+        assert_eq!(
+            reply("/unignore bob").as_deref(),
+            Some("[Local] No longer ignoring bob")
+        );
+        assert_eq!(
+            reply("/unignore bob").as_deref(),
+            Some("[Local] You are not ignoring bob")
+        );
+        assert_eq!(
+            reply("/ignore").as_deref(),
+            Some("[Local] Usage: /ignore <nickname>")
+        );
+        assert_eq!(
+            reply("/ignore bob smith").as_deref(),
+            Some("[Local] Usage: /ignore <nickname>")
+        );
+
+        // Everything else goes to the server
+        assert_eq!(reply("/ignorant"), None);
+        assert_eq!(reply("/msg bob hi"), None);
+        assert_eq!(reply("ignore bob"), None);
+    }
+}
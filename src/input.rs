@@ -0,0 +1,217 @@
+//! The input module reads the lines the user types into the client.
+//!
+//! ## Overview
+//! At a terminal, lines are read with a line editor: the up and down arrows cycle
+//! through the lines sent before, also in earlier sessions, and Ctrl-C quits. The
+//! history is kept in a file, `~/.chat_history` unless
+//! [`crate::client::ClientOptions::history_file`] says otherwise. When standard input
+//! is a pipe or a file, lines are read as they come, with no editing or history.
+//!
+//! ## Key Features
+//! - **Non-blocking**: Lines are read on a thread of their own and handed over
+//!   through a channel, so reading never holds up the runtime.
+//! - **Tidy Output**: While the editor waits for a line, [`show`] prints messages
+//!   above it instead of through it.
+//! - **Safe Exit**: [`restore_terminal`] undoes the editor's terminal settings if
+//!   the client stops while a line is being read.
+
+use rustyline::{error::ReadlineError, history::History, DefaultEditor, ExternalPrinter};
+use std::{
+    fmt::Display,
+    io::{IsTerminal, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+use tokio::sync::mpsc::{self, Receiver};
+
+/// The name of the history file in the user's home directory.
+pub const HISTORY_FILE_NAME: &str = ".chat_history";
+
+/// The line sent on the user's behalf when they press Ctrl-C.
+pub const QUIT_COMMAND: &str = "/quit";
+
+/// Prints messages above the line being edited, while there is an editor.
+static PRINTER: Mutex<Option<Box<dyn ExternalPrinter + Send>>> = Mutex::new(None);
+
+/// Whether the next line typed must be kept out of the history.
+static FORGET_NEXT: AtomicBool = AtomicBool::new(false);
+
+/// The terminal settings from before the editor changed them.
+#[cfg(unix)]
+static SAVED_TERMINAL: Mutex<Option<nix::sys::termios::Termios>> = Mutex::new(None);
+
+/// Returns `~/.chat_history`, or `None` if the home directory is unknown.
+pub fn default_history_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE_NAME))
+}
+
+/// Reads lines typed by the user on a thread of their own.
+///
+/// Reading stdin blocks, so it gets a plain thread: unlike a runtime task, it cannot
+/// keep the client from exiting.
+///
+/// # Arguments
+/// * `history_file` - Where to load the line history from and save it to, if
+///   anywhere. Only used at a terminal.
+///
+/// # Returns
+/// The lines, which end when standard input is closed or the user presses Ctrl-C.
+pub fn read_input(history_file: Option<PathBuf>) -> Receiver<String> {
+    let (tx, rx) = mpsc::channel::<String>(10);
+    std::thread::spawn(move || {
+        let editor = match std::io::stdin().is_terminal() {
+            true => DefaultEditor::new().ok(),
+            false => None,
+        };
+        let Some(mut editor) = editor else {
+            for line in std::io::stdin().lines() {
+                let Ok(line) = line else { break };
+                if tx.blocking_send(line).is_err() {
+                    break; // The client has quit
+                }
+            }
+            return;
+        };
+
+        if let Some(path) = &history_file {
+            // A missing file only means there is no history yet
+            let _ = editor.load_history(path);
+        }
+        save_terminal();
+        if let Ok(printer) = editor.create_external_printer() {
+            *PRINTER.lock().unwrap() = Some(Box::new(printer));
+        }
+        loop {
+            let line = match editor.readline("") {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => {
+                    let _ = tx.blocking_send(QUIT_COMMAND.to_string());
+                    break;
+                }
+                Err(_) => break,
+            };
+            if !FORGET_NEXT.swap(false, Ordering::Relaxed) {
+                remember(editor.history_mut(), &line);
+            }
+            if let Some(path) = &history_file {
+                let _ = editor.save_history(path);
+            }
+            // Reading on would put the terminal back into the editor's hands
+            if line.trim() == QUIT_COMMAND || tx.blocking_send(line).is_err() {
+                break;
+            }
+        }
+        *PRINTER.lock().unwrap() = None;
+    });
+    rx
+}
+
+/// Adds `line` to `history`, unless it is blank.
+fn remember(history: &mut impl History, line: &str) {
+    if !line.trim().is_empty() {
+        let _ = history.add(line);
+    }
+}
+
+/// Keeps the next line the user types out of the history, as for a password.
+pub fn forget_next_line() {
+    FORGET_NEXT.store(true, Ordering::Relaxed);
+}
+
+/// Shows a prompt for the next line: at the end of the output, or on a line of its
+/// own above the line being edited if there is one.
+///
+/// # Errors
+/// Returns an error if standard output cannot be flushed.
+pub fn show_prompt(prompt: impl Display) -> std::io::Result<()> {
+    match PRINTER.lock().unwrap().as_mut() {
+        Some(printer) => {
+            let _ = printer.print(format!("{}\n", prompt.to_string().trim_end()));
+        }
+        None => {
+            print!("{}", prompt);
+            std::io::stdout().flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints `text` on a line of its own, above the line being edited if there is one.
+pub fn show(text: impl Display) {
+    let text = text.to_string();
+    match PRINTER.lock().unwrap().as_mut() {
+        Some(printer) => {
+            let _ = printer.print(format!("{}\n", text.trim_end_matches('\n')));
+        }
+        None => println!("{}", text.trim_end_matches('\n')),
+    }
+}
+
+/// Prints `text` as [`show`] does, but to standard error when there is no editor.
+pub fn show_error(text: impl Display) {
+    if PRINTER.lock().unwrap().is_some() {
+        show(text);
+    } else {
+        eprintln!("{}", text);
+    }
+}
+
+/// Remembers the terminal settings before the editor changes them.
+fn save_terminal() {
+    #[cfg(unix)]
+    if let Ok(settings) = nix::sys::termios::tcgetattr(std::io::stdin()) {
+        *SAVED_TERMINAL.lock().unwrap() = Some(settings);
+    }
+}
+
+/// Puts the terminal back the way it was before the editor changed it.
+///
+/// The client calls this before it returns, since the editor may still be waiting
+/// for a line and would otherwise leave the terminal unusable after the process exits.
+pub fn restore_terminal() {
+    #[cfg(unix)]
+    if let Some(settings) = SAVED_TERMINAL.lock().unwrap().as_ref() {
+        let _ = nix::sys::termios::tcsetattr(
+            std::io::stdin(),
+            nix::sys::termios::SetArg::TCSANOW,
+            settings,
+        );
+    }
+}
+
+/// Tests for the input module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustyline::history::{DefaultHistory, SearchDirection};
+
+    #[test]
+    fn test_history_cycles_and_survives_sessions() {
+        let mut history = DefaultHistory::new();
+        for line in ["hello", "   ", "/msg 2 hi", ""] {
+            remember(&mut history, line);
+        }
+        assert_eq!(history.len(), 2);
+
+        let path = std::env::temp_dir().join(format!("chat-history-{}", std::process::id()));
+        history.save(&path).unwrap();
+        assert!(path.is_file());
+        let mut loaded = DefaultHistory::new();
+        loaded.load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The up arrow starts from the newest line and walks back to the oldest
+        let entry = |index| {
+            loaded
+                .get(index, SearchDirection::Reverse)
+                .unwrap()
+                .map(|found| found.entry.into_owned())
+        };
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(entry(1).as_deref(), Some("/msg 2 hi"));
+        assert_eq!(entry(0).as_deref(), Some("hello"));
+    }
+}
@@ -0,0 +1,60 @@
+//! A simple chat application with a server and multiple clients.
+//!
+//! The crate is split into twenty-seven modules:
+//! - [`server`]: accepts client connections, broadcasts messages and relays private messages.
+//! - [`config`]: the server settings read from a TOML file.
+//! - [`command`]: parses the lines clients send into commands.
+//! - [`error`]: the [`error::ChatError`] returned when a server or client fails.
+//! - [`events`]: the stream of [`events::ServerEvent`]s a running server reports.
+//! - [`filter`]: the banned words masked in broadcast messages.
+//! - [`client`]: connects to a server and bridges the terminal with the chat.
+//! - [`color`]: the terminal colors the client shows each kind of message in.
+//! - [`e2e`]: the keys that encrypt private messages between two clients.
+//! - [`ignore`]: the users whose messages the client hides.
+//! - [`input`]: the lines the user types, with a history to cycle through.
+//! - [`settings`]: the bundle that moves the client's saved state to another machine.
+//! - [`protocol`]: the messages exchanged between the two and their wire encodings.
+//! - [`format`]: how chat messages read on the text protocol.
+//! - [`framing`]: length-prefixed frames used by the structured protocol.
+//! - [`codec`]: splits the protocol's byte stream into messages for message-based transports.
+//! - [`rate_limit`]: the per-connection token bucket that throttles senders.
+//! - [`logging`]: the optional rotating JSON log file of server activity.
+//! - [`metrics`]: the optional Prometheus endpoint for the server's counters.
+//! - [`history`]: the optional store of past messages replayed to joining clients.
+//! - [`rooms`]: the rooms that keep separate conversations apart.
+//! - [`nicknames`]: the unique names clients can chat under.
+//! - [`users`]: the accounts clients log in with.
+//! - [`socket`]: the TCP options set on every connection.
+//! - [`proxy`]: the SOCKS5 proxy the client can connect to the server through.
+//! - [`tls`]: the optional certificate and key that encrypt connections.
+//! - [`websocket`]: the optional WebSocket transport for browser clients.
+
+pub mod client;
+pub mod codec;
+pub mod color;
+pub mod command;
+pub mod config;
+pub mod e2e;
+pub mod error;
+pub mod events;
+pub mod filter;
+pub mod format;
+pub mod framing;
+pub mod history;
+pub mod ignore;
+pub mod input;
+pub mod logging;
+pub mod metrics;
+pub mod nicknames;
+pub mod protocol;
+pub mod proxy;
+pub mod rate_limit;
+pub mod rooms;
+pub mod server;
+pub mod settings;
+pub mod socket;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod users;
+#[cfg(feature = "websocket")]
+pub mod websocket;
@@ -0,0 +1,256 @@
+//! The logging module records server activity in a size-capped log file.
+//!
+//! ## Overview
+//! A [`Logger`] appends one timestamped JSON object per line to a log file. When the
+//! file would grow past its size limit, it is rotated: `chat.log` becomes
+//! `chat.log.1`, `chat.log.1` becomes `chat.log.2`, and so on, keeping at most
+//! [`KEPT_ROTATIONS`] old files. The server feeds its [`ServerEvent`] stream into a
+//! logger when [`crate::server::ServerConfig::log_path`] is set.
+//!
+//! ## Key Features
+//! - **JSON Lines**: Every entry is a single JSON object with a `ts` field holding
+//!   milliseconds since the Unix epoch, easy to process with standard tools.
+//! - **Rotation**: The log never grows much past the configured size.
+//! - **Shareable**: A [`Logger`] is cheap to clone, and clones append to the same file.
+
+use crate::events::ServerEvent;
+use serde_json::{Map, Value};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::{mpsc, Mutex},
+};
+
+/// The number of rotated files kept next to the active log file.
+pub const KEPT_ROTATIONS: usize = 5;
+
+/// The open log file and how many bytes it holds.
+#[derive(Debug)]
+struct LogFile {
+    file: File,
+    len: u64,
+}
+
+/// Appends timestamped JSON lines to a rotating log file.
+#[derive(Debug, Clone)]
+pub struct Logger {
+    path: PathBuf,
+    max_bytes: u64,
+    file: Arc<Mutex<LogFile>>,
+}
+
+impl Logger {
+    /// Opens `path` for appending, creating it if needed.
+    ///
+    /// # Arguments
+    /// - `path`: The active log file. Rotated files are stored next to it.
+    /// - `max_bytes`: The size at which the file is rotated.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened.
+    pub async fn open(path: impl Into<PathBuf>, max_bytes: u64) -> io::Result<Self> {
+        let path = path.into();
+        let file = open_append(&path).await?;
+        let len = file.metadata().await?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            file: Arc::new(Mutex::new(LogFile { file, len })),
+        })
+    }
+
+    /// Appends `entry` as a JSON line of the form `{"ts": ..., "message": entry}`.
+    ///
+    /// # Errors
+    /// Returns an error if writing or rotating the file fails.
+    pub async fn log(&self, entry: &str) -> io::Result<()> {
+        let mut fields = Map::new();
+        fields.insert("message".to_string(), entry.into());
+        self.write_entry(fields).await
+    }
+
+    /// Appends `event` as a JSON line, with its fields next to `ts` and an `event` name.
+    ///
+    /// # Example
+    /// A broadcast from Client 1 is logged as
+    /// `{"ts":1700000000000,"event":"message_broadcast","from":1,"text":"hi"}`.
+    ///
+    /// # Errors
+    /// Returns an error if writing or rotating the file fails.
+    pub async fn log_event(&self, event: &ServerEvent) -> io::Result<()> {
+        match serde_json::to_value(event)? {
+            Value::Object(fields) => self.write_entry(fields).await,
+            _ => unreachable!("server events serialize to objects"),
+        }
+    }
+
+    /// Stamps `fields` with the current time and appends them as one line.
+    async fn write_entry(&self, fields: Map<String, Value>) -> io::Result<()> {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        let mut entry = Map::new();
+        entry.insert("ts".to_string(), ts.into());
+        entry.extend(fields);
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+
+        let mut log = self.file.lock().await;
+        if log.len > 0 && log.len + line.len() as u64 > self.max_bytes {
+            log.file.flush().await?;
+            rotate(&self.path).await?;
+            log.file = open_append(&self.path).await?;
+            log.len = 0;
+        }
+        log.file.write_all(&line).await?;
+        log.file.flush().await?;
+        log.len += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Writes every event to `logger` until the server stops or drops its side of the stream.
+///
+/// Failed writes are reported on standard error and do not stop the logging.
+pub async fn log_to_file(logger: Logger, mut events: mpsc::Receiver<ServerEvent>) {
+    while let Some(event) = events.recv().await {
+        if let Err(e) = logger.log_event(&event).await {
+            eprintln!("Failed to write to {}: {}", logger.path.display(), e);
+        }
+        if event == ServerEvent::Stopped {
+            break;
+        }
+    }
+}
+
+/// Opens `path` for appending, creating it if needed.
+async fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
+
+/// Returns the name of the `index`-th rotation of `path`, such as `chat.log.1`.
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+/// Shifts every rotation of `path` up by one, dropping the oldest, and moves `path`
+/// itself to the first rotation.
+async fn rotate(path: &Path) -> io::Result<()> {
+    for index in (1..KEPT_ROTATIONS).rev() {
+        match fs::rename(rotated_path(path, index), rotated_path(path, index + 1)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    fs::rename(path, rotated_path(path, 1)).await
+}
+
+/// Tests for the logging module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an empty scratch directory unique to `name`.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("chat-log-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Parses every line of the file at `path`.
+    fn read_entries(path: &Path) -> Vec<Value> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_entries_are_timestamped_json_lines() {
+        let dir = scratch_dir("entries");
+        let path = dir.join("chat.log");
+        let logger = Logger::open(&path, 1 << 20).await.unwrap();
+
+        logger.log("server started").await.unwrap();
+        logger
+            .log_event(&ServerEvent::MessageBroadcast {
+                from: 1,
+                text: "hi".to_string(),
+            })
+            .await
+            .unwrap();
+
+        let entries = read_entries(&path);
+        assert_eq!(entries.len(), 2);
+        assert!(entries
+            .iter()
+            .all(|entry| entry["ts"].as_u64().unwrap() > 0));
+        assert_eq!(entries[0]["message"], "server started");
+        assert_eq!(entries[1]["event"], "message_broadcast");
+        assert_eq!(entries[1]["from"], 1);
+        assert_eq!(entries[1]["text"], "hi");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_log_is_rotated_when_full() {
+        let dir = scratch_dir("rotation");
+        let path = dir.join("chat.log");
+        // Each entry is about 40 bytes, so every file holds two of them
+        let logger = Logger::open(&path, 100).await.unwrap();
+
+        for i in 0..(2 * KEPT_ROTATIONS + 4) {
+            logger.log(&format!("entry {:02}", i)).await.unwrap();
+        }
+
+        // The newest entries are in the active file, older ones shifted up by one
+        let messages = |path: &Path| -> Vec<String> {
+            read_entries(path)
+                .iter()
+                .map(|entry| entry["message"].as_str().unwrap().to_string())
+                .collect()
+        };
+        let last = 2 * KEPT_ROTATIONS + 3;
+        assert_eq!(
+            messages(&path),
+            [
+                format!("entry {:02}", last - 1),
+                format!("entry {:02}", last)
+            ]
+        );
+        assert_eq!(
+            messages(&rotated_path(&path, 1)),
+            [
+                format!("entry {:02}", last - 3),
+                format!("entry {:02}", last - 2)
+            ]
+        );
+        assert!(rotated_path(&path, KEPT_ROTATIONS).exists());
+        assert!(!rotated_path(&path, KEPT_ROTATIONS + 1).exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotated_path() {
+        assert_eq!(
+            rotated_path(Path::new("logs/chat.log"), 2),
+            PathBuf::from("logs/chat.log.2")
+        );
+    }
+}
@@ -1,37 +1,478 @@
 //! A simple P2P chat application with a server and multiple clients.
 //! The server broadcasts messages to all connected clients, and each client displays messages
-//! from others, tagging its own messages with "(Me)".
+//! from others, showing its own messages as "You: ...".
 
-mod client;
-mod server;
+use chat_app::client::{self, ClientOptions};
+use chat_app::config::ConfigFile;
+use chat_app::error::ChatError;
+use chat_app::proxy::ProxyConfig;
+use chat_app::server::{shutdown_signal, ChatServer};
+use chat_app::settings::{self, ImportPolicy};
+use chat_app::users::{self, DEFAULT_USERS_FILE};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::Duration,
+};
+use tracing_subscriber::EnvFilter;
 
-use std::env;
+/// Returns the value that follows `flag` on the command line, if any.
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|index| args.get(index + 1))
+        .map(String::as_str)
+}
+
+/// Returns every value given for a flag that may be repeated, in order.
+fn flag_values<'a>(args: &'a [String], flag: &str) -> Vec<&'a str> {
+    args.windows(2)
+        .filter(|pair| pair[0] == flag)
+        .map(|pair| pair[1].as_str())
+        .collect()
+}
+
+/// Explains `error` to the person at the terminal, with a hint for each kind of failure.
+fn describe(error: &ChatError) -> String {
+    match error {
+        ChatError::Io(e) => format!("Network error: {}", e),
+        ChatError::Protocol(what) => format!(
+            "Protocol error: {}. Is the address a chat server speaking this version?",
+            what
+        ),
+        ChatError::Auth(why) => {
+            format!("Login refused: {}. Check your username and password.", why)
+        }
+        ChatError::Config(what) => format!("Invalid configuration: {}", what),
+    }
+}
+
+/// Flags that stand alone instead of taking a value.
+const SWITCHES: &[&str] = &[
+    "--require-nick",
+    "--no-reconnect",
+    "--no-nodelay",
+    "--no-timestamps",
+    "--no-color",
+    "--overwrite",
+    "--merge",
+];
+
+/// Returns the arguments after the mode, skipping over `--flag value` pairs and switches.
+fn positional_args(args: &[String]) -> Vec<&str> {
+    let mut positional = Vec::new();
+    let mut rest = args.iter().skip(2);
+    while let Some(arg) = rest.next() {
+        if SWITCHES.contains(&arg.as_str()) {
+            continue;
+        }
+        if arg.starts_with("--") {
+            rest.next();
+        } else {
+            positional.push(arg.as_str());
+        }
+    }
+    positional
+}
+
+/// Returns the address argument, skipping over `--flag value` pairs and switches.
+fn address_arg(args: &[String]) -> Option<String> {
+    positional_args(args).first().map(|arg| arg.to_string())
+}
+
+/// Runs `client export-settings <path>` or `client import-settings <path>`, which
+/// move the client's saved state (see [`settings`]) between machines.
+fn settings_command(args: &[String], command: &str) -> ExitCode {
+    let usage = "[--overwrite | --merge] [--history-file <path>]";
+    let [_, path] = positional_args(args)[..] else {
+        eprintln!("Usage: {} client {} <path> {}", args[0], command, usage);
+        return ExitCode::FAILURE;
+    };
+    let overwrite = args.iter().any(|arg| arg == "--overwrite");
+    if overwrite && args.iter().any(|arg| arg == "--merge") {
+        eprintln!("--overwrite and --merge cannot be used together");
+        return ExitCode::FAILURE;
+    }
+    let history_file = flag_value(args, "--history-file")
+        .map(PathBuf::from)
+        .or_else(chat_app::input::default_history_file);
+    let Some(history_file) = history_file else {
+        eprintln!("No history file: set HOME or pass --history-file <path>");
+        return ExitCode::FAILURE;
+    };
+
+    let result = match command {
+        "export-settings" => settings::export_settings(Path::new(path), &history_file),
+        _ => {
+            let policy = match overwrite {
+                true => ImportPolicy::Overwrite,
+                false => ImportPolicy::Merge,
+            };
+            settings::import_settings(Path::new(path), &history_file, policy)
+        }
+    };
+    match result {
+        Ok(()) if command == "export-settings" => println!("Exported settings to {}", path),
+        Ok(()) => println!("Imported settings from {}", path),
+        Err(e) => {
+            let direction = match command {
+                "export-settings" => "export settings to",
+                _ => "import settings from",
+            };
+            eprintln!("Failed to {} {}: {}", direction, path, e);
+            return ExitCode::FAILURE;
+        }
+    }
+    ExitCode::SUCCESS
+}
 
 #[tokio::main]
-async fn main() {
-    let args: Vec<String> = env::args().collect();
+async fn main() -> ExitCode {
+    let mut args: Vec<String> = env::args().collect();
 
     if args.len() < 2 {
-        eprintln!("Usage: {} [server|client] [address]", args[0]);
-        return;
+        eprintln!(
+            "Usage: {} [server|client] [address] [--listen <address>]... [--listen-unix <path>] [--metrics <address>] [--config <path>] [--protocol text|framed-json] [--transport tcp|websocket] [--admin-password <password>] [--log-file <path>] [--max-clients <n>] [--max-per-ip <n>] [--history-db <path> | --history-size <n>] [--motd <text>] [--idle-timeout <seconds>] [--require-nick] [--users <path>] [--word-filter <path>] [--tls-cert <path> --tls-key <path>] [--no-nodelay] [--keepalive <seconds>] [--no-timestamps]\n       {} client [address | tls://address | ws://address | --unix <path>] [--tls-ca <path>] [--proxy socks5://[user:password@]ip:port] [--no-reconnect] [--max-retries <n>] [--no-nodelay] [--keepalive <seconds>] [--no-timestamps] [--no-color] [--history-file <path>]\n       {} client export-settings|import-settings <path> [--overwrite | --merge] [--history-file <path>]\n       {} --config <path>\n       {} adduser <name> <password> [--users <path>]",
+            args[0], args[0], args[0], args[0], args[0]
+        );
+        return ExitCode::FAILURE;
+    }
+
+    // Start from the config file, if any, and let flags override it
+    let config_path = flag_value(&args, "--config").map(str::to_string);
+    let mut file = match &config_path {
+        Some(path) => match ConfigFile::from_file(Path::new(path)) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("Failed to load config file {}: {}", path, describe(&e));
+                return ExitCode::FAILURE;
+            }
+        },
+        None => ConfigFile::default(),
+    };
+    // Without a mode on the command line, the config file names it
+    if args[1].starts_with("--") && config_path.is_some() {
+        args.insert(1, file.mode.clone());
     }
 
     let mode = &args[1];
     match mode.as_str() {
         "server" => {
-            let address = args
-                .get(2)
-                .map(String::from)
-                .unwrap_or_else(|| "0.0.0.0:8080".to_string());
-            server::run_server(&address).await.unwrap();
+            // Server events are logged through tracing; RUST_LOG picks what is shown,
+            // such as `debug` to include every message
+            tracing_subscriber::fmt()
+                .with_env_filter(
+                    EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+                )
+                .init();
+
+            if let Some(address) = address_arg(&args) {
+                file.address = address;
+            }
+            // Each --listen adds an address, replacing the positional one
+            let listen = flag_values(&args, "--listen");
+            if !listen.is_empty() {
+                file.address = listen.join(",");
+            }
+            if let Some(path) = flag_value(&args, "--listen-unix") {
+                file.listen_unix = Some(path.into());
+            }
+            if let Some(address) = flag_value(&args, "--metrics") {
+                file.metrics_address = Some(address.to_string());
+            }
+            if let Some(protocol) = flag_value(&args, "--protocol") {
+                file.protocol = protocol.to_string();
+            }
+            if let Some(transport) = flag_value(&args, "--transport") {
+                file.transport = transport.to_string();
+            }
+            if args.iter().any(|arg| arg == "--require-nick") {
+                file.require_nickname = true;
+            }
+            if let Some(path) = flag_value(&args, "--users") {
+                file.users_file = Some(path.into());
+            }
+            if let Some(path) = flag_value(&args, "--word-filter") {
+                file.word_filter_file = Some(path.into());
+            }
+            if let Some(password) = flag_value(&args, "--admin-password") {
+                file.admin_password = Some(password.to_string());
+            }
+            if let Some(path) = flag_value(&args, "--log-file") {
+                file.log_file = Some(path.into());
+            }
+            if let Some(path) = flag_value(&args, "--history-db") {
+                file.history_db = Some(path.into());
+            }
+            if let Some(path) = flag_value(&args, "--tls-cert") {
+                file.tls_cert = Some(path.into());
+            }
+            if let Some(path) = flag_value(&args, "--tls-key") {
+                file.tls_key = Some(path.into());
+            }
+
+            // Zero turns a limit or timeout off
+            for (flag, setting) in [
+                ("--max-clients", &mut file.max_clients),
+                ("--max-per-ip", &mut file.max_per_ip),
+                ("--history-size", &mut file.history_size),
+            ] {
+                if let Some(value) = flag_value(&args, flag) {
+                    match value.parse() {
+                        Ok(value) => *setting = value,
+                        Err(_) => {
+                            eprintln!("Invalid {} value: {}", flag, value);
+                            return ExitCode::FAILURE;
+                        }
+                    }
+                }
+            }
+            for (flag, setting) in [
+                ("--idle-timeout", &mut file.idle_timeout_secs),
+                ("--keepalive", &mut file.tcp_keepalive_secs),
+            ] {
+                if let Some(seconds) = flag_value(&args, flag) {
+                    match seconds.parse() {
+                        Ok(seconds) => *setting = seconds,
+                        Err(_) => {
+                            eprintln!("Invalid {} value: {}", flag, seconds);
+                            return ExitCode::FAILURE;
+                        }
+                    }
+                }
+            }
+            if args.iter().any(|arg| arg == "--no-nodelay") {
+                file.tcp_nodelay = false;
+            }
+            if args.iter().any(|arg| arg == "--no-timestamps") {
+                file.timestamps = false;
+            }
+
+            // The flag wins over the environment, which wins over the file; an empty
+            // MOTD means none
+            if let Some(motd) = flag_value(&args, "--motd")
+                .map(str::to_string)
+                .or_else(|| env::var("CHAT_MOTD").ok())
+            {
+                file.motd = Some(motd);
+            }
+
+            let config = match file.server_config().await {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("{}", describe(&e));
+                    return ExitCode::FAILURE;
+                }
+            };
+            let mut server = match ChatServer::bind_with_config(&file.address, config).await {
+                Ok(server) => server,
+                Err(e) => {
+                    eprintln!("Failed to listen on {}: {}", file.address, e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            if let Some(path) = &file.listen_unix {
+                #[cfg(unix)]
+                match server.with_unix_socket(path) {
+                    Ok(with_socket) => server = with_socket,
+                    Err(e) => {
+                        eprintln!("Failed to listen on {}: {}", path.display(), e);
+                        return ExitCode::FAILURE;
+                    }
+                }
+                #[cfg(not(unix))]
+                {
+                    eprintln!("Unix domain sockets are not supported on this system");
+                    return ExitCode::FAILURE;
+                }
+            }
+            if let Some(address) = &file.metrics_address {
+                match server.with_metrics(address).await {
+                    Ok(with_metrics) => server = with_metrics,
+                    Err(e) => {
+                        eprintln!("Failed to serve metrics on {}: {}", address, e);
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
+            let addrs: Vec<String> = match server.local_addrs() {
+                Ok(addrs) => addrs.iter().map(ToString::to_string).collect(),
+                Err(e) => {
+                    eprintln!("Failed to read the listening addresses: {}", e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            println!("Server listening on {}", addrs.join(", "));
+            if let Some(path) = server.unix_socket_path() {
+                println!("Server listening on {}", path.display());
+            }
+            if let Some(addr) = server.metrics_addr() {
+                println!("Serving metrics on http://{}/metrics", addr);
+            }
+
+            // Lines typed at the server's terminal are announced to every client
+            let server = Arc::new(server);
+            let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(16);
+            std::thread::spawn(move || {
+                for line in std::io::stdin().lines() {
+                    let Ok(line) = line else { break };
+                    if tx.blocking_send(line).is_err() {
+                        break;
+                    }
+                }
+            });
+            tokio::spawn({
+                let server = server.clone();
+                async move {
+                    while let Some(line) = rx.recv().await {
+                        if !line.trim().is_empty() {
+                            server.announce(line.trim()).await;
+                        }
+                    }
+                }
+            });
+
+            let result = server.run_until(shutdown_signal()).await;
+            for line in server.stats().summary() {
+                println!("{}", line);
+            }
+            if let Err(e) = result {
+                eprintln!("{}", describe(&e));
+                return ExitCode::FAILURE;
+            }
         }
         "client" => {
-            let address = args
-                .get(2)
-                .map(String::from)
+            if let Some(command @ ("export-settings" | "import-settings")) =
+                positional_args(&args).first().copied()
+            {
+                return settings_command(&args, command);
+            }
+            // A config file's address is one the server listens on, and so one to reach it at
+            let address = address_arg(&args)
+                .or_else(|| {
+                    config_path
+                        .as_ref()
+                        .map(|_| file.client_address().to_string())
+                })
                 .unwrap_or_else(|| "127.0.0.1:8080".to_string());
-            client::run_client(&address).await.unwrap();
+            let mut options = ClientOptions {
+                reconnect: !args.iter().any(|arg| arg == "--no-reconnect"),
+                ..ClientOptions::default()
+            };
+            // Zero retries forever
+            if let Some(retries) = flag_value(&args, "--max-retries") {
+                match retries.parse() {
+                    Ok(0) => options.max_retries = None,
+                    Ok(retries) => options.max_retries = Some(retries),
+                    Err(_) => {
+                        eprintln!("Invalid --max-retries value: {}", retries);
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
+            options.socket_options.nodelay = !args.iter().any(|arg| arg == "--no-nodelay");
+            options.show_timestamps = !args.iter().any(|arg| arg == "--no-timestamps");
+            options.color = !args.iter().any(|arg| arg == "--no-color");
+            if let Some(path) = flag_value(&args, "--history-file") {
+                options.history_file = Some(path.into());
+            }
+            if let Some(path) = flag_value(&args, "--tls-ca") {
+                #[cfg(feature = "tls")]
+                {
+                    options.tls_ca = Some(path.into());
+                }
+                #[cfg(not(feature = "tls"))]
+                {
+                    eprintln!("TLS support is not enabled in this build: {}", path);
+                    return ExitCode::FAILURE;
+                }
+            }
+            if let Some(url) = flag_value(&args, "--proxy") {
+                match ProxyConfig::parse(url) {
+                    Ok(proxy) => options.proxy = Some(proxy),
+                    Err(e) => {
+                        eprintln!("Invalid --proxy value: {}", e);
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
+            if let Some(path) = flag_value(&args, "--unix") {
+                #[cfg(unix)]
+                {
+                    options.unix_socket = Some(path.into());
+                }
+                #[cfg(not(unix))]
+                {
+                    eprintln!(
+                        "Unix domain sockets are not supported on this system: {}",
+                        path
+                    );
+                    return ExitCode::FAILURE;
+                }
+            }
+            // Zero sends no keepalive probes
+            if let Some(seconds) = flag_value(&args, "--keepalive") {
+                match seconds.parse() {
+                    Ok(0) => options.socket_options.keepalive = None,
+                    Ok(seconds) => {
+                        options.socket_options.keepalive = Some(Duration::from_secs(seconds))
+                    }
+                    Err(_) => {
+                        eprintln!("Invalid --keepalive value: {}", seconds);
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
+            // WebSocket URLs get their own client, which does not reconnect
+            if address.starts_with(client::WEBSOCKET_SCHEME) {
+                #[cfg(feature = "websocket")]
+                return match client::run_ws_client(&address).await {
+                    Ok(()) => ExitCode::SUCCESS,
+                    Err(e) => {
+                        eprintln!("{}", describe(&e));
+                        ExitCode::FAILURE
+                    }
+                };
+                #[cfg(not(feature = "websocket"))]
+                {
+                    eprintln!(
+                        "WebSocket support is not enabled in this build: {}",
+                        address
+                    );
+                    return ExitCode::FAILURE;
+                }
+            }
+            if let Err(e) = client::run_client_with_options(&address, options).await {
+                eprintln!("{}", describe(&e));
+                return ExitCode::FAILURE;
+            }
+        }
+        "adduser" => {
+            let [name, password] = positional_args(&args)[..] else {
+                eprintln!(
+                    "Usage: {} adduser <name> <password> [--users <path>]",
+                    args[0]
+                );
+                return ExitCode::FAILURE;
+            };
+            let path = flag_value(&args, "--users").unwrap_or(DEFAULT_USERS_FILE);
+            match users::add_user(path, name, password) {
+                Ok(()) => println!("Added user {} to {}", name, path),
+                Err(e) => {
+                    eprintln!("Failed to add user {}: {}", name, e);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        _ => {
+            eprintln!(
+                "Unknown mode: {}. Use 'server', 'client' or 'adduser'.",
+                mode
+            );
+            return ExitCode::FAILURE;
         }
-        _ => eprintln!("Unknown mode: {}. Use 'server' or 'client'.", mode),
     }
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,245 @@
+//! The metrics module exposes the server's counters to Prometheus.
+//!
+//! ## Overview
+//! A server started with [`crate::server::ChatServer::with_metrics`] listens on a
+//! second port and answers `GET /metrics` with its [`ServerStats`] in the Prometheus
+//! text exposition format. The endpoint speaks just enough HTTP/1.1 for a scraper or
+//! `curl`: it reads one request, writes one response and closes the connection, so the
+//! chat port and its clients are unaffected.
+//!
+//! ## Key Features
+//! - **Prometheus Format**: [`render`] writes each counter with its `# HELP` and
+//!   `# TYPE` lines, and rejected messages with a `reason` label per
+//!   [`RejectionReason::code`].
+//! - **Tiny HTTP**: [`serve_scrape`] answers a single request without pulling in a web
+//!   framework, and refuses request heads larger than [`MAX_REQUEST_LEN`].
+
+use crate::protocol::RejectionReason;
+use crate::server::ServerStats;
+use std::{fmt::Write as _, io, time::Duration};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The path the metrics are served on.
+pub const METRICS_PATH: &str = "/metrics";
+
+/// The largest request head, in bytes, the endpoint reads before giving up.
+pub const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+/// How long a scraper has to send its request.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The content type of the Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Renders `stats` in the Prometheus text exposition format.
+///
+/// # Example
+/// ```
+/// use chat_app::metrics::render;
+/// use chat_app::server::ServerStats;
+///
+/// let text = render(&ServerStats::default());
+/// assert!(text.contains("chat_messages_broadcast_total 0\n"));
+/// ```
+pub fn render(stats: &ServerStats) -> String {
+    let metrics: [(&str, &str, &str, String); 7] = [
+        (
+            "chat_connected_clients",
+            "gauge",
+            "Clients currently connected.",
+            stats.current_connections().to_string(),
+        ),
+        (
+            "chat_peak_connected_clients",
+            "gauge",
+            "The most clients that were connected at once.",
+            stats.peak_connections().to_string(),
+        ),
+        (
+            "chat_messages_broadcast_total",
+            "counter",
+            "Chat messages broadcast to a room.",
+            stats.broadcast_messages().to_string(),
+        ),
+        (
+            "chat_private_messages_total",
+            "counter",
+            "Private messages relayed between clients.",
+            stats.private_messages().to_string(),
+        ),
+        (
+            "chat_bytes_sent_total",
+            "counter",
+            "Bytes written to clients.",
+            stats.bytes_sent().to_string(),
+        ),
+        (
+            "chat_queue_overflows_total",
+            "counter",
+            "Messages that found a client's send queue full.",
+            stats.queue_overflows().to_string(),
+        ),
+        (
+            "chat_uptime_seconds",
+            "gauge",
+            "Seconds since the server started.",
+            stats.uptime().as_secs().to_string(),
+        ),
+    ];
+
+    let mut text = String::new();
+    for (name, kind, help, value) in metrics {
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} {}", name, kind);
+        let _ = writeln!(text, "{} {}", name, value);
+    }
+    let _ = writeln!(
+        text,
+        "# HELP chat_messages_rejected_total Messages the server refused to deliver, by reason."
+    );
+    let _ = writeln!(text, "# TYPE chat_messages_rejected_total counter");
+    for code in RejectionReason::CODES {
+        let _ = writeln!(
+            text,
+            "chat_messages_rejected_total{{reason=\"{}\"}} {}",
+            code,
+            stats.messages_rejected(code)
+        );
+    }
+    text
+}
+
+/// Answers one HTTP request on `stream`, then shuts the stream down.
+///
+/// `GET /metrics` gets the rendered `stats`; any other path gets `404 Not Found` and
+/// any other method `405 Method Not Allowed`. A request that is malformed, larger than
+/// [`MAX_REQUEST_LEN`] or slower than [`REQUEST_TIMEOUT`] gets `400 Bad Request`.
+///
+/// # Errors
+/// Returns an error if writing the response fails.
+///
+/// # Example
+/// ```
+/// use chat_app::metrics::serve_scrape;
+/// use chat_app::server::ServerStats;
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+///
+/// #[tokio::main]
+/// async fn main() -> std::io::Result<()> {
+///     let (mut scraper, endpoint) = tokio::io::duplex(4096);
+///     scraper.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").await?;
+///     serve_scrape(endpoint, &ServerStats::default()).await?;
+///
+///     let mut response = String::new();
+///     scraper.read_to_string(&mut response).await?;
+///     assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+///     Ok(())
+/// }
+/// ```
+pub async fn serve_scrape<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    stats: &ServerStats,
+) -> io::Result<()> {
+    let request_line = tokio::time::timeout(REQUEST_TIMEOUT, read_request_line(&mut stream))
+        .await
+        .ok()
+        .flatten();
+
+    let (status, body) = match request_line.as_deref().map(str::split_whitespace) {
+        Some(mut parts) => match (parts.next(), parts.next()) {
+            (Some("GET"), Some(METRICS_PATH)) => ("200 OK", render(stats)),
+            (Some("GET"), Some(_)) => ("404 Not Found", "Not found\n".to_string()),
+            (Some(_), Some(_)) => ("405 Method Not Allowed", "Method not allowed\n".to_string()),
+            _ => ("400 Bad Request", "Bad request\n".to_string()),
+        },
+        None => ("400 Bad Request", "Bad request\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        CONTENT_TYPE,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Reads a request head up to its blank line and returns its first line, or `None`
+/// if the stream ends first, the head is too large or it is not UTF-8.
+async fn read_request_line<R: AsyncRead + Unpin>(reader: &mut R) -> Option<String> {
+    let mut head = Vec::new();
+    let mut chunk = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = reader.read(&mut chunk).await.ok()?;
+        if read == 0 || head.len() + read > MAX_REQUEST_LEN {
+            return None;
+        }
+        head.extend_from_slice(&chunk[..read]);
+    }
+    let head = String::from_utf8(head).ok()?;
+    head.lines().next().map(str::to_string)
+}
+
+/// Tests for the metrics module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends `request` to the endpoint and returns the whole response.
+    async fn scrape(request: &[u8]) -> String {
+        let (mut scraper, endpoint) = tokio::io::duplex(16 * 1024);
+        scraper.write_all(request).await.unwrap();
+        serve_scrape(endpoint, &ServerStats::default())
+            .await
+            .unwrap();
+        let mut response = String::new();
+        scraper.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[test]
+    fn test_render_format() {
+        let text = render(&ServerStats::default());
+        assert!(text.starts_with(
+            "# HELP chat_connected_clients Clients currently connected.\n\
+             # TYPE chat_connected_clients gauge\n\
+             chat_connected_clients 0\n"
+        ));
+        for name in [
+            "chat_messages_broadcast_total",
+            "chat_private_messages_total",
+            "chat_bytes_sent_total",
+        ] {
+            assert!(text.contains(&format!("# TYPE {} counter\n{} 0\n", name, name)));
+        }
+        assert!(text.ends_with(
+            "# TYPE chat_messages_rejected_total counter\n\
+             chat_messages_rejected_total{reason=\"too_long\"} 0\n\
+             chat_messages_rejected_total{reason=\"rate_limited\"} 0\n\
+             chat_messages_rejected_total{reason=\"invalid_utf8\"} 0\n\
+             chat_messages_rejected_total{reason=\"muted\"} 0\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_are_served() {
+        let response = scrape(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+        assert_eq!(body, render(&ServerStats::default()));
+    }
+
+    #[tokio::test]
+    async fn test_other_requests_are_refused() {
+        let not_found = scrape(b"GET / HTTP/1.1\r\n\r\n").await;
+        assert!(not_found.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let wrong_method = scrape(b"POST /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(wrong_method.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        // The head never ends, so the reader gives up once it is too large
+        let oversized = scrape(&[b'x'; MAX_REQUEST_LEN + 1]).await;
+        assert!(oversized.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+}
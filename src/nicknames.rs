@@ -0,0 +1,310 @@
+//! The nicknames module keeps track of the names clients chat under.
+//!
+//! ## Overview
+//! A client may claim a nickname, which is then shown instead of `Client <id>` in the
+//! messages it sends and can be used to address it with `/msg`. The
+//! [`NicknameRegistry`] maps client IDs to nicknames and makes sure no two clients
+//! hold the same one. When [`crate::server::ServerConfig::require_nickname`] is set,
+//! the server asks every client for a nickname before letting it chat.
+//!
+//! ## Key Features
+//! - **Unique**: A nickname belongs to at most one client until it disconnects, and
+//!   names that differ only in case, such as `Alice` and `alice`, count as the same.
+//! - **Validated**: Nicknames are 1 to 24 ASCII letters, digits, `_` or `-` (see
+//!   [`validate_nickname`]), so they cannot carry control characters or escape
+//!   sequences, or mimic another client's `Client 1:` prefix or a server notice.
+
+use std::{collections::HashMap, fmt, sync::Arc};
+use tokio::sync::Mutex;
+
+/// The shortest nickname accepted, in characters.
+pub const MIN_NICKNAME_LEN: usize = 1;
+
+/// The longest nickname accepted, in characters.
+pub const MAX_NICKNAME_LEN: usize = 24;
+
+/// Why a name cannot be used as a nickname.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidNickname {
+    /// The name is empty.
+    Empty,
+    /// The name is longer than [`MAX_NICKNAME_LEN`] characters.
+    TooLong,
+    /// The name starts with `/`, like a command.
+    LeadingSlash,
+    /// The name contains a character outside the allowed set.
+    Character(char),
+}
+
+impl fmt::Display for InvalidNickname {
+    /// Explains the rule the name broke. A disallowed character is shown escaped, so
+    /// echoing it back cannot inject control characters.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidNickname::Empty => write!(f, "a nickname cannot be empty"),
+            InvalidNickname::TooLong => {
+                write!(f, "use at most {} characters", MAX_NICKNAME_LEN)
+            }
+            InvalidNickname::LeadingSlash => write!(f, "a nickname cannot start with '/'"),
+            InvalidNickname::Character(c) => write!(
+                f,
+                "{:?} is not allowed; use letters, digits, '_' and '-'",
+                c
+            ),
+        }
+    }
+}
+
+/// Checks that `name` may be used as a nickname.
+///
+/// Nicknames are [`MIN_NICKNAME_LEN`] to [`MAX_NICKNAME_LEN`] ASCII letters, digits,
+/// `_` or `-`. Whitespace, punctuation such as `:` and `[`, control characters and
+/// non-ASCII letters are all refused, so a nickname can neither break a line of the
+/// text protocol nor pass itself off as another client or the server.
+///
+/// # Errors
+/// Returns the first rule the name breaks.
+///
+/// # Example
+/// ```
+/// use chat_app::nicknames::{validate_nickname, InvalidNickname};
+///
+/// assert_eq!(validate_nickname("alice_99"), Ok(()));
+/// assert_eq!(validate_nickname("/quit"), Err(InvalidNickname::LeadingSlash));
+/// assert_eq!(
+///     validate_nickname("alice smith"),
+///     Err(InvalidNickname::Character(' '))
+/// );
+/// ```
+pub fn validate_nickname(name: &str) -> Result<(), InvalidNickname> {
+    if name.is_empty() {
+        return Err(InvalidNickname::Empty);
+    }
+    if name.starts_with('/') {
+        return Err(InvalidNickname::LeadingSlash);
+    }
+    if name.chars().count() > MAX_NICKNAME_LEN {
+        return Err(InvalidNickname::TooLong);
+    }
+    match name
+        .chars()
+        .find(|&c| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+    {
+        Some(c) => Err(InvalidNickname::Character(c)),
+        None => Ok(()),
+    }
+}
+
+/// Returns whether `name` may be used as a nickname (see [`validate_nickname`]).
+///
+/// # Example
+/// ```
+/// use chat_app::nicknames::is_valid_nickname;
+///
+/// assert!(is_valid_nickname("alice_99"));
+/// assert!(!is_valid_nickname(""));
+/// assert!(!is_valid_nickname("alice smith"));
+/// ```
+pub fn is_valid_nickname(name: &str) -> bool {
+    validate_nickname(name).is_ok()
+}
+
+/// Why a nickname could not be claimed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClaimError {
+    /// The name breaks the rules of [`validate_nickname`].
+    Invalid(InvalidNickname),
+    /// Another client holds the name, possibly spelled with different case.
+    Taken,
+}
+
+/// The nicknames held by connected clients.
+#[derive(Debug, Default)]
+pub struct NicknameRegistry {
+    /// The nickname of each client that has one, as the client spelled it.
+    by_id: HashMap<usize, String>,
+    /// The holder of each nickname, keyed by its lowercase form so that uniqueness
+    /// checks ignore case.
+    taken: HashMap<String, usize>,
+}
+
+impl NicknameRegistry {
+    /// Gives `name` to `client_id`, replacing any nickname it held before.
+    ///
+    /// A client may claim its own nickname again, or respell it with different case.
+    ///
+    /// # Errors
+    /// Returns [`ClaimError::Invalid`] with the broken rule if `name` is not a valid
+    /// nickname, or
+    /// [`ClaimError::Taken`] if another client holds it; the registry is then left
+    /// unchanged.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_app::nicknames::{ClaimError, NicknameRegistry};
+    ///
+    /// let mut registry = NicknameRegistry::default();
+    /// assert_eq!(registry.claim(1, "alice"), Ok(()));
+    /// assert_eq!(registry.claim(2, "Alice"), Err(ClaimError::Taken));
+    /// ```
+    pub fn claim(&mut self, client_id: usize, name: &str) -> Result<(), ClaimError> {
+        validate_nickname(name).map_err(ClaimError::Invalid)?;
+        let key = name.to_ascii_lowercase();
+        if self
+            .taken
+            .get(&key)
+            .is_some_and(|&holder| holder != client_id)
+        {
+            return Err(ClaimError::Taken);
+        }
+        self.release(client_id);
+        self.taken.insert(key, client_id);
+        self.by_id.insert(client_id, name.to_string());
+        Ok(())
+    }
+
+    /// Frees the nickname held by `client_id`, if any.
+    pub fn release(&mut self, client_id: usize) {
+        if let Some(name) = self.by_id.remove(&client_id) {
+            self.taken.remove(&name.to_ascii_lowercase());
+        }
+    }
+
+    /// Returns the nickname held by `client_id`.
+    pub fn get(&self, client_id: usize) -> Option<&str> {
+        self.by_id.get(&client_id).map(String::as_str)
+    }
+
+    /// Returns the ID of the client holding `name`, whatever its case.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.taken.get(&name.to_ascii_lowercase()).copied()
+    }
+}
+
+/// A thread-safe, shared [`NicknameRegistry`].
+pub type SharedNicknames = Arc<Mutex<NicknameRegistry>>;
+
+/// Tests for the nicknames module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nicknames_are_unique() {
+        let mut registry = NicknameRegistry::default();
+        assert_eq!(registry.claim(1, "alice"), Ok(()));
+        assert_eq!(registry.claim(2, "alice"), Err(ClaimError::Taken));
+        assert_eq!(registry.claim(2, "bob"), Ok(()));
+        assert_eq!(registry.find("alice"), Some(1));
+        assert_eq!(registry.get(2), Some("bob"));
+
+        // Claiming your own nickname again is harmless
+        assert_eq!(registry.claim(1, "alice"), Ok(()));
+
+        // A freed nickname can be claimed by someone else
+        registry.release(1);
+        assert_eq!(registry.get(1), None);
+        assert_eq!(registry.find("alice"), None);
+        assert_eq!(registry.claim(2, "alice"), Ok(()));
+        assert_eq!(registry.find("bob"), None);
+    }
+
+    #[test]
+    fn test_nicknames_ignore_case() {
+        let mut registry = NicknameRegistry::default();
+        assert_eq!(registry.claim(1, "Alice"), Ok(()));
+        assert_eq!(registry.claim(2, "alice"), Err(ClaimError::Taken));
+        assert_eq!(registry.claim(2, "ALICE"), Err(ClaimError::Taken));
+        assert_eq!(registry.find("aLiCe"), Some(1));
+
+        // The holder may respell its own nickname, which frees the old spelling
+        assert_eq!(registry.claim(1, "ALICE"), Ok(()));
+        assert_eq!(registry.get(1), Some("ALICE"));
+        registry.release(1);
+        assert_eq!(registry.claim(2, "alice"), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_nickname_is_not_claimed() {
+        let mut registry = NicknameRegistry::default();
+        assert_eq!(
+            registry.claim(1, "Client 1"),
+            Err(ClaimError::Invalid(InvalidNickname::Character(' ')))
+        );
+        assert_eq!(registry.get(1), None);
+    }
+
+    #[test]
+    fn test_nickname_rules() {
+        assert_eq!(validate_nickname("x"), Ok(()));
+        assert_eq!(validate_nickname("Rust-ace_2024"), Ok(()));
+        assert_eq!(validate_nickname(&"x".repeat(MAX_NICKNAME_LEN)), Ok(()));
+        assert_eq!(validate_nickname(""), Err(InvalidNickname::Empty));
+        assert_eq!(
+            validate_nickname(&"x".repeat(MAX_NICKNAME_LEN + 1)),
+            Err(InvalidNickname::TooLong)
+        );
+        assert_eq!(
+            validate_nickname(&"x".repeat(10 * 1024)),
+            Err(InvalidNickname::TooLong)
+        );
+        assert_eq!(
+            validate_nickname("/quit"),
+            Err(InvalidNickname::LeadingSlash)
+        );
+        // Names that could pass for a server notice or another client's prefix
+        for (name, bad) in [("[Server]", '['), ("bob:", ':'), ("Client 1", ' ')] {
+            assert_eq!(
+                validate_nickname(name),
+                Err(InvalidNickname::Character(bad))
+            );
+        }
+    }
+
+    #[test]
+    fn test_nicknames_refuse_line_breaks_and_escapes() {
+        for (name, bad) in [
+            ("alice\nClient 2: hi", '\n'),
+            ("alice\r", '\r'),
+            ("tab\tbed", '\t'),
+            ("\u{1b}[31mred", '\u{1b}'),
+            ("nul\0", '\0'),
+        ] {
+            assert_eq!(
+                validate_nickname(name),
+                Err(InvalidNickname::Character(bad))
+            );
+        }
+    }
+
+    #[test]
+    fn test_nicknames_refuse_unicode() {
+        // Accented letters, look-alike Cyrillic, emoji and invisible characters
+        for (name, bad) in [
+            ("zoë", 'ë'),
+            ("аlice", 'а'),
+            ("party🎉", '🎉'),
+            ("zero\u{200b}width", '\u{200b}'),
+            ("\u{202e}evil", '\u{202e}'),
+        ] {
+            assert_eq!(
+                validate_nickname(name),
+                Err(InvalidNickname::Character(bad))
+            );
+        }
+    }
+
+    #[test]
+    fn test_errors_never_echo_control_characters() {
+        let message = InvalidNickname::Character('\u{1b}').to_string();
+        assert_eq!(
+            message,
+            "'\\u{1b}' is not allowed; use letters, digits, '_' and '-'"
+        );
+        assert!(!message.chars().any(char::is_control));
+        assert_eq!(
+            InvalidNickname::TooLong.to_string(),
+            "use at most 24 characters"
+        );
+    }
+}
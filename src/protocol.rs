@@ -0,0 +1,880 @@
+//! The protocol module defines the messages the server sends to its clients.
+//!
+//! ## Overview
+//! Every message the server writes is described by a [`ServerMessage`]. How messages
+//! are put on the wire in both directions depends on the [`Protocol`] the server is
+//! configured with:
+//! - **Text**: newline-delimited, human-readable lines (e.g. `Client 1: hi`), suitable
+//!   for `telnet` or `nc`.
+//! - **Json**: length-prefixed frames (see [`crate::framing`]). Server messages are
+//!   versioned JSON objects (e.g. `{"v":1,"kind":"broadcast","from":1,"body":"hi"}`),
+//!   so clients never have to guess the message type from its text. Client messages
+//!   are raw UTF-8 text that may contain newlines, or a [`ClientMessage`] object when
+//!   the client wants to match rejections to the messages they refer to.
+//!
+//! Requests the server refuses are reported with a [`RejectionReason`], which carries a
+//! stable machine-readable code alongside the human-readable explanation.
+
+use crate::framing::{encode_frame, read_frame, write_frame, Frame, MAX_FRAME_LEN};
+use serde::{Deserialize, Serialize};
+use std::{fmt, io};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// The version stamped on every JSON envelope.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The start of the reply a client sends when it receives a [`ServerMessage::Ping`],
+/// followed by a space and the ping's nonce (see [`pong`]).
+pub const PONG: &str = "[Pong]";
+
+/// Builds the reply to the [`ServerMessage::Ping`] carrying `nonce`.
+///
+/// # Example
+/// ```
+/// use chat_app::protocol::{parse_pong, pong};
+///
+/// assert_eq!(pong(7), "[Pong] 7");
+/// assert_eq!(parse_pong("[Pong] 7"), Some(7));
+/// ```
+pub fn pong(nonce: u64) -> String {
+    format!("{} {}", PONG, nonce)
+}
+
+/// Returns the nonce of a [`ServerMessage::Ping`] rendered as a text protocol line, or
+/// `None` if `line` is not a ping.
+pub fn parse_ping(line: &str) -> Option<u64> {
+    line.trim_end().strip_prefix("[Ping] ")?.parse().ok()
+}
+
+/// Parses a [`ServerMessage::PublicKey`] rendered as a text protocol line, or
+/// returns `None` if `line` is not one.
+///
+/// # Example
+/// ```
+/// use chat_app::protocol::{parse_public_key_line, ServerMessage};
+///
+/// assert_eq!(
+///     parse_public_key_line("[Key bob] Client 2 has no key\n"),
+///     Some(ServerMessage::PublicKey { target: "bob".to_string(), id: Some(2), key: None })
+/// );
+/// assert_eq!(parse_public_key_line("bob: [Key bob] No such user"), None);
+/// ```
+pub fn parse_public_key_line(line: &str) -> Option<ServerMessage> {
+    let (target, rest) = line.trim_end().strip_prefix("[Key ")?.split_once("] ")?;
+    let (id, key) = match rest.strip_prefix("Client ") {
+        Some(rest) => match rest.split_once(": ") {
+            Some((id, key)) => (Some(id.parse().ok()?), Some(key.to_string())),
+            None => (Some(rest.strip_suffix(" has no key")?.parse().ok()?), None),
+        },
+        None if rest == "No such user" => (None, None),
+        None => return None,
+    };
+    Some(ServerMessage::PublicKey {
+        target: target.to_string(),
+        id,
+        key,
+    })
+}
+
+/// Returns the nonce of a reply built by [`pong`], or `None` if `message` is not one.
+pub fn parse_pong(message: &str) -> Option<u64> {
+    message
+        .trim()
+        .strip_prefix(PONG)?
+        .strip_prefix(' ')?
+        .parse()
+        .ok()
+}
+
+/// A message sent from the server to a client.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// The first message on every connection, carrying the client's assigned ID.
+    Welcome { id: usize },
+    /// A chat message sent to every client in the sender's room.
+    ///
+    /// `nick` is the sender's nickname, if it has one, and `timestamp` is when the
+    /// server sent the message, in RFC 3339 format, unless timestamps are turned off.
+    Broadcast {
+        from: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nick: Option<String>,
+        body: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<String>,
+    },
+    /// An action sent with `/me` to every client in the sender's room, rendered as
+    /// `* <sender> <body>` rather than as something the sender said.
+    Action {
+        from: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nick: Option<String>,
+        body: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<String>,
+    },
+    /// A message sent by one client to another with `/msg`.
+    Private {
+        from: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nick: Option<String>,
+        body: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<String>,
+    },
+    /// The copy of a `/msg` sent back to its author once it was delivered.
+    ///
+    /// `to` and `nick` name the recipient, not the sender.
+    PrivateSent {
+        to: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nick: Option<String>,
+        body: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<String>,
+    },
+    /// A request for the client's nickname, answered with a single line.
+    NicknamePrompt,
+    /// A request for the username of the account to log in with.
+    UsernamePrompt,
+    /// A request for the password of the account named in the previous answer.
+    PasswordPrompt,
+    /// A notice from the server itself, such as the shutdown announcement.
+    System { text: String },
+    /// An error reply sent only to the client whose request failed.
+    Error { message: String },
+    /// A message the server refused to deliver, sent only to its author.
+    ///
+    /// `code` is one of the stable [`RejectionReason::code`] values, so scripted clients
+    /// can tell rejections apart without parsing `message`. `id` is the ID of the
+    /// refused [`ClientMessage`], if it had one.
+    Rejected {
+        code: String,
+        message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    /// A heartbeat; the client must answer with [`pong`]`(nonce)` to show it is still
+    /// there. Each ping to a client carries a new nonce.
+    Ping { nonce: u64 },
+    /// A chat message from the server's history, replayed on join or by `/history`.
+    ///
+    /// `timestamp` is when the server received the message, in RFC 3339 format.
+    History {
+        from: usize,
+        body: String,
+        timestamp: String,
+    },
+    /// The answer to `/getkey <target>` or `/key`: the public key private messages
+    /// to a client are encrypted with (see [`crate::e2e`]).
+    ///
+    /// `target` is the client as the request named it. `id` is `None` if there is
+    /// no such client, and `key` is `None` if it has not registered one.
+    PublicKey {
+        target: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        key: Option<String>,
+    },
+}
+
+impl From<RejectionReason> for ServerMessage {
+    fn from(reason: RejectionReason) -> Self {
+        reason.rejecting(None)
+    }
+}
+
+/// A JSON client's message with an ID, sent as `{"id":"m1","body":"hi"}`.
+///
+/// The server echoes `id` in the [`ServerMessage::Rejected`] it sends if it refuses
+/// the message; accepted messages are handled exactly like a raw-text frame holding
+/// `body`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientMessage {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub body: String,
+}
+
+impl ClientMessage {
+    /// Reads the text of a JSON client's frame.
+    ///
+    /// A frame holding a `ClientMessage` object is unwrapped; any other text is the
+    /// body of a message without an ID.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_app::protocol::ClientMessage;
+    ///
+    /// let message = ClientMessage::parse(r#"{"id":"m1","body":"hi"}"#.to_string());
+    /// assert_eq!(message.id.as_deref(), Some("m1"));
+    /// assert_eq!(message.body, "hi");
+    /// assert_eq!(ClientMessage::parse("hi".to_string()).id, None);
+    /// ```
+    pub fn parse(text: String) -> ClientMessage {
+        if text.starts_with('{') {
+            if let Ok(message) = serde_json::from_str(&text) {
+                return message;
+            }
+        }
+        ClientMessage {
+            id: None,
+            body: text,
+        }
+    }
+}
+
+/// Why the server refused to deliver a client's message.
+///
+/// A rejection is sent as soon as the refused message is read, before anything the
+/// client sends after it is handled. JSON clients that send a [`ClientMessage`] with
+/// an ID get that ID back in the rejection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectionReason {
+    /// The message exceeded the server's maximum message size.
+    TooLong { max_bytes: usize },
+    /// The client is sending faster than the server's rate limit allows.
+    RateLimited,
+    /// The message was not valid UTF-8.
+    InvalidUtf8,
+    /// The client was muted for flooding, `seconds` is how long the mute has left,
+    /// rounded up.
+    Muted { seconds: u64 },
+}
+
+impl RejectionReason {
+    /// Every [`code`](RejectionReason::code), in declaration order.
+    pub const CODES: [&'static str; 4] = ["too_long", "rate_limited", "invalid_utf8", "muted"];
+
+    /// A stable identifier for the reason, safe to match on in scripts.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RejectionReason::TooLong { .. } => "too_long",
+            RejectionReason::RateLimited => "rate_limited",
+            RejectionReason::InvalidUtf8 => "invalid_utf8",
+            RejectionReason::Muted { .. } => "muted",
+        }
+    }
+
+    /// A human-readable explanation of the rejection.
+    pub fn message(&self) -> String {
+        match self {
+            RejectionReason::TooLong { max_bytes } => {
+                format!("Message too long (max {} bytes)", max_bytes)
+            }
+            RejectionReason::RateLimited => "Rate limited, slow down".to_string(),
+            RejectionReason::InvalidUtf8 => "Message is not valid UTF-8".to_string(),
+            RejectionReason::Muted { seconds } => {
+                format!("You are muted for {} seconds", seconds)
+            }
+        }
+    }
+
+    /// The [`ServerMessage::Rejected`] refusing the message with the given `id`.
+    pub fn rejecting(&self, id: Option<String>) -> ServerMessage {
+        ServerMessage::Rejected {
+            code: self.code().to_string(),
+            message: self.message(),
+            id,
+        }
+    }
+}
+
+impl Serialize for RejectionReason {
+    /// Serializes the reason as its [`code`](RejectionReason::code).
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+/// The versioned wrapper around a [`ServerMessage`] in the JSON protocol.
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    v: u32,
+    #[serde(flatten)]
+    message: ServerMessage,
+}
+
+impl fmt::Display for ServerMessage {
+    /// Renders the message as a line of the text protocol, without the trailing newline.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerMessage::Welcome { id } => write!(f, "Your ID: {}", id),
+            ServerMessage::Broadcast {
+                from,
+                nick,
+                body,
+                timestamp,
+            } => write!(
+                f,
+                "{}{}: {}",
+                Stamp(timestamp.as_deref()),
+                Sender(*from, nick.as_deref()),
+                body
+            ),
+            ServerMessage::Action {
+                from,
+                nick,
+                body,
+                timestamp,
+            } => write!(
+                f,
+                "{}* {} {}",
+                Stamp(timestamp.as_deref()),
+                Sender(*from, nick.as_deref()),
+                body
+            ),
+            ServerMessage::Private {
+                from,
+                nick,
+                body,
+                timestamp,
+            } => write!(
+                f,
+                "{}[Private] {}: {}",
+                Stamp(timestamp.as_deref()),
+                Sender(*from, nick.as_deref()),
+                body
+            ),
+            ServerMessage::PrivateSent {
+                to,
+                nick,
+                body,
+                timestamp,
+            } => write!(
+                f,
+                "{}[Private → {}] {}",
+                Stamp(timestamp.as_deref()),
+                Sender(*to, nick.as_deref()),
+                body
+            ),
+            ServerMessage::NicknamePrompt => write!(f, "Enter nickname: "),
+            ServerMessage::UsernamePrompt => write!(f, "Username: "),
+            ServerMessage::PasswordPrompt => write!(f, "Password: "),
+            ServerMessage::System { text } => write!(f, "{}", text),
+            ServerMessage::Error { message } => write!(f, "[Error] {}", message),
+            ServerMessage::Rejected { code, message, .. } => {
+                write!(f, "[Rejected: {}] {}", code, message)
+            }
+            ServerMessage::Ping { nonce } => write!(f, "[Ping] {}", nonce),
+            ServerMessage::History {
+                from,
+                body,
+                timestamp,
+            } => write!(f, "[History {}] Client {}: {}", timestamp, from, body),
+            ServerMessage::PublicKey { target, id, key } => match (id, key) {
+                (Some(id), Some(key)) => write!(f, "[Key {}] Client {}: {}", target, id, key),
+                (Some(id), None) => write!(f, "[Key {}] Client {} has no key", target, id),
+                (None, _) => write!(f, "[Key {}] No such user", target),
+            },
+        }
+    }
+}
+
+/// Renders the author of a message: its nickname if it has one, `Client <id>` if not.
+struct Sender<'a>(usize, Option<&'a str>);
+
+impl fmt::Display for Sender<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.1 {
+            Some(nick) => write!(f, "{}", nick),
+            None => write!(f, "Client {}", self.0),
+        }
+    }
+}
+
+/// Renders the timestamp a chat message starts with, as `[<timestamp>] `, if it has one.
+struct Stamp<'a>(Option<&'a str>);
+
+impl fmt::Display for Stamp<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(timestamp) => write!(f, "[{}] ", timestamp),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Splits the `[<timestamp>] ` a text protocol chat line starts with off the rest of
+/// it, or returns `None` as the timestamp if the line has none.
+///
+/// Only an RFC 3339 timestamp counts, so lines such as `[Private] bob: hi` are left
+/// whole.
+///
+/// # Example
+/// ```
+/// use chat_app::protocol::split_timestamp;
+///
+/// assert_eq!(
+///     split_timestamp("[2024-01-15T14:32:10Z] alice: hi"),
+///     (Some("2024-01-15T14:32:10Z"), "alice: hi")
+/// );
+/// assert_eq!(split_timestamp("[Private] bob: hi"), (None, "[Private] bob: hi"));
+/// ```
+pub fn split_timestamp(line: &str) -> (Option<&str>, &str) {
+    let split = line
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once("] "))
+        .filter(|(timestamp, _)| chrono::DateTime::parse_from_rfc3339(timestamp).is_ok());
+    match split {
+        Some((timestamp, rest)) => (Some(timestamp), rest),
+        None => (None, line),
+    }
+}
+
+impl ServerMessage {
+    /// Serializes the message as a single-line JSON envelope.
+    pub fn to_json(&self) -> String {
+        let envelope = Envelope {
+            v: PROTOCOL_VERSION,
+            message: self.clone(),
+        };
+        serde_json::to_string(&envelope).expect("server messages always serialize")
+    }
+
+    /// Parses a JSON envelope produced by [`ServerMessage::to_json`].
+    ///
+    /// # Errors
+    /// Returns an error if the line is not valid JSON, is not a known message kind,
+    /// or carries an unsupported protocol version.
+    pub fn from_json(line: &str) -> Result<Self, serde_json::Error> {
+        let envelope: Envelope = serde_json::from_str(line)?;
+        if envelope.v != PROTOCOL_VERSION {
+            return Err(serde::de::Error::custom(format!(
+                "unsupported protocol version {}",
+                envelope.v
+            )));
+        }
+        Ok(envelope.message)
+    }
+}
+
+/// The wire format the server uses for the messages it sends.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Protocol {
+    /// Plain text lines, as rendered by [`ServerMessage`]'s `Display` implementation.
+    #[default]
+    Text,
+    /// One JSON envelope per length-prefixed frame, named `framed-json` or `json`.
+    Json,
+}
+
+impl Protocol {
+    /// Encodes a server message into the bytes written to the socket.
+    pub fn encode(&self, message: &ServerMessage) -> Vec<u8> {
+        match self {
+            Protocol::Text => format!("{}\n", message).into_bytes(),
+            Protocol::Json => {
+                encode_frame(message.to_json().as_bytes()).expect("server messages fit in a frame")
+            }
+        }
+    }
+
+    /// Encodes a line of user input into the bytes a client sends to the server.
+    ///
+    /// # Errors
+    /// Returns an error if the text is too large to fit in a frame.
+    pub fn encode_input(&self, text: &str) -> std::io::Result<Vec<u8>> {
+        match self {
+            Protocol::Text => Ok(format!("{}\n", text).into_bytes()),
+            Protocol::Json => encode_frame(text.as_bytes()),
+        }
+    }
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(Protocol::Text),
+            "framed-json" | "json" => Ok(Protocol::Json),
+            other => Err(format!(
+                "Unknown protocol: {}. Use 'text' or 'framed-json'.",
+                other
+            )),
+        }
+    }
+}
+
+/// Writes `message` to `writer` as a JSON envelope in a single frame.
+///
+/// # Errors
+/// Returns an error if the write fails.
+///
+/// # Example
+/// ```
+/// use chat_app::protocol::{read_message, write_message, ServerMessage};
+///
+/// #[tokio::main]
+/// async fn main() -> std::io::Result<()> {
+///     let (mut client, mut server) = tokio::io::duplex(1024);
+///     write_message(&mut server, &ServerMessage::Welcome { id: 1 }).await?;
+///     assert_eq!(
+///         read_message(&mut client).await?,
+///         Some(ServerMessage::Welcome { id: 1 })
+///     );
+///     Ok(())
+/// }
+/// ```
+pub async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &ServerMessage,
+) -> io::Result<()> {
+    write_frame(writer, message.to_json().as_bytes()).await
+}
+
+/// Reads the next JSON envelope written by [`write_message`] from `reader`.
+///
+/// Frames longer than [`MAX_FRAME_LEN`] are skipped.
+///
+/// # Returns
+/// `Ok(None)` if the stream ended cleanly before a new message started.
+///
+/// # Errors
+/// Returns an [`io::ErrorKind::InvalidData`] error if a frame is not a valid envelope,
+/// in which case the next message can still be read, or any error from reading.
+pub async fn read_message<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> io::Result<Option<ServerMessage>> {
+    loop {
+        match read_frame(reader, MAX_FRAME_LEN).await? {
+            Some(Frame::Data(payload)) => {
+                return ServerMessage::from_json(&String::from_utf8_lossy(&payload))
+                    .map(Some)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+            Some(Frame::TooLong(_)) => continue,
+            None => return Ok(None),
+        }
+    }
+}
+
+/// Tests for the protocol module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_messages_survive_the_wire() {
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        let messages = [
+            ServerMessage::Welcome { id: 2 },
+            ServerMessage::Broadcast {
+                from: 1,
+                nick: None,
+                body: "two\nlines".to_string(),
+                timestamp: None,
+            },
+        ];
+        for message in &messages {
+            write_message(&mut server, message).await.unwrap();
+        }
+        // A frame that is not an envelope is reported without losing the stream
+        write_frame(&mut server, b"not json").await.unwrap();
+        write_message(&mut server, &ServerMessage::Ping { nonce: 9 })
+            .await
+            .unwrap();
+        drop(server);
+
+        for message in messages {
+            assert_eq!(read_message(&mut client).await.unwrap(), Some(message));
+        }
+        let error = read_message(&mut client).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            read_message(&mut client).await.unwrap(),
+            Some(ServerMessage::Ping { nonce: 9 })
+        );
+        assert_eq!(read_message(&mut client).await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let messages = vec![
+            ServerMessage::Welcome { id: 3 },
+            ServerMessage::Broadcast {
+                from: 1,
+                nick: None,
+                body: "Client 2: [Private] not really".to_string(),
+                timestamp: None,
+            },
+            ServerMessage::Private {
+                from: 2,
+                nick: Some("bob".to_string()),
+                body: "hi".to_string(),
+                timestamp: None,
+            },
+            ServerMessage::Action {
+                from: 2,
+                nick: None,
+                body: "waves".to_string(),
+                timestamp: None,
+            },
+            ServerMessage::PrivateSent {
+                to: 3,
+                nick: Some("carol".to_string()),
+                body: "psst".to_string(),
+                timestamp: Some("2024-01-15T14:32:10Z".to_string()),
+            },
+            ServerMessage::NicknamePrompt,
+            ServerMessage::UsernamePrompt,
+            ServerMessage::PasswordPrompt,
+            ServerMessage::System {
+                text: "Server shutting down".to_string(),
+            },
+            ServerMessage::Error {
+                message: "Something went wrong".to_string(),
+            },
+            RejectionReason::TooLong { max_bytes: 10 }.into(),
+            ServerMessage::Ping { nonce: 42 },
+            ServerMessage::History {
+                from: 2,
+                body: "earlier".to_string(),
+                timestamp: "2024-01-01T12:00:00Z".to_string(),
+            },
+            ServerMessage::PublicKey {
+                target: "bob".to_string(),
+                id: Some(2),
+                key: Some("ab".repeat(32)),
+            },
+            ServerMessage::PublicKey {
+                target: "7".to_string(),
+                id: None,
+                key: None,
+            },
+        ];
+
+        for message in messages {
+            let line = message.to_json();
+            assert!(!line.contains('\n'));
+            assert_eq!(ServerMessage::from_json(&line).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn test_json_envelope_shape() {
+        let message = ServerMessage::Broadcast {
+            from: 1,
+            nick: None,
+            body: "hi".to_string(),
+            timestamp: None,
+        };
+        assert_eq!(
+            message.to_json(),
+            r#"{"v":1,"kind":"broadcast","from":1,"body":"hi"}"#
+        );
+
+        let message = ServerMessage::Broadcast {
+            from: 1,
+            nick: Some("alice".to_string()),
+            body: "hi".to_string(),
+            timestamp: None,
+        };
+        assert_eq!(
+            message.to_json(),
+            r#"{"v":1,"kind":"broadcast","from":1,"nick":"alice","body":"hi"}"#
+        );
+
+        let message = ServerMessage::Private {
+            from: 1,
+            nick: None,
+            body: "hi".to_string(),
+            timestamp: Some("2024-01-15T14:32:10Z".to_string()),
+        };
+        assert_eq!(
+            message.to_json(),
+            r#"{"v":1,"kind":"private","from":1,"body":"hi","timestamp":"2024-01-15T14:32:10Z"}"#
+        );
+    }
+
+    #[test]
+    fn test_json_rejects_unknown_version() {
+        let line = r#"{"v":2,"kind":"welcome","id":1}"#;
+        assert!(ServerMessage::from_json(line).is_err());
+    }
+
+    #[test]
+    fn test_text_rendering() {
+        assert_eq!(
+            Protocol::Text.encode(&ServerMessage::Welcome { id: 1 }),
+            b"Your ID: 1\n"
+        );
+        assert_eq!(
+            Protocol::Text.encode(&ServerMessage::Private {
+                from: 1,
+                nick: None,
+                body: "Hello!".to_string(),
+                timestamp: None,
+            }),
+            b"[Private] Client 1: Hello!\n"
+        );
+        assert_eq!(
+            Protocol::Text.encode(&ServerMessage::PrivateSent {
+                to: 2,
+                nick: None,
+                body: "Hello!".to_string(),
+                timestamp: None,
+            }),
+            "[Private → Client 2] Hello!\n".as_bytes()
+        );
+        assert_eq!(
+            Protocol::Text.encode(&ServerMessage::Broadcast {
+                from: 1,
+                nick: Some("alice".to_string()),
+                body: "Hello!".to_string(),
+                timestamp: None,
+            }),
+            b"alice: Hello!\n"
+        );
+        assert_eq!(
+            Protocol::Text.encode(&ServerMessage::Broadcast {
+                from: 1,
+                nick: None,
+                body: "Hello!".to_string(),
+                timestamp: Some("2024-01-15T14:32:10Z".to_string()),
+            }),
+            b"[2024-01-15T14:32:10Z] Client 1: Hello!\n"
+        );
+        assert_eq!(
+            Protocol::Text.encode(&ServerMessage::Action {
+                from: 1,
+                nick: None,
+                body: "waves hello".to_string(),
+                timestamp: None,
+            }),
+            b"* Client 1 waves hello\n"
+        );
+        assert_eq!(
+            Protocol::Text.encode(&ServerMessage::NicknamePrompt),
+            b"Enter nickname: \n"
+        );
+    }
+
+    #[test]
+    fn test_public_key_lines_parse_back() {
+        for (id, key) in [
+            (Some(2), Some("ab".repeat(32))),
+            (Some(2), None),
+            (None, None),
+        ] {
+            let message = ServerMessage::PublicKey {
+                target: "bob".to_string(),
+                id,
+                key,
+            };
+            assert_eq!(parse_public_key_line(&message.to_string()), Some(message));
+        }
+        assert_eq!(parse_public_key_line("[Key bob] Client two: ab"), None);
+        assert_eq!(parse_public_key_line("[Keys] Client 2: ab"), None);
+    }
+
+    #[test]
+    fn test_heartbeat_nonces() {
+        let ping = ServerMessage::Ping { nonce: 12 }.to_string();
+        assert_eq!(parse_ping(&format!("{}\n", ping)), Some(12));
+        assert_eq!(parse_pong(&pong(12)), Some(12));
+
+        // Chat lines and bare or malformed replies are not heartbeats
+        assert_eq!(parse_ping("Client 1: [Ping] 12"), None);
+        assert_eq!(parse_pong(PONG), None);
+        assert_eq!(parse_pong("[Pong] twelve"), None);
+        assert_eq!(parse_pong("[Pong]12"), None);
+    }
+
+    #[test]
+    fn test_rejection_codes() {
+        let reason = RejectionReason::TooLong { max_bytes: 4096 };
+        assert_eq!(reason.code(), "too_long");
+        assert_eq!(RejectionReason::RateLimited.code(), "rate_limited");
+        assert_eq!(RejectionReason::InvalidUtf8.code(), "invalid_utf8");
+        let muted = RejectionReason::Muted { seconds: 30 };
+        assert_eq!(muted.code(), "muted");
+        for (index, reason) in [
+            reason,
+            RejectionReason::RateLimited,
+            RejectionReason::InvalidUtf8,
+            muted,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            assert_eq!(RejectionReason::CODES[index], reason.code());
+        }
+        assert_eq!(
+            ServerMessage::from(muted).to_string(),
+            "[Rejected: muted] You are muted for 30 seconds"
+        );
+
+        // Legacy text clients get the code and the explanation on one line
+        let message = ServerMessage::from(reason);
+        assert_eq!(
+            message.to_string(),
+            "[Rejected: too_long] Message too long (max 4096 bytes)"
+        );
+
+        // JSON clients get the code as its own field, and the message's ID if it had one
+        assert_eq!(
+            message.to_json(),
+            r#"{"v":1,"kind":"rejected","code":"too_long","message":"Message too long (max 4096 bytes)"}"#
+        );
+        let message = RejectionReason::RateLimited.rejecting(Some("m1".to_string()));
+        assert_eq!(
+            message.to_json(),
+            r#"{"v":1,"kind":"rejected","code":"rate_limited","message":"Rate limited, slow down","id":"m1"}"#
+        );
+        assert_eq!(
+            message.to_string(),
+            "[Rejected: rate_limited] Rate limited, slow down"
+        );
+    }
+
+    #[test]
+    fn test_client_message_parse() {
+        let message = ClientMessage::parse(r#"{"id":"m1","body":"line one\nline two"}"#.into());
+        assert_eq!(message.id.as_deref(), Some("m1"));
+        assert_eq!(message.body, "line one\nline two");
+
+        let message = ClientMessage::parse(r#"{"body":"hi"}"#.into());
+        assert_eq!(message.id, None);
+        assert_eq!(message.body, "hi");
+
+        // Anything that is not exactly a client message object is raw text
+        for text in [
+            "hi",
+            "{not json",
+            r#"{"id":"m1"}"#,
+            r#"{"body":"hi","to":2}"#,
+        ] {
+            let message = ClientMessage::parse(text.to_string());
+            assert_eq!((message.id, message.body.as_str()), (None, text));
+        }
+    }
+
+    #[test]
+    fn test_protocol_names() {
+        assert_eq!("text".parse(), Ok(Protocol::Text));
+        assert_eq!("framed-json".parse(), Ok(Protocol::Json));
+        assert_eq!("json".parse(), Ok(Protocol::Json));
+        assert!("xml"
+            .parse::<Protocol>()
+            .unwrap_err()
+            .contains("framed-json"));
+    }
+
+    #[test]
+    fn test_json_messages_are_framed() {
+        let message = ServerMessage::Welcome { id: 1 };
+        let encoded = Protocol::Json.encode(&message);
+        let json = message.to_json();
+        assert_eq!(&encoded[..4], (json.len() as u32).to_be_bytes());
+        assert_eq!(&encoded[4..], json.as_bytes());
+
+        // User input keeps its newlines inside a frame
+        let input = Protocol::Json.encode_input("line one\nline two").unwrap();
+        assert_eq!(&input[4..], b"line one\nline two");
+    }
+}
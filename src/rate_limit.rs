@@ -0,0 +1,88 @@
+//! The rate_limit module throttles how fast a single client may send messages.
+//!
+//! ## Overview
+//! Each connection owns a [`TokenBucket`]. Every message takes one token; tokens are
+//! refilled at a steady rate up to a maximum burst. A message that finds the bucket
+//! empty is dropped, so a client can send short bursts but not a sustained stream.
+
+use tokio::time::Instant;
+
+/// A token-bucket rate limiter for a single connection.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket that allows `burst` messages at once and refills at
+    /// `per_second` messages per second.
+    pub fn new(per_second: u32, burst: u32) -> Self {
+        Self {
+            capacity: f64::from(burst),
+            tokens: f64::from(burst),
+            refill_per_sec: f64::from(per_second),
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes a token for a message sent now.
+    ///
+    /// # Returns
+    /// `true` if the message may be sent, `false` if it must be dropped.
+    pub fn try_acquire(&mut self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    /// Takes a token for a message sent at `now`.
+    fn try_acquire_at(&mut self, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Tests for the rate_limit module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_burst_then_refill() {
+        let mut bucket = TokenBucket::new(5, 10);
+        let start = bucket.last_refill;
+
+        // The whole burst is available at once, and nothing more
+        for _ in 0..10 {
+            assert!(bucket.try_acquire_at(start));
+        }
+        assert!(!bucket.try_acquire_at(start));
+
+        // 5 tokens per second means one every 200ms
+        assert!(!bucket.try_acquire_at(start + Duration::from_millis(100)));
+        assert!(bucket.try_acquire_at(start + Duration::from_millis(200)));
+        assert!(!bucket.try_acquire_at(start + Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn test_refill_is_capped_at_burst() {
+        let mut bucket = TokenBucket::new(5, 2);
+        let later = bucket.last_refill + Duration::from_secs(60);
+
+        assert!(bucket.try_acquire_at(later));
+        assert!(bucket.try_acquire_at(later));
+        assert!(!bucket.try_acquire_at(later));
+    }
+}
@@ -0,0 +1,151 @@
+//! The rooms module splits the chat into separate conversations.
+//!
+//! ## Overview
+//! Every client is in exactly one [`Room`] at a time, starting in [`DEFAULT_ROOM`].
+//! Chat messages only reach the members of the sender's room. Clients move between
+//! rooms with `/join <room>` and return to the default room with `/leave`; a room is
+//! created when its first member joins and removed when its last member leaves.
+//!
+//! ## Key Features
+//! - **Isolation**: Messages sent in one room are never delivered to another.
+//! - **Self-Cleaning**: Empty rooms disappear, except for the default room.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+
+/// The room every client starts in and returns to with `/leave`.
+pub const DEFAULT_ROOM: &str = "general";
+
+/// The longest room name accepted by `/join`, in characters.
+pub const MAX_ROOM_NAME_LEN: usize = 32;
+
+/// A named group of clients that see each other's messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Room {
+    /// The name clients use to join the room.
+    pub name: String,
+    /// The IDs of the clients in the room.
+    pub members: HashSet<usize>,
+    /// A short description shown by `/rooms`, empty if none is set.
+    pub topic: String,
+}
+
+impl Room {
+    /// Creates an empty room without a topic.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            members: HashSet::new(),
+            topic: String::new(),
+        }
+    }
+}
+
+/// A thread-safe, shared map of rooms by name.
+pub type SharedRooms = Arc<Mutex<HashMap<String, Room>>>;
+
+/// Returns whether `name` may be used as a room name.
+///
+/// Names are 1 to [`MAX_ROOM_NAME_LEN`] ASCII letters, digits, `-` or `_`.
+///
+/// # Example
+/// ```
+/// use chat_app::rooms::is_valid_room_name;
+///
+/// assert!(is_valid_room_name("rust-help"));
+/// assert!(!is_valid_room_name("two words"));
+/// ```
+pub fn is_valid_room_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_ROOM_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Adds `client_id` to the room called `name`, creating the room if needed.
+pub fn add_member(rooms: &mut HashMap<String, Room>, name: &str, client_id: usize) {
+    rooms
+        .entry(name.to_string())
+        .or_insert_with(|| Room::new(name))
+        .members
+        .insert(client_id);
+}
+
+/// Removes `client_id` from the room called `name`.
+///
+/// The room is removed once it is empty, unless it is the [`DEFAULT_ROOM`].
+pub fn remove_member(rooms: &mut HashMap<String, Room>, name: &str, client_id: usize) {
+    if let Some(room) = rooms.get_mut(name) {
+        room.members.remove(&client_id);
+        if room.members.is_empty() && name != DEFAULT_ROOM {
+            rooms.remove(name);
+        }
+    }
+}
+
+/// Builds the `/rooms` reply: a header, then one line per room sorted by name.
+pub fn room_listing(rooms: &HashMap<String, Room>) -> Vec<String> {
+    let mut rooms: Vec<&Room> = rooms.values().collect();
+    rooms.sort_by(|a, b| a.name.cmp(&b.name));
+    std::iter::once("Rooms:".to_string())
+        .chain(rooms.into_iter().map(|room| {
+            let mut line = format!("  {} ({})", room.name, room.members.len());
+            if !room.topic.is_empty() {
+                line.push_str(" - ");
+                line.push_str(&room.topic);
+            }
+            line
+        }))
+        .collect()
+}
+
+/// Tests for the rooms module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rooms_are_created_and_removed() {
+        let mut rooms = HashMap::new();
+        add_member(&mut rooms, DEFAULT_ROOM, 1);
+        add_member(&mut rooms, "rust", 1);
+        add_member(&mut rooms, "rust", 2);
+        assert_eq!(rooms["rust"].members, HashSet::from([1, 2]));
+
+        remove_member(&mut rooms, "rust", 1);
+        assert!(rooms.contains_key("rust"));
+        remove_member(&mut rooms, "rust", 2);
+        assert!(!rooms.contains_key("rust"));
+
+        // The default room stays even when empty
+        remove_member(&mut rooms, DEFAULT_ROOM, 1);
+        assert!(rooms[DEFAULT_ROOM].members.is_empty());
+    }
+
+    #[test]
+    fn test_room_listing() {
+        let mut rooms = HashMap::new();
+        add_member(&mut rooms, "rust", 3);
+        add_member(&mut rooms, DEFAULT_ROOM, 1);
+        add_member(&mut rooms, DEFAULT_ROOM, 2);
+        rooms.get_mut("rust").unwrap().topic = "All things Rust".to_string();
+
+        assert_eq!(
+            room_listing(&rooms),
+            ["Rooms:", "  general (2)", "  rust (1) - All things Rust"]
+        );
+    }
+
+    #[test]
+    fn test_room_names() {
+        assert!(is_valid_room_name("general"));
+        assert!(is_valid_room_name("Room_2"));
+        assert!(!is_valid_room_name(""));
+        assert!(!is_valid_room_name("#rust"));
+        assert!(!is_valid_room_name(&"a".repeat(MAX_ROOM_NAME_LEN + 1)));
+    }
+}
@@ -8,232 +8,3562 @@
 //! - Manages disconnections and ensures the server continues functioning even if a client disconnects.
 //!
 //! ## Key Features
-//! - **Broadcast Messaging**: Messages sent by a client are broadcasted to all connected clients.
-//! - **Private Messaging**: Clients can send private messages using the `/msg <client_id> <message>` command.
+//! - **Broadcast Messaging**: Messages sent by a client are broadcasted to all clients in the same room.
+//! - **Rooms**: Clients start in the `general` room and move between rooms with `/join` and `/leave` (see [`crate::rooms`]).
+//! - **Private Messaging**: Clients can send private messages using the `/msg <client_id|nickname>[,...] <message>` command, to one client or several at once.
+//! - **Nicknames**: With [`ServerConfig::require_nickname`], each client picks a unique nickname before it can chat (see [`crate::nicknames`]).
+//! - **Accounts**: With [`ServerConfig::users`], each client logs in with a username and password before it can chat (see [`crate::users`]).
 //! - **Concurrency**: Uses Tokio's asynchronous features to handle multiple clients concurrently.
 //! - **Graceful Disconnection**: Removes disconnected clients from the shared client list without crashing the server.
+//! - **Presence**: Each client's arrival and departure is announced to its room.
+//! - **Commands**: Lines are parsed into a [`Command`]; `/help` lists every command from the [`COMMANDS`] table.
+//! - **Admin Commands**: Clients that authenticate with `/auth <password>` may use privileged commands such as `/kick`.
+//! - **Idle Timeout**: Clients that stay silent longer than the configured timeout are disconnected.
+//! - **Rate Limiting**: Each connection has a token bucket; messages beyond it are dropped.
+//! - **Flood Protection**: Clients sending too many messages in a short window are muted for a while.
+//! - **Observable**: Connections and messages are reported as [`ServerEvent`]s rather than printed.
+//! - **History**: Broadcasts can be kept in a [`MessageStore`] and replayed to clients that join or ask with `/history`.
+//! - **Metrics**: Counters can be served to Prometheus on a separate port (see [`crate::metrics`]).
+//! - **Log File**: Events can also be written to a rotating JSON log file (see [`ServerConfig::log_path`]).
+//! - **Text or JSON**: Messages are encoded with the configured [`Protocol`], either as plain text lines or JSON envelopes.
+//! - **WebSocket**: With [`Transport::WebSocket`], browsers can connect to the same port as raw TCP clients (see [`crate::websocket`]).
 
-use std::sync::Arc;
+#[cfg(feature = "websocket")]
+use crate::codec::Codec;
+use crate::command::{find_command, parse_command, usage, Command, Recipient, COMMANDS};
+use crate::error::ChatError;
+use crate::events::{log_events, EventBus, ServerEvent};
+use crate::filter::WordFilter;
+use crate::format::{chat_message, ChatLine, DefaultFormatter, MessageFormatter};
+use crate::framing::{read_frame, read_line_bounded, Frame};
+use crate::history::{ChatMessage, MessageKind, MessageStore};
+use crate::logging::{log_to_file, Logger};
+use crate::metrics::serve_scrape;
+use crate::nicknames::{ClaimError, SharedNicknames};
+use crate::protocol::{parse_pong, ClientMessage, Protocol, RejectionReason, ServerMessage};
+use crate::rate_limit::TokenBucket;
+use crate::rooms::{add_member, remove_member, room_listing, Room, SharedRooms, DEFAULT_ROOM};
+use crate::socket::SocketOptions;
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+use crate::users::UserStore;
+#[cfg(feature = "websocket")]
+use crate::websocket;
+use chrono::{SecondsFormat, Utc};
+use socket2::{Domain, Socket, Type};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::Poll,
+    time::Duration,
+};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    io::{AsyncBufRead, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter},
     net::TcpListener,
-    sync::Mutex,
+    sync::{
+        mpsc::{self, error::TrySendError},
+        watch, Mutex, RwLock,
+    },
+    task::JoinSet,
+    time::Instant,
 };
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
+use tracing::Instrument;
+
+/// An encoded message ready to be written to a socket.
+///
+/// Messages are encoded once and shared: every recipient's queue holds a clone of the
+/// same `Arc`, so broadcasting to many clients does not copy the bytes per client.
+type Payload = Arc<[u8]>;
+
+/// The queue of encoded messages waiting to be written to one client.
+///
+/// A dedicated writer task (see [`write_outgoing`]) owns the socket and drains the
+/// queue, so sending to a client never waits on its network connection. The queue
+/// holds at most [`ServerConfig::send_queue_capacity`] messages plus one slot kept
+/// free for a final notice (see [`client_queue`]); see [`broadcast_message`] for what
+/// happens when it is full.
+type ClientSender = mpsc::Sender<Payload>;
 
 /// A thread-safe, shared collection of client connections.
 ///
-/// Each client connection is represented by a `tokio::net::tcp::OwnedWriteHalf`,
-/// which allows sending messages to the client.
-type SharedClients = Arc<Mutex<Vec<tokio::net::tcp::OwnedWriteHalf>>>;
+/// Each client connection is represented by its [`ClientSender`]. Senders are keyed by
+/// client ID so a client can be addressed directly even after others have disconnected.
+/// Sending only needs a read guard; only registering and removing clients take the
+/// write guard.
+type SharedClients = Arc<RwLock<HashMap<usize, ClientSender>>>;
+
+/// Where a client connected from, as the server sees it.
+#[derive(Debug, Clone)]
+enum Peer {
+    /// A TCP connection, possibly upgraded to TLS or WebSocket, from this address.
+    Tcp(SocketAddr),
+    /// A connection on the Unix domain socket at this path.
+    Local(PathBuf),
+}
+
+impl std::fmt::Display for Peer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Peer::Tcp(addr) => write!(f, "{}", addr),
+            Peer::Local(path) => write!(f, "{} (local)", path.display()),
+        }
+    }
+}
+
+/// What the server knows about an admitted client's connection.
+#[derive(Debug, Clone)]
+struct ClientInfo {
+    /// Where the client connected from.
+    peer: Peer,
+    /// When the client joined, for the durations `/list` and `/whoami` show.
+    joined_at: Instant,
+    /// The client that last sent this one a private message, which `/reply` answers.
+    last_private_from: Option<usize>,
+    /// The public key registered with `/key`, in hex, which `/getkey` hands out.
+    public_key: Option<String>,
+    /// Whether the client's own chat messages and actions are sent back to it, as
+    /// set with `/echo`.
+    echo: bool,
+    /// Set to `true` to end the client's session (see [`disconnect_client`]); closed
+    /// once the session has cleaned up after itself.
+    disconnect: watch::Sender<bool>,
+}
+
+/// The connection details of each admitted client, and whom it would `/reply` to.
+type SharedClientInfo = Arc<std::sync::Mutex<HashMap<usize, ClientInfo>>>;
+
+/// The number of open connections from each IP address.
+type SharedIpCounts = Arc<std::sync::Mutex<HashMap<IpAddr, usize>>>;
+
+/// A connection's place in the per-address count, released when dropped.
+///
+/// The connection task owns its slot, so the count goes down however the task ends,
+/// even if it panics.
+#[derive(Debug)]
+struct IpSlot {
+    counts: SharedIpCounts,
+    ip: IpAddr,
+}
+
+impl IpSlot {
+    /// Counts a new connection from `ip`, unless `limit` connections from it are open.
+    ///
+    /// IPv4 addresses mapped into IPv6 count as their IPv4 address, so dual-stack
+    /// listeners do not give a host a second allowance.
+    fn acquire(counts: &SharedIpCounts, ip: IpAddr, limit: Option<usize>) -> Option<IpSlot> {
+        let ip = ip.to_canonical();
+        let mut map = counts.lock().unwrap();
+        let count = map.entry(ip).or_insert(0);
+        if limit.is_some_and(|limit| *count >= limit) {
+            return None;
+        }
+        *count += 1;
+        Some(IpSlot {
+            counts: counts.clone(),
+            ip,
+        })
+    }
+}
+
+impl Drop for IpSlot {
+    fn drop(&mut self) {
+        let mut map = self.counts.lock().unwrap();
+        if let Some(count) = map.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                map.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// A connection that has been accepted but not yet admitted, counted in
+/// [`ServerState::admitting`] until it is dropped.
+///
+/// Clients only enter the shared list once admitted, so without this count the
+/// connections waiting at a nickname or login prompt would slip past
+/// [`ServerConfig::max_clients`].
+#[derive(Debug)]
+struct Admission(Arc<AtomicUsize>);
+
+impl Admission {
+    /// Counts one more connection being admitted.
+    fn start(admitting: &Arc<AtomicUsize>) -> Self {
+        admitting.fetch_add(1, Ordering::Relaxed);
+        Self(admitting.clone())
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A thread-safe, shared set of the IDs of clients that have authenticated as admins.
+type SharedAdmins = Arc<Mutex<HashSet<usize>>>;
+
+/// The default number of messages that may wait to be written to a single client.
+///
+/// A client that stops reading fills its queue, and is then handled according to
+/// [`ServerConfig::slow_client_policy`] instead of piling up messages in memory or
+/// holding up other clients.
+pub const SEND_QUEUE_CAPACITY: usize = 128;
+
+/// The notice sent to a client disconnected by [`SlowClientPolicy::Disconnect`].
+pub const TOO_SLOW_NOTICE: &str = "[Server] You were too slow to keep up and were disconnected";
+
+/// What the server does with a client whose send queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SlowClientPolicy {
+    /// Drop the new message for that client only. The client stays connected and
+    /// receives later messages once its queue has drained.
+    #[default]
+    DropNewest,
+    /// Disconnect the client after queuing [`TOO_SLOW_NOTICE`].
+    Disconnect,
+}
+
+/// How clients reach the server's TCP listeners.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Transport {
+    /// Every connection speaks the [`Protocol`] directly.
+    #[default]
+    Tcp,
+    /// Connections that open with a WebSocket upgrade request are upgraded, and the
+    /// rest are served as with [`Transport::Tcp`] (see [`crate::websocket`]).
+    ///
+    /// Raw TCP clients are greeted up to [`crate::websocket::UPGRADE_WAIT`] later, and
+    /// TLS connections are never upgraded.
+    #[cfg(feature = "websocket")]
+    WebSocket,
+}
+
+impl std::str::FromStr for Transport {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "tcp" => Ok(Transport::Tcp),
+            #[cfg(feature = "websocket")]
+            "websocket" => Ok(Transport::WebSocket),
+            #[cfg(not(feature = "websocket"))]
+            "websocket" => Err("WebSocket support is not enabled in this build".to_string()),
+            other => Err(format!(
+                "Unknown transport: {}. Use 'tcp' or 'websocket'.",
+                other
+            )),
+        }
+    }
+}
+
+/// Counters describing a running server.
+#[derive(Debug)]
+pub struct ServerStats {
+    started_at: Instant,
+    total_messages: AtomicU64,
+    broadcast_messages: AtomicU64,
+    private_messages: AtomicU64,
+    bytes_sent: AtomicU64,
+    current_connections: AtomicUsize,
+    peak_connections: AtomicUsize,
+    queue_overflows: AtomicU64,
+    messages_rejected: [AtomicU64; RejectionReason::CODES.len()],
+}
+
+impl Default for ServerStats {
+    fn default() -> Self {
+        Self {
+            started_at: Instant::now(),
+            total_messages: AtomicU64::new(0),
+            broadcast_messages: AtomicU64::new(0),
+            private_messages: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            current_connections: AtomicUsize::new(0),
+            peak_connections: AtomicUsize::new(0),
+            queue_overflows: AtomicU64::new(0),
+            messages_rejected: Default::default(),
+        }
+    }
+}
+
+impl ServerStats {
+    /// How long ago the server was created.
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// The number of chat messages clients have sent, broadcast or private.
+    pub fn total_messages(&self) -> u64 {
+        self.total_messages.load(Ordering::Relaxed)
+    }
+
+    /// The number of chat messages broadcast to a room.
+    pub fn broadcast_messages(&self) -> u64 {
+        self.broadcast_messages.load(Ordering::Relaxed)
+    }
+
+    /// The number of private messages relayed with `/msg`.
+    pub fn private_messages(&self) -> u64 {
+        self.private_messages.load(Ordering::Relaxed)
+    }
+
+    /// The number of bytes written to clients, including greetings and notices.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// The number of clients admitted and still connected.
+    pub fn current_connections(&self) -> usize {
+        self.current_connections.load(Ordering::Relaxed)
+    }
+
+    /// The most clients that were connected at once.
+    pub fn peak_connections(&self) -> usize {
+        self.peak_connections.load(Ordering::Relaxed)
+    }
+
+    /// The number of times a message found a client's send queue full, whether it
+    /// was dropped or the client was disconnected.
+    pub fn queue_overflows(&self) -> u64 {
+        self.queue_overflows.load(Ordering::Relaxed)
+    }
+
+    /// The number of messages rejected with the given [`RejectionReason::code`], or 0
+    /// for an unknown code.
+    pub fn messages_rejected(&self, code: &str) -> u64 {
+        RejectionReason::CODES
+            .iter()
+            .position(|known| *known == code)
+            .map_or(0, |index| {
+                self.messages_rejected[index].load(Ordering::Relaxed)
+            })
+    }
+
+    /// Renders the counters as the lines of the `/stats` reply.
+    pub fn summary(&self) -> Vec<String> {
+        vec![
+            "Server stats:".to_string(),
+            format!("  Uptime: {}", format_uptime(self.uptime())),
+            format!("  Messages: {}", self.total_messages()),
+            format!(
+                "  Connections: {} (peak {})",
+                self.current_connections(),
+                self.peak_connections()
+            ),
+            format!("  Queue overflows: {}", self.queue_overflows()),
+            format!(
+                "  Rejected: {}",
+                RejectionReason::CODES
+                    .map(|code| format!("{} {}", code, self.messages_rejected(code)))
+                    .join(", ")
+            ),
+        ]
+    }
+
+    /// Counts a newly admitted client.
+    fn client_joined(&self) {
+        let current = self.current_connections.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak_connections.fetch_max(current, Ordering::Relaxed);
+    }
+
+    /// Counts a client that left.
+    fn client_left(&self) {
+        self.current_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Counts a chat message broadcast to a room.
+    fn message_broadcast(&self) {
+        self.total_messages.fetch_add(1, Ordering::Relaxed);
+        self.broadcast_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a private message.
+    fn private_message_sent(&self) {
+        self.total_messages.fetch_add(1, Ordering::Relaxed);
+        self.private_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a message rejected for `reason`.
+    fn message_rejected(&self, reason: RejectionReason) {
+        if let Some(index) = RejectionReason::CODES
+            .iter()
+            .position(|code| *code == reason.code())
+        {
+            self.messages_rejected[index].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts bytes written to a client.
+    fn bytes_written(&self, count: usize) {
+        self.bytes_sent.fetch_add(count as u64, Ordering::Relaxed);
+    }
+}
+
+/// Renders a duration in whole seconds, such as `3s`, `2m 5s` or `1d 0h 2m 5s`.
+fn format_uptime(uptime: Duration) -> String {
+    let seconds = uptime.as_secs();
+    let (days, hours, minutes) = (seconds / 86_400, seconds / 3600 % 24, seconds / 60 % 60);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{}s", seconds),
+        (0, 0, _) => format!("{}m {}s", minutes, seconds % 60),
+        (0, _, _) => format!("{}h {}m {}s", hours, minutes, seconds % 60),
+        _ => format!("{}d {}h {}m {}s", days, hours, minutes, seconds % 60),
+    }
+}
+
+/// The number of wrong `/auth` attempts after which a client is disconnected.
+pub const MAX_AUTH_FAILURES: usize = 3;
+
+/// How long a client has to complete the TLS handshake.
+#[cfg(feature = "tls")]
+pub const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The number of nicknames a client may try before it is disconnected.
+pub const MAX_NICKNAME_ATTEMPTS: usize = 3;
+
+/// The start of the error sent to a client that picked an invalid nickname, which
+/// goes on to say which rule it broke.
+pub const NICKNAME_REJECTED: &str = "Invalid nickname";
+
+/// The error sent to a client that picked a nickname another client holds, compared
+/// without regard to case.
+pub const NICKNAME_IN_USE: &str = "Nickname already in use";
+
+/// The number of failed logins after which a client is disconnected.
+pub const MAX_LOGIN_ATTEMPTS: usize = 3;
+
+/// The error sent to a client whose login failed, whatever the reason.
+pub const LOGIN_FAILED: &str = "Invalid username or password";
+
+/// The notice sent to a client before it is disconnected for failing to log in
+/// [`MAX_LOGIN_ATTEMPTS`] times.
+pub const AUTHENTICATION_FAILED_NOTICE: &str = "Authentication failed";
+
+/// The notice sent to a client before it is disconnected for logging in to an account
+/// that is already logged in, under [`DuplicateLoginPolicy::Reject`].
+pub const ALREADY_LOGGED_IN_NOTICE: &str = "Already logged in elsewhere";
+
+/// The notice sent to a client before it is disconnected because its account logged
+/// in again, under [`DuplicateLoginPolicy::ReplaceOld`].
+pub const LOGGED_IN_ELSEWHERE_NOTICE: &str =
+    "[Server] Your account logged in from another connection";
+
+/// What the server does when an account that is already logged in logs in again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateLoginPolicy {
+    /// Disconnect the new connection after sending it [`ALREADY_LOGGED_IN_NOTICE`].
+    #[default]
+    Reject,
+    /// Disconnect the old connection after sending it [`LOGGED_IN_ELSEWHERE_NOTICE`],
+    /// and let the new one in under the username once the old one has left.
+    ReplaceOld,
+}
+
+impl std::str::FromStr for DuplicateLoginPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "reject" => Ok(DuplicateLoginPolicy::Reject),
+            "replace" => Ok(DuplicateLoginPolicy::ReplaceOld),
+            other => Err(format!(
+                "Unknown duplicate login policy: {}. Use 'reject' or 'replace'.",
+                other
+            )),
+        }
+    }
+}
+
+/// Tunable server settings.
+///
+/// Use [`ServerConfig::default`] for the standard limits and override individual
+/// fields as needed.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// The maximum size of a single message in bytes, excluding the line terminator.
+    /// Longer messages are rejected and never broadcast.
+    pub max_message_bytes: usize,
+    /// The wire format used for every message the server sends.
+    pub protocol: Protocol,
+    /// Whether connections may also arrive over WebSocket.
+    pub transport: Transport,
+    /// The maximum number of clients connected at once, or `None` for no limit.
+    /// Connections still at a nickname or login prompt count as well. Connections
+    /// beyond the limit receive [`SERVER_FULL_NOTICE`] and are closed without an ID.
+    /// Defaults to 256, so a runaway script cannot exhaust the server's file descriptors.
+    pub max_clients: Option<usize>,
+    /// The maximum number of connections from a single IP address, or `None` for no
+    /// limit. Connections beyond the limit receive [`TOO_MANY_CONNECTIONS_NOTICE`] and
+    /// are closed. Defaults to 5, so one host cannot take every slot.
+    pub max_connections_per_ip: Option<usize>,
+    /// The name substituted for `{server_name}` in the MOTD.
+    pub server_name: String,
+    /// An optional message of the day sent to each client right after its ID.
+    ///
+    /// Each line is sent as its own message. The placeholders `{server_name}`,
+    /// `{client_count}` and `{client_id}` are filled in per client.
+    pub motd: Option<String>,
+    /// The steady rate, in messages per second, at which a client's send budget refills.
+    pub rate_limit_per_sec: u32,
+    /// The number of messages a client may send in one burst.
+    pub rate_limit_burst: u32,
+    /// The number of messages a client may send within `flood_window` before being muted.
+    ///
+    /// Every message counts, including those dropped by the rate limiter, so a client
+    /// that keeps sending while rate limited is eventually muted.
+    pub flood_limit: usize,
+    /// The sliding window over which `flood_limit` is enforced.
+    pub flood_window: Duration,
+    /// How long a flooding client stays muted.
+    pub mute_duration: Duration,
+    /// The password that grants admin access through `/auth`, or `None` to disable it.
+    pub admin_password: Option<String>,
+    /// How long a client may stay silent before it is disconnected, or `None` to let
+    /// clients stay silent forever. Anything the client sends counts, heartbeat
+    /// replies included.
+    pub idle_timeout: Option<Duration>,
+    /// How often to ping each client, or `None` to disable heartbeats.
+    ///
+    /// A client that leaves `heartbeat_max_missed` pings in a row without a [`pong`](crate::protocol::pong) is
+    /// disconnected at the next interval, which catches connections that died without
+    /// closing.
+    pub heartbeat_interval: Option<Duration>,
+    /// How many pings in a row a client may leave unanswered, at least 1.
+    pub heartbeat_max_missed: u32,
+    /// How long outgoing messages may wait to be batched with later ones before they
+    /// are flushed to the socket. Zero flushes as soon as a client's queue is empty.
+    pub flush_delay: Duration,
+    /// How many messages may wait to be written to a single client.
+    pub send_queue_capacity: usize,
+    /// What happens to a client whose send queue is full.
+    pub slow_client_policy: SlowClientPolicy,
+    /// Where to write a JSON line for every [`ServerEvent`], or `None` to not log to a file.
+    pub log_path: Option<PathBuf>,
+    /// The size in bytes at which the log file is rotated (see [`Logger`]).
+    pub log_max_bytes: u64,
+    /// Where broadcast messages are kept, or `None` to keep no history.
+    ///
+    /// With a store, recent messages are replayed to every client that joins, and
+    /// clients can ask for more with `/history <n>`.
+    pub message_store: Option<Arc<dyn MessageStore>>,
+    /// How broadcast and private messages read on the text protocol.
+    pub formatter: Arc<dyn MessageFormatter>,
+    /// Whether chat messages carry the time the server sent them.
+    ///
+    /// On the text protocol the line starts with `[2024-01-15T14:32:10Z] `, ahead of
+    /// what the [`ServerConfig::formatter`] renders; JSON messages get a `timestamp`.
+    pub timestamps: bool,
+    /// How many of the most recent messages are replayed to a client when it joins.
+    pub history_on_join: usize,
+    /// Whether clients must pick a nickname before they can chat.
+    ///
+    /// The server then asks each new client for a nickname before its ID, and
+    /// disconnects it after [`MAX_NICKNAME_ATTEMPTS`] invalid or taken ones.
+    pub require_nickname: bool,
+    /// The accounts clients must log in with, or `None` to let anyone in.
+    ///
+    /// The server then asks each new client for a username and password before its
+    /// ID, and disconnects it after [`MAX_LOGIN_ATTEMPTS`] failed logins. An empty
+    /// store puts the server in guest mode, where clients pick any free nickname as
+    /// with [`ServerConfig::require_nickname`].
+    pub users: Option<Arc<UserStore>>,
+    /// What happens when an account that is already logged in logs in again.
+    pub duplicate_login: DuplicateLoginPolicy,
+    /// The words masked with asterisks in broadcasts and actions, which admins can
+    /// change with `/filter`. Empty by default, which lets everything through.
+    pub word_filter: WordFilter,
+    /// The TCP options set on every accepted connection.
+    pub socket_options: SocketOptions,
+    /// The certificate and key to encrypt every connection with, or `None` to accept
+    /// plaintext connections (see [`crate::tls::load_config`]).
+    ///
+    /// Connections turned away because the server or their address is full are closed
+    /// without a notice, since it could only be sent after a handshake.
+    #[cfg(feature = "tls")]
+    pub tls: Option<Arc<TlsConfig>>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_message_bytes: 4096,
+            protocol: Protocol::Text,
+            transport: Transport::Tcp,
+            max_clients: Some(256),
+            max_connections_per_ip: Some(5),
+            server_name: "Rust ChatApp".to_string(),
+            motd: None,
+            rate_limit_per_sec: 5,
+            rate_limit_burst: 10,
+            flood_limit: 30,
+            flood_window: Duration::from_secs(5),
+            mute_duration: Duration::from_secs(30),
+            admin_password: None,
+            idle_timeout: Some(Duration::from_secs(5 * 60)),
+            heartbeat_interval: Some(Duration::from_secs(30)),
+            heartbeat_max_missed: 1,
+            flush_delay: Duration::from_millis(1),
+            send_queue_capacity: SEND_QUEUE_CAPACITY,
+            slow_client_policy: SlowClientPolicy::default(),
+            log_path: None,
+            log_max_bytes: 10 * 1024 * 1024,
+            message_store: None,
+            formatter: Arc::new(DefaultFormatter),
+            timestamps: true,
+            history_on_join: 50,
+            require_nickname: false,
+            users: None,
+            duplicate_login: DuplicateLoginPolicy::default(),
+            word_filter: WordFilter::default(),
+            socket_options: SocketOptions::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+/// The prefix of the notices the server sends on its own account, such as
+/// [`ChatServer::announce`]ments. No nickname can contain it.
+pub const ANNOUNCEMENT_PREFIX: &str = "[Server]";
+
+/// The notice broadcast to every client right before the server stops.
+pub const SHUTDOWN_NOTICE: &str = "[Server] Server is shutting down";
+
+/// The notice sent to a connection that arrives while the server is full.
+pub const SERVER_FULL_NOTICE: &str = "Server full, try again later";
+
+/// The notice sent to a connection from an address that already has too many.
+pub const TOO_MANY_CONNECTIONS_NOTICE: &str =
+    "Too many connections from your address, try again later";
 
 /// Starts the server and listens for incoming connections.
 ///
 /// This function initializes the server, binds to the provided address,
 /// and waits for client connections. For each connected client, it spawns
-/// a new task to handle the connection.
+/// a new task to handle the connection. The server runs until Ctrl-C is
+/// received, at which point it shuts down gracefully (see [`ChatServer::run`]).
 ///
 /// # Arguments
 /// - `address`: A string slice representing the IP address and port to bind to (e.g., `"127.0.0.1:8080"`).
+///   Several addresses separated by commas (e.g., `"0.0.0.0:8080,[::]:8080"`) are all
+///   listened on; see [`ChatServer::bind`].
 ///
 /// # Errors
 /// Returns an error if the server fails to bind to the address.
 ///
 /// # Example
 /// ```no_run
-/// use my_server::run_server;
+/// use chat_app::server::run_server;
 ///
 /// #[tokio::main]
 /// async fn main() {
 ///     run_server("127.0.0.1:8080").await.unwrap();
 /// }
 /// ```
-pub async fn run_server(address: &str) -> std::io::Result<()> {
-    let listener = TcpListener::bind(address).await?;
-    println!("Server listening on {}", address);
+pub async fn run_server(address: &str) -> Result<(), ChatError> {
+    run_server_with_config(address, ServerConfig::default()).await
+}
 
-    let clients: SharedClients = Arc::new(Mutex::new(Vec::new()));
-    let mut client_id = 1;
+/// Starts the server with custom settings and listens for incoming connections.
+///
+/// Behaves like [`run_server`], but applies the given [`ServerConfig`] instead of the defaults.
+///
+/// # Arguments
+/// - `address`: The IP address and port to bind to (e.g., `"127.0.0.1:8080"`).
+/// - `config`: The settings applied to every connection.
+///
+/// # Errors
+/// Returns an error if the server fails to bind to the address.
+pub async fn run_server_with_config(address: &str, config: ServerConfig) -> Result<(), ChatError> {
+    let server = ChatServer::bind_with_config(address, config).await?;
+    println!("Server listening on {}", server.local_addr()?);
 
-    loop {
-        let (socket, addr) = listener.accept().await?;
-        println!("New connection: {} (Client {})", addr, client_id);
+    let logger = tokio::spawn(log_events(server.subscribe()));
+    let result = server.run_until(shutdown_signal()).await;
 
-        let (reader, mut writer) = socket.into_split();
-        let clients = clients.clone();
+    // Dropping the server ends the event stream once the log has caught up
+    drop(server);
+    let _ = logger.await;
+    result
+}
+
+/// Completes when the process receives Ctrl-C.
+///
+/// Pass it to [`ChatServer::run_until`] to stop the server from the terminal.
+pub async fn shutdown_signal() {
+    if tokio::signal::ctrl_c().await.is_err() {
+        // Without a signal handler there is nothing to wait for; keep serving.
+        std::future::pending::<()>().await;
+    }
+}
 
-        let current_id = client_id;
-        client_id += 1;
+/// Accepts connections on an already bound listener until `shutdown` completes.
+///
+/// A shorthand for [`ChatServer::from_listener`] followed by [`ChatServer::run_until`].
+///
+/// # Arguments
+/// - `listener`: A bound `TcpListener` to accept connections from.
+/// - `config`: The limits applied to every connection.
+/// - `shutdown`: A future that triggers the graceful shutdown when it completes.
+///
+/// # Errors
+/// Returns an error if accepting a connection fails.
+///
+/// # Example
+/// ```no_run
+/// use chat_app::server::{serve_with_shutdown, ServerConfig};
+/// use tokio::net::TcpListener;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+///     serve_with_shutdown(listener, ServerConfig::default(), async {
+///         tokio::signal::ctrl_c().await.unwrap();
+///     })
+///     .await
+///     .unwrap();
+/// }
+/// ```
+pub async fn serve_with_shutdown(
+    listener: TcpListener,
+    config: ServerConfig,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), ChatError> {
+    ChatServer::from_listener(listener, config)
+        .run_until(shutdown)
+        .await
+}
 
-        writer
-            .write_all(format!("Your ID: {}\n", current_id).as_bytes())
-            .await?;
+/// A bound chat server that can be started and stopped programmatically.
+///
+/// Binding and running are separate steps, so callers can learn the actual
+/// address (useful when binding to port 0) before accepting connections. While
+/// [`ChatServer::run`] drives it, the same handle can count the clients, send them
+/// notices and shut the server down.
+/// Use [`ChatServer::builder`] to customize the server before binding it.
+///
+/// # Example
+/// ```no_run
+/// use chat_app::error::ChatError;
+/// use chat_app::server::ChatServer;
+/// use std::sync::Arc;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), ChatError> {
+///     let server = Arc::new(ChatServer::bind("127.0.0.1:0").await?);
+///     println!("Listening on {}", server.local_addr()?);
+///
+///     let running = tokio::spawn({
+///         let server = server.clone();
+///         async move { server.run().await }
+///     });
+///
+///     server.shutdown();
+///     running.await.unwrap()
+/// }
+/// ```
+pub struct ChatServer {
+    listeners: Vec<TcpListener>,
+    #[cfg(unix)]
+    unix_socket: Option<UnixSocket>,
+    metrics: Option<TcpListener>,
+    config: Arc<ServerConfig>,
+    shutdown: watch::Sender<bool>,
+    events: EventBus,
+    stats: Arc<ServerStats>,
+    /// The clients, rooms and nicknames, kept here so the handle can reach them while
+    /// [`ChatServer::run`] serves them.
+    state: ServerState,
+}
 
-        tokio::spawn(async move {
-            handle_connection(reader, writer, clients, current_id).await;
-        });
+impl ChatServer {
+    /// Returns a builder for configuring and binding a server.
+    pub fn builder() -> ChatServerBuilder {
+        ChatServerBuilder::new()
+    }
+
+    /// Binds a server with the default settings to `address`.
+    ///
+    /// `address` may list several addresses separated by commas, such as
+    /// `"0.0.0.0:8080,[::]:8080"`. The server then listens on all of them at once,
+    /// with one set of clients and IDs. An IPv6 listener whose port is also bound by
+    /// an IPv4 listener only takes IPv6 connections, so the two do not conflict.
+    ///
+    /// # Errors
+    /// Returns an error if the server fails to bind to any of the addresses.
+    pub async fn bind(address: &str) -> std::io::Result<Self> {
+        Self::bind_with_config(address, ServerConfig::default()).await
+    }
+
+    /// Binds a server with custom settings to `address`, which may list several
+    /// addresses as in [`ChatServer::bind`].
+    ///
+    /// # Errors
+    /// Returns an error if the server fails to bind to any of the addresses.
+    pub async fn bind_with_config(address: &str, config: ServerConfig) -> std::io::Result<Self> {
+        let listeners = bind_listeners(address).await?;
+        Ok(Self::from_listeners(listeners, config))
+    }
+
+    /// Wraps an already bound listener.
+    pub fn from_listener(listener: TcpListener, config: ServerConfig) -> Self {
+        Self::from_listeners(vec![listener], config)
+    }
+
+    /// Wraps already bound listeners, accepting connections from all of them.
+    ///
+    /// # Panics
+    /// Panics if `listeners` is empty.
+    pub fn from_listeners(listeners: Vec<TcpListener>, config: ServerConfig) -> Self {
+        assert!(
+            !listeners.is_empty(),
+            "a server needs at least one listener"
+        );
+        let (shutdown, _) = watch::channel(false);
+        let config = Arc::new(config);
+        let events = EventBus::default();
+        let stats = Arc::<ServerStats>::default();
+        let state = ServerState {
+            clients: SharedClients::default(),
+            admins: SharedAdmins::default(),
+            rooms: Arc::new(Mutex::new(HashMap::from([(
+                DEFAULT_ROOM.to_string(),
+                Room::new(DEFAULT_ROOM),
+            )]))),
+            nicknames: SharedNicknames::default(),
+            client_info: SharedClientInfo::default(),
+            config: config.clone(),
+            events: events.clone(),
+            stats: stats.clone(),
+            admitting: Arc::default(),
+        };
+        Self {
+            listeners,
+            #[cfg(unix)]
+            unix_socket: None,
+            metrics: None,
+            config,
+            shutdown,
+            events,
+            stats,
+            state,
+        }
+    }
+
+    /// Returns the server's counters, which are updated while it runs.
+    pub fn stats(&self) -> &ServerStats {
+        &self.stats
+    }
+
+    /// Returns how many clients have been admitted and are still connected.
+    ///
+    /// Clients still answering a nickname or login prompt are not counted.
+    pub async fn client_count(&self) -> usize {
+        self.state.clients.read().await.len()
+    }
+
+    /// Sends `text` as a server notice to every connected client, whatever its room.
+    ///
+    /// Clients that are too slow to take it are handled by the configured
+    /// [`SlowClientPolicy`], as for any broadcast.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use chat_app::server::ChatServer;
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let server = Arc::new(ChatServer::bind("127.0.0.1:8080").await.unwrap());
+    ///     tokio::spawn({
+    ///         let server = server.clone();
+    ///         async move {
+    ///             tokio::time::sleep(Duration::from_secs(3600)).await;
+    ///             server.broadcast("Restarting for maintenance in 5 minutes").await;
+    ///         }
+    ///     });
+    ///     server.run().await.unwrap();
+    /// }
+    /// ```
+    ///
+    /// See [`ChatServer::announce`] for a notice that says it comes from the server.
+    pub async fn broadcast(&self, text: impl Into<String>) {
+        let state = &self.state;
+        let notice = state
+            .config
+            .protocol
+            .encode(&ServerMessage::System { text: text.into() });
+        let overflowed = broadcast_message(
+            state.clients.clone(),
+            &state.rooms,
+            None,
+            None,
+            notice.into(),
+        )
+        .await;
+        handle_overflows(
+            &state.clients,
+            &state.config,
+            &state.events,
+            &state.stats,
+            overflowed,
+        )
+        .await;
+    }
+
+    /// Announces `text` to every connected client as `[Server] <text>` (see
+    /// [`ChatServer::broadcast`]).
+    ///
+    /// The `server` binary announces each line typed on its standard input.
+    pub async fn announce(&self, text: &str) {
+        self.broadcast(format!("{} {}", ANNOUNCEMENT_PREFIX, text))
+            .await;
+    }
+
+    /// Subscribes to the events reported by the server from now on.
+    ///
+    /// The stream ends once the server is dropped. A subscriber that falls more than
+    /// [`crate::events::EVENT_BUFFER`] events behind misses the newest ones.
+    pub fn subscribe(&self) -> mpsc::Receiver<ServerEvent> {
+        self.events.subscribe()
+    }
+
+    /// Returns the address the server is bound to, or the first one if it listens on
+    /// several.
+    ///
+    /// # Errors
+    /// Returns an error if the socket address cannot be queried.
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listeners[0].local_addr()
+    }
+
+    /// Returns every address the server is bound to, in the order they were given.
+    ///
+    /// # Errors
+    /// Returns an error if a socket address cannot be queried.
+    pub fn local_addrs(&self) -> std::io::Result<Vec<SocketAddr>> {
+        self.listeners.iter().map(TcpListener::local_addr).collect()
+    }
+
+    /// Also accepts connections on a Unix domain socket at `path`, which local bots
+    /// and tools can use instead of TCP. Access is then controlled by the permissions
+    /// of the socket file.
+    ///
+    /// A socket file left behind by a server that did not shut down cleanly is
+    /// replaced, and the file is removed again when the server is dropped. Clients on
+    /// the socket share the chat and ID space with TCP clients, but are not limited per
+    /// address and never use TLS.
+    ///
+    /// # Errors
+    /// Returns an error if another server is listening on `path`, if `path` exists and
+    /// is not a socket, or if the socket cannot be bound.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use chat_app::error::ChatError;
+    /// use chat_app::server::ChatServer;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), ChatError> {
+    ///     let server = ChatServer::bind("127.0.0.1:8080")
+    ///         .await?
+    ///         .with_unix_socket("/tmp/chat.sock")?;
+    ///     server.run().await
+    /// }
+    /// ```
+    #[cfg(unix)]
+    pub fn with_unix_socket(mut self, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        self.unix_socket = Some(UnixSocket::bind(path.as_ref())?);
+        Ok(self)
+    }
+
+    /// Returns the path of the server's Unix domain socket, if it has one.
+    pub fn unix_socket_path(&self) -> Option<&Path> {
+        #[cfg(unix)]
+        if let Some(socket) = &self.unix_socket {
+            return Some(&socket.path);
+        }
+        None
+    }
+
+    /// Waits for a connection on the server's Unix domain socket, forever if it has none.
+    async fn accept_local(&self) -> std::io::Result<LocalStream> {
+        #[cfg(unix)]
+        if let Some(socket) = &self.unix_socket {
+            return socket.listener.accept().await.map(|(stream, _)| stream);
+        }
+        std::future::pending().await
+    }
+
+    /// Also serves the server's [`ServerStats`] to Prometheus at `/metrics` on
+    /// `address`, a separate port from the chat (see [`crate::metrics`]).
+    ///
+    /// # Errors
+    /// Returns an error if the metrics port cannot be bound.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use chat_app::error::ChatError;
+    /// use chat_app::server::ChatServer;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), ChatError> {
+    ///     let server = ChatServer::bind("127.0.0.1:8080")
+    ///         .await?
+    ///         .with_metrics("127.0.0.1:9090")
+    ///         .await?;
+    ///     server.run().await
+    /// }
+    /// ```
+    pub async fn with_metrics(mut self, address: &str) -> std::io::Result<Self> {
+        self.metrics = Some(TcpListener::bind(address).await?);
+        Ok(self)
+    }
+
+    /// Returns the address the metrics endpoint is bound to, if the server has one.
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok())
+    }
+
+    /// Waits for a scraper on the metrics port, forever if there is none.
+    async fn accept_scraper(&self) -> std::io::Result<tokio::net::TcpStream> {
+        match &self.metrics {
+            Some(listener) => listener.accept().await.map(|(stream, _)| stream),
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Asks the server to shut down gracefully.
+    ///
+    /// [`ChatServer::run`] returns once every client has been notified and disconnected.
+    /// Calling this before `run` makes `run` shut down immediately.
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Accepts connections until [`ChatServer::shutdown`] is called.
+    ///
+    /// For each connected client, the server writes its ID and spawns a task to handle
+    /// the connection. On shutdown, it stops accepting new connections, broadcasts
+    /// [`SHUTDOWN_NOTICE`] to every connected client, flushes and closes each client
+    /// writer, and waits for all connection tasks to exit before returning.
+    ///
+    /// # Errors
+    /// Returns an error if accepting a connection fails, or if the configured log
+    /// file cannot be opened.
+    pub async fn run(&self) -> Result<(), ChatError> {
+        let config = &self.config;
+        let events = &self.events;
+        let state = &self.state;
+        let ip_counts = SharedIpCounts::default();
+        let mut shutdown_requested = self.shutdown.subscribe();
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let mut connections = JoinSet::new();
+        let mut client_id = 1;
+
+        // The file logger stops by itself once it has written the final event
+        let file_logger = match &config.log_path {
+            Some(path) => {
+                let logger = Logger::open(path, config.log_max_bytes).await?;
+                Some(tokio::spawn(log_to_file(logger, events.subscribe())))
+            }
+            None => None,
+        };
+
+        loop {
+            tokio::select! {
+                accepted = accept_any(&self.listeners) => {
+                    let (socket, addr) = accepted?;
+                    // A socket that refuses an option still works, just less well
+                    let _ = config.socket_options.apply(&socket);
+
+                    // Turn the connection away before assigning an ID if the server is
+                    // full or its address already has enough connections
+                    if server_full(state).await {
+                        events.emit(ServerEvent::ConnectionRefused { addr });
+                        refuse_connection(socket, config, SERVER_FULL_NOTICE).await;
+                        continue;
+                    }
+                    let Some(ip_slot) = IpSlot::acquire(&ip_counts, addr.ip(), config.max_connections_per_ip) else {
+                        events.emit(ServerEvent::TooManyConnections { addr });
+                        refuse_connection(socket, config, TOO_MANY_CONNECTIONS_NOTICE).await;
+                        continue;
+                    };
+
+                    let current_id = client_id;
+                    client_id += 1;
+                    // Everything logged about the connection, from its arrival to its
+                    // departure, is recorded inside its span
+                    let span = tracing::info_span!("connection", client_id = current_id, %addr);
+                    span.in_scope(|| events.emit(ServerEvent::ClientConnected { id: current_id, addr }));
+
+                    let admission = Admission::start(&state.admitting);
+                    let state = state.clone();
+                    let shutdown_rx = shutdown_rx.clone();
+                    connections.spawn(async move {
+                        // The address's count drops when this task ends, however it ends
+                        let _ip_slot = ip_slot;
+
+                        #[cfg(feature = "websocket")]
+                        if state.config.transport == Transport::WebSocket
+                            && websocket::is_upgrade_request(&socket).await
+                        {
+                            let handshake = tokio_tungstenite::accept_async(socket);
+                            let reason = match tokio::time::timeout(websocket::HANDSHAKE_TIMEOUT, handshake).await {
+                                Ok(Ok(upgraded)) => {
+                                    state.events.emit(ServerEvent::WebSocketUpgraded { id: current_id });
+                                    // The chat is served over an in-memory pipe that the
+                                    // bridge relays to and from the WebSocket
+                                    let (stream, far_end) = tokio::io::duplex(websocket::BRIDGE_BUFFER);
+                                    let (reader, writer) = tokio::io::split(stream);
+                                    let codec = Codec::new(state.config.protocol);
+                                    tokio::join!(
+                                        serve_connection(reader, writer, state, current_id, Peer::Tcp(addr), shutdown_rx, admission),
+                                        websocket::bridge(upgraded, far_end, codec),
+                                    );
+                                    return;
+                                }
+                                Ok(Err(e)) => e.to_string(),
+                                Err(_) => "timed out".to_string(),
+                            };
+                            state.events.emit(ServerEvent::WebSocketHandshakeFailed { id: current_id, reason });
+                            state.events.emit(ServerEvent::ClientDisconnected { id: current_id });
+                            return;
+                        }
+
+                        #[cfg(feature = "tls")]
+                        if let Some(tls) = state.config.tls.clone() {
+                            let handshake = TlsAcceptor::from(tls).accept(socket);
+                            let reason = match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, handshake).await {
+                                Ok(Ok(stream)) => {
+                                    let (reader, writer) = tokio::io::split(stream);
+                                    serve_connection(reader, writer, state, current_id, Peer::Tcp(addr), shutdown_rx, admission).await;
+                                    return;
+                                }
+                                Ok(Err(e)) => e.to_string(),
+                                Err(_) => "timed out".to_string(),
+                            };
+                            state.events.emit(ServerEvent::TlsHandshakeFailed { id: current_id, reason });
+                            state.events.emit(ServerEvent::ClientDisconnected { id: current_id });
+                            return;
+                        }
+
+                        let (reader, writer) = socket.into_split();
+                        serve_connection(reader, writer, state, current_id, Peer::Tcp(addr), shutdown_rx, admission).await;
+                    }.instrument(span));
+                }
+                accepted = self.accept_local(), if self.unix_socket_path().is_some() => {
+                    let stream = accepted?;
+                    let path = self.unix_socket_path().unwrap_or(Path::new("")).to_path_buf();
+
+                    if server_full(state).await {
+                        events.emit(ServerEvent::LocalConnectionRefused { path });
+                        send_refusal(stream, config, SERVER_FULL_NOTICE).await;
+                        continue;
+                    }
+
+                    let current_id = client_id;
+                    client_id += 1;
+                    let span = tracing::info_span!("connection", client_id = current_id, path = %path.display());
+                    let peer = Peer::Local(path.clone());
+                    span.in_scope(|| events.emit(ServerEvent::LocalClientConnected { id: current_id, path }));
+
+                    let admission = Admission::start(&state.admitting);
+                    let state = state.clone();
+                    let shutdown_rx = shutdown_rx.clone();
+                    connections.spawn(async move {
+                        let (reader, writer) = tokio::io::split(stream);
+                        serve_connection(reader, writer, state, current_id, peer, shutdown_rx, admission).await;
+                    }.instrument(span));
+                }
+                // A scrape is answered on its own task; a failed accept on the metrics
+                // port must not take the chat down with it
+                accepted = self.accept_scraper(), if self.metrics.is_some() => {
+                    if let Ok(stream) = accepted {
+                        let stats = self.stats.clone();
+                        tokio::spawn(async move {
+                            let _ = serve_scrape(stream, &stats).await;
+                        });
+                    }
+                }
+                // Reap finished connection tasks so the set does not grow unbounded
+                Some(_) = connections.join_next() => {}
+                _ = async {
+                    // Drop the borrowed value here; it must not be held across awaits
+                    let _ = shutdown_requested.wait_for(|&requested| requested).await;
+                } => break,
+            }
+        }
+
+        events.emit(ServerEvent::ShuttingDown);
+
+        // Tell the connection tasks to say goodbye and unregister, then wait for them,
+        // and for the writer tasks to drain their queues, before returning
+        let _ = shutdown_tx.send(true);
+        while connections.join_next().await.is_some() {}
+
+        events.emit(ServerEvent::Stopped);
+        if let Some(file_logger) = file_logger {
+            let _ = file_logger.await;
+        }
+        Ok(())
+    }
+
+    /// Runs the server until `shutdown` completes, then shuts it down gracefully.
+    ///
+    /// # Errors
+    /// Returns an error if accepting a connection fails.
+    pub async fn run_until(&self, shutdown: impl Future<Output = ()>) -> Result<(), ChatError> {
+        let run = self.run();
+        tokio::pin!(run);
+
+        tokio::select! {
+            result = &mut run => return result,
+            _ = shutdown => self.shutdown(),
+        }
+        run.await
+    }
+}
+
+/// Configures and binds a [`ChatServer`].
+///
+/// # Example
+/// ```no_run
+/// use chat_app::error::ChatError;
+/// use chat_app::server::ChatServer;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), ChatError> {
+///     let server = ChatServer::builder()
+///         .address("127.0.0.1:8080")
+///         .max_clients(50)
+///         .motd("Welcome to {server_name}, Client {client_id}!")
+///         .build()
+///         .await?;
+///     server.run().await
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChatServerBuilder {
+    address: String,
+    config: ServerConfig,
+    #[cfg(unix)]
+    unix_socket: Option<PathBuf>,
+    metrics_address: Option<String>,
+}
+
+impl Default for ChatServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChatServerBuilder {
+    /// Creates a builder with the default settings, bound to `0.0.0.0:8080`.
+    pub fn new() -> Self {
+        Self {
+            address: "0.0.0.0:8080".to_string(),
+            config: ServerConfig::default(),
+            #[cfg(unix)]
+            unix_socket: None,
+            metrics_address: None,
+        }
+    }
+
+    /// Sets the address to bind to (e.g., `"127.0.0.1:8080"`, or port 0 for any free port).
+    pub fn address(mut self, address: impl Into<String>) -> Self {
+        self.address = address.into();
+        self
+    }
+
+    /// Sets several addresses to listen on at once, such as a LAN interface and
+    /// localhost. Clients on every listener share one chat and one ID space.
+    pub fn addresses(mut self, addresses: impl IntoIterator<Item = SocketAddr>) -> Self {
+        let addresses: Vec<String> = addresses.into_iter().map(|a| a.to_string()).collect();
+        self.address = addresses.join(",");
+        self
+    }
+
+    /// Limits the number of clients connected at once.
+    ///
+    /// A slot is freed as soon as a connected client disconnects.
+    pub fn max_clients(mut self, max_clients: usize) -> Self {
+        self.config.max_clients = Some(max_clients);
+        self
+    }
+
+    /// Limits the number of connections from a single IP address, or lifts the limit
+    /// with `None`.
+    pub fn max_connections_per_ip(mut self, limit: Option<usize>) -> Self {
+        self.config.max_connections_per_ip = limit;
+        self
+    }
+
+    /// Sets the server name used in the MOTD.
+    pub fn server_name(mut self, server_name: impl Into<String>) -> Self {
+        self.config.server_name = server_name.into();
+        self
+    }
+
+    /// Sets the message of the day sent to each client right after its ID.
+    ///
+    /// See [`ServerConfig::motd`] for the supported placeholders.
+    pub fn motd(mut self, motd: impl Into<String>) -> Self {
+        self.config.motd = Some(motd.into());
+        self
+    }
+
+    /// Sets the maximum size of a single message in bytes.
+    pub fn max_message_bytes(mut self, max_message_bytes: usize) -> Self {
+        self.config.max_message_bytes = max_message_bytes;
+        self
+    }
+
+    /// Limits each client to `per_second` messages per second, with bursts of up to `burst`.
+    pub fn rate_limit(mut self, per_second: u32, burst: u32) -> Self {
+        self.config.rate_limit_per_sec = per_second;
+        self.config.rate_limit_burst = burst;
+        self
+    }
+
+    /// Mutes clients that send more than `limit` messages within `window`.
+    pub fn flood_limit(mut self, limit: usize, window: Duration) -> Self {
+        self.config.flood_limit = limit;
+        self.config.flood_window = window;
+        self
+    }
+
+    /// Sets how long a flooding client stays muted.
+    pub fn mute_duration(mut self, mute_duration: Duration) -> Self {
+        self.config.mute_duration = mute_duration;
+        self
+    }
+
+    /// Disconnects clients that send nothing for `idle_timeout`, or never with `None`.
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.config.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Sets how often clients are pinged, or disables heartbeats with `None`.
+    pub fn heartbeat_interval(mut self, interval: Option<Duration>) -> Self {
+        self.config.heartbeat_interval = interval;
+        self
+    }
+
+    /// Sets how many pings in a row a client may leave unanswered before it is
+    /// disconnected. Values below 1 count as 1.
+    pub fn heartbeat_max_missed(mut self, max_missed: u32) -> Self {
+        self.config.heartbeat_max_missed = max_missed.max(1);
+        self
+    }
+
+    /// Sets how long outgoing messages may wait to be batched before they are flushed.
+    pub fn flush_delay(mut self, flush_delay: Duration) -> Self {
+        self.config.flush_delay = flush_delay;
+        self
+    }
+
+    /// Sets the TCP options applied to every accepted connection.
+    pub fn socket_options(mut self, options: SocketOptions) -> Self {
+        self.config.socket_options = options;
+        self
+    }
+
+    /// Sets how many messages may wait for each client, and what happens to a client
+    /// whose queue is full.
+    pub fn send_queue(mut self, capacity: usize, policy: SlowClientPolicy) -> Self {
+        self.config.send_queue_capacity = capacity;
+        self.config.slow_client_policy = policy;
+        self
+    }
+
+    /// Logs every server event to `path`, rotating it when it reaches `max_bytes`.
+    pub fn log_file(mut self, path: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        self.config.log_path = Some(path.into());
+        self.config.log_max_bytes = max_bytes;
+        self
+    }
+
+    /// Keeps the chat history in `store` and replays it to clients that join.
+    pub fn message_store(mut self, store: impl MessageStore + 'static) -> Self {
+        self.config.message_store = Some(Arc::new(store));
+        self
+    }
+
+    /// Renders broadcast and private messages on the text protocol with `formatter`.
+    pub fn formatter(mut self, formatter: impl MessageFormatter + 'static) -> Self {
+        self.config.formatter = Arc::new(formatter);
+        self
+    }
+
+    /// Sets whether chat messages start with the time the server sent them.
+    pub fn timestamps(mut self, enabled: bool) -> Self {
+        self.config.timestamps = enabled;
+        self
+    }
+
+    /// Encrypts every connection with TLS (see [`crate::tls::load_config`]).
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: Arc<TlsConfig>) -> Self {
+        self.config.tls = Some(tls);
+        self
+    }
+
+    /// Makes every client pick a unique nickname before it can chat.
+    pub fn require_nickname(mut self) -> Self {
+        self.config.require_nickname = true;
+        self
+    }
+
+    /// Makes every client log in with one of the accounts in `users`, or pick a
+    /// nickname if there are none.
+    pub fn users(mut self, users: UserStore) -> Self {
+        self.config.users = Some(Arc::new(users));
+        self
+    }
+
+    /// Masks the words in `filter` in every broadcast and action.
+    pub fn word_filter(mut self, filter: WordFilter) -> Self {
+        self.config.word_filter = filter;
+        self
+    }
+
+    /// Sets what happens when an account that is already logged in logs in again.
+    pub fn duplicate_login(mut self, policy: DuplicateLoginPolicy) -> Self {
+        self.config.duplicate_login = policy;
+        self
+    }
+
+    /// Enables privileged commands for clients that authenticate with `password`.
+    pub fn admin_password(mut self, password: impl Into<String>) -> Self {
+        self.config.admin_password = Some(password.into());
+        self
+    }
+
+    /// Sets the wire format used for every message.
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.config.protocol = protocol;
+        self
+    }
+
+    /// Sets how clients reach the server's TCP listeners.
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.config.transport = transport;
+        self
+    }
+
+    /// Also listens on a Unix domain socket at `path` (see [`ChatServer::with_unix_socket`]).
+    #[cfg(unix)]
+    pub fn unix_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.unix_socket = Some(path.into());
+        self
+    }
+
+    /// Also serves metrics on `address` (see [`ChatServer::with_metrics`]).
+    pub fn metrics_address(mut self, address: impl Into<String>) -> Self {
+        self.metrics_address = Some(address.into());
+        self
+    }
+
+    /// Binds the server with the configured settings.
+    ///
+    /// # Errors
+    /// Returns an error if the server fails to bind to the address, to the Unix
+    /// domain socket if one is set, or to the metrics address if one is set.
+    pub async fn build(self) -> std::io::Result<ChatServer> {
+        let mut server = ChatServer::bind_with_config(&self.address, self.config).await?;
+        #[cfg(unix)]
+        if let Some(path) = self.unix_socket {
+            server = server.with_unix_socket(path)?;
+        }
+        if let Some(address) = self.metrics_address {
+            server = server.with_metrics(&address).await?;
+        }
+        Ok(server)
+    }
+}
+
+/// Binds a listener for each address in the comma-separated `address` list.
+///
+/// Host names are resolved, and each is bound to the first of its addresses that
+/// works. IPv6 listeners are made IPv6-only when the list also binds their port on
+/// IPv4; otherwise they keep the system's default, which usually accepts both.
+async fn bind_listeners(address: &str) -> std::io::Result<Vec<TcpListener>> {
+    let mut resolved = Vec::new();
+    for part in address.split(',').map(str::trim) {
+        resolved.push(tokio::net::lookup_host(part).await?.collect::<Vec<_>>());
+    }
+    let ipv4_ports: HashSet<u16> = resolved
+        .iter()
+        .flatten()
+        .filter(|addr| addr.is_ipv4() && addr.port() != 0)
+        .map(SocketAddr::port)
+        .collect();
+
+    let mut listeners = Vec::new();
+    for candidates in resolved {
+        let mut last_error = None;
+        let listener = candidates.into_iter().find_map(|addr| {
+            let only_v6 = addr.is_ipv6() && ipv4_ports.contains(&addr.port());
+            bind_listener(addr, only_v6)
+                .map_err(|e| last_error = Some(e))
+                .ok()
+        });
+        match listener {
+            Some(listener) => listeners.push(listener),
+            None => {
+                return Err(last_error.unwrap_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "could not resolve to any address",
+                    )
+                }))
+            }
+        }
+    }
+    Ok(listeners)
+}
+
+/// Binds a listening socket to `addr`, as `TcpListener::bind` would, optionally
+/// restricting an IPv6 socket to IPv6 connections.
+fn bind_listener(addr: SocketAddr, only_v6: bool) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if only_v6 {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// A connection accepted on the server's Unix domain socket.
+#[cfg(unix)]
+type LocalStream = tokio::net::UnixStream;
+
+/// Without Unix domain sockets no local connection is ever accepted, so this type is
+/// only a stand-in.
+#[cfg(not(unix))]
+type LocalStream = tokio::io::DuplexStream;
+
+/// A bound Unix domain socket whose file is removed when it is dropped.
+#[cfg(unix)]
+#[derive(Debug)]
+struct UnixSocket {
+    listener: tokio::net::UnixListener,
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixSocket {
+    /// Binds a socket at `path`, replacing a stale socket file left there.
+    fn bind(path: &Path) -> std::io::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!("a server is already listening on {}", path.display()),
+            ));
+        }
+        // Nothing answers, so a socket file here is left over from an earlier server
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(Self {
+            listener: tokio::net::UnixListener::bind(path)?,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Waits for a connection on any of `listeners`.
+async fn accept_any(
+    listeners: &[TcpListener],
+) -> std::io::Result<(tokio::net::TcpStream, SocketAddr)> {
+    std::future::poll_fn(|cx| {
+        for listener in listeners {
+            if let Poll::Ready(accepted) = listener.poll_accept(cx) {
+                return Poll::Ready(accepted);
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// Writes a single notice to a connection that is not admitted, then closes it.
+///
+/// A TLS server closes the connection without a notice, as it cannot send one before
+/// a handshake, and handshakes are only spent on admitted clients.
+async fn refuse_connection(socket: tokio::net::TcpStream, config: &ServerConfig, text: &str) {
+    #[cfg(feature = "tls")]
+    if config.tls.is_some() {
+        return;
+    }
+    send_refusal(socket, config, text).await;
+}
+
+/// Writes a single notice to a connection, then closes it.
+async fn send_refusal<S: AsyncWrite + Unpin>(mut socket: S, config: &ServerConfig, text: &str) {
+    let notice = config.protocol.encode(&ServerMessage::System {
+        text: text.to_string(),
+    });
+    let _ = socket.write_all(&notice).await;
+    let _ = socket.shutdown().await;
+}
+
+/// The state every connection task shares with the rest of the server.
+#[derive(Debug, Clone)]
+struct ServerState {
+    /// A shared collection of all connected clients.
+    clients: SharedClients,
+    /// The IDs of clients that have authenticated as admins.
+    admins: SharedAdmins,
+    /// The open rooms and their members.
+    rooms: SharedRooms,
+    /// The nicknames held by connected clients.
+    nicknames: SharedNicknames,
+    /// Where each admitted client connected from, and when it joined.
+    client_info: SharedClientInfo,
+    /// The server limits to enforce.
+    config: Arc<ServerConfig>,
+    /// Where connection activity is reported.
+    events: EventBus,
+    /// The server's counters.
+    stats: Arc<ServerStats>,
+    /// The connections accepted but not admitted yet (see [`Admission`]).
+    admitting: Arc<AtomicUsize>,
+}
+
+/// Returns whether [`ServerConfig::max_clients`] clients are connected, counting
+/// those still being admitted.
+///
+/// Connections are accepted one at a time, so no other can be counted between this
+/// check and the [`Admission`] started for the connection that passes it.
+async fn server_full(state: &ServerState) -> bool {
+    let Some(max_clients) = state.config.max_clients else {
+        return false;
+    };
+    let admitting = state.admitting.load(Ordering::Relaxed);
+    state.clients.read().await.len() + admitting >= max_clients
+}
+
+/// Serves one client from its admission until both directions of its connection
+/// are done.
+///
+/// The messages queued for the client are written by [`write_outgoing`] while
+/// [`admit_client`] and [`handle_connection`] read from it. `admission` is released
+/// once the client is admitted or turned away.
+async fn serve_connection<R, W>(
+    reader: R,
+    writer: W,
+    state: ServerState,
+    client_id: usize,
+    peer: Peer,
+    shutdown: watch::Receiver<bool>,
+    admission: Admission,
+) where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let (sender, queue) = client_queue(state.config.send_queue_capacity);
+    let (disconnect, disconnected) = watch::channel(false);
+    let writing = write_outgoing(writer, queue, state.config.flush_delay, state.stats.clone());
+    let reading = async {
+        let mut reader = BufReader::new(reader);
+        let admitted = admit_client(
+            &mut reader,
+            sender,
+            &state,
+            client_id,
+            peer,
+            disconnect,
+            shutdown.clone(),
+        )
+        .await;
+        // An admitted client is counted in the shared list from now on, and one that
+        // was not has its place back before its departure is reported
+        drop(admission);
+        if admitted {
+            handle_connection(reader, state.clone(), client_id, disconnected, shutdown).await;
+        } else {
+            state
+                .events
+                .emit(ServerEvent::ClientDisconnected { id: client_id });
+        }
+    };
+    tokio::join!(reading, writing);
+}
+
+/// Lets a new client into the chat.
+///
+/// If [`ServerConfig::users`] holds accounts, the client must first log in (see
+/// [`log_in`]); if it is empty or [`ServerConfig::require_nickname`] is set, the client
+/// must first pick a nickname (see [`ask_nickname`]). The client then receives its greeting (its ID, the MOTD and
+/// recent history) and is registered in [`DEFAULT_ROOM`], so the greeting is written
+/// before any broadcast.
+///
+/// # Arguments
+/// - `reader`: The buffered read half of the client connection.
+/// - `sender`: The client's queue, registered once the client is admitted.
+/// - `state`: The clients, settings and counters shared with the rest of the server.
+/// - `client_id`: The ID assigned to the client.
+/// - `peer`: Where the client connected from, recorded in [`ServerState::client_info`].
+/// - `disconnect`: The sender that ends the client's session, recorded with `peer`.
+/// - `shutdown`: A watch channel that flips to `true` to cancel the connection.
+///
+/// # Returns
+/// `true` if the client was registered, or `false` if it left or was turned away.
+async fn admit_client<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    sender: ClientSender,
+    state: &ServerState,
+    client_id: usize,
+    peer: Peer,
+    disconnect: watch::Sender<bool>,
+    shutdown: watch::Receiver<bool>,
+) -> bool {
+    let config = &state.config;
+    let admitted = match &config.users {
+        Some(users) if !users.is_empty() => {
+            log_in(reader, &sender, state, users.clone(), client_id, shutdown).await
+        }
+        Some(_) => ask_nickname(reader, &sender, state, client_id, shutdown).await,
+        None if config.require_nickname => {
+            ask_nickname(reader, &sender, state, client_id, shutdown).await
+        }
+        None => true,
+    };
+    if !admitted {
+        return false;
+    }
+
+    // The new client counts itself even though it is not registered yet
+    let client_count = state.clients.read().await.len() + 1;
+    let mut greeting = config
+        .protocol
+        .encode(&ServerMessage::Welcome { id: client_id });
+    for line in motd_messages(config, client_count, client_id) {
+        greeting.extend(config.protocol.encode(&line));
+    }
+    for line in history_messages(config, config.history_on_join).await {
+        greeting.extend(config.protocol.encode(&line));
+    }
+    let _ = sender.try_send(greeting.into());
+
+    state.clients.write().await.insert(client_id, sender);
+    // The room hears about the client before the client joins it, so it does not
+    // hear about itself
+    let name = display_name(client_id, state.nicknames.lock().await.get(client_id));
+    announce_to_room(state, DEFAULT_ROOM, format!("{} joined", name)).await;
+    add_member(&mut *state.rooms.lock().await, DEFAULT_ROOM, client_id);
+    state.client_info.lock().unwrap().insert(
+        client_id,
+        ClientInfo {
+            peer,
+            joined_at: Instant::now(),
+            last_private_from: None,
+            public_key: None,
+            echo: true,
+            disconnect,
+        },
+    );
+    state.stats.client_joined();
+    true
+}
+
+/// Returns the error sent to a client whose nickname could not be claimed.
+fn nickname_error(error: ClaimError) -> String {
+    match error {
+        ClaimError::Invalid(reason) => format!("{}: {}", NICKNAME_REJECTED, reason),
+        ClaimError::Taken => NICKNAME_IN_USE.to_string(),
+    }
+}
+
+/// Asks a new client for a nickname until it picks a valid, free one.
+///
+/// Each attempt sends a [`ServerMessage::NicknamePrompt`] and reads one message; a
+/// rejected nickname is answered with [`NICKNAME_IN_USE`] or [`NICKNAME_REJECTED`]. The
+/// accepted nickname is claimed in [`ServerState::nicknames`].
+///
+/// # Returns
+/// `true` once the client holds a nickname, or `false` if it used up its
+/// [`MAX_NICKNAME_ATTEMPTS`], disconnected, stayed silent past the idle timeout, or
+/// the server shut down.
+async fn ask_nickname<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    sender: &ClientSender,
+    state: &ServerState,
+    client_id: usize,
+    mut shutdown: watch::Receiver<bool>,
+) -> bool {
+    for _ in 0..MAX_NICKNAME_ATTEMPTS {
+        let prompt = &ServerMessage::NicknamePrompt;
+        let Some(answer) = ask(reader, sender, &state.config, prompt, &mut shutdown).await else {
+            return false;
+        };
+
+        let message = match answer {
+            Incoming::Message(name) => {
+                match state.nicknames.lock().await.claim(client_id, name.trim()) {
+                    Ok(()) => return true,
+                    Err(error) => nickname_error(error),
+                }
+            }
+            Incoming::Rejected(reason) => reason.message(),
+        };
+        send_message(sender, &state.config, &ServerMessage::Error { message });
+    }
+    false
+}
+
+/// Asks a new client to log in until it gives the password of an account.
+///
+/// Each attempt sends a [`ServerMessage::UsernamePrompt`] and a
+/// [`ServerMessage::PasswordPrompt`], each answered with one message; a failed login
+/// is answered with [`LOGIN_FAILED`], and running out of attempts with
+/// [`AUTHENTICATION_FAILED_NOTICE`]. The client then chats under its username, which
+/// is claimed in [`ServerState::nicknames`]. If another connection holds it, the
+/// [`ServerConfig::duplicate_login`] policy decides which of the two is disconnected.
+///
+/// # Returns
+/// `true` once the client is logged in, or `false` if it used up its
+/// [`MAX_LOGIN_ATTEMPTS`], was turned away as a duplicate login, disconnected, stayed
+/// silent past the idle timeout, or the server shut down.
+async fn log_in<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    sender: &ClientSender,
+    state: &ServerState,
+    users: Arc<UserStore>,
+    client_id: usize,
+    mut shutdown: watch::Receiver<bool>,
+) -> bool {
+    let config = &state.config;
+    for _ in 0..MAX_LOGIN_ATTEMPTS {
+        let prompt = &ServerMessage::UsernamePrompt;
+        let Some(username) = ask(reader, sender, config, prompt, &mut shutdown).await else {
+            return false;
+        };
+        let prompt = &ServerMessage::PasswordPrompt;
+        let Some(password) = ask(reader, sender, config, prompt, &mut shutdown).await else {
+            return false;
+        };
+
+        if let (Incoming::Message(username), Incoming::Message(password)) = (username, password) {
+            let username = username.trim().to_string();
+            // Hashing is slow on purpose, so it must not hold up the runtime
+            let verified = tokio::task::spawn_blocking({
+                let users = users.clone();
+                let username = username.clone();
+                move || users.verify(&username, &password)
+            })
+            .await
+            .unwrap_or(false);
+            if verified {
+                // The registry stays locked from the check to the claim, so two
+                // logins to one account cannot both get in
+                let mut nicknames = state.nicknames.lock().await;
+                match (nicknames.find(&username), config.duplicate_login) {
+                    (None, _) => {
+                        if nicknames.claim(client_id, &username).is_ok() {
+                            return true;
+                        }
+                    }
+                    (Some(_), DuplicateLoginPolicy::Reject) => {
+                        drop(nicknames);
+                        send_message(
+                            sender,
+                            config,
+                            &ServerMessage::System {
+                                text: ALREADY_LOGGED_IN_NOTICE.to_string(),
+                            },
+                        );
+                        return false;
+                    }
+                    (Some(old_id), DuplicateLoginPolicy::ReplaceOld) => {
+                        // The old session leaves under the username and gives it up
+                        // on the way out; whoever claims it first after that gets in
+                        drop(nicknames);
+                        disconnect_client(state, old_id, LOGGED_IN_ELSEWHERE_NOTICE).await;
+                        if state
+                            .nicknames
+                            .lock()
+                            .await
+                            .claim(client_id, &username)
+                            .is_ok()
+                        {
+                            return true;
+                        }
+                    }
+                }
+            }
+            state.events.emit(ServerEvent::LoginFailed {
+                id: client_id,
+                username,
+            });
+        }
+        send_message(
+            sender,
+            config,
+            &ServerMessage::Error {
+                message: LOGIN_FAILED.to_string(),
+            },
+        );
+    }
+    send_message(
+        sender,
+        config,
+        &ServerMessage::System {
+            text: AUTHENTICATION_FAILED_NOTICE.to_string(),
+        },
+    );
+    false
+}
+
+/// Sends `prompt` to a client that is not admitted yet and reads its answer.
+///
+/// # Returns
+/// The answer, or `None` if the client disconnected, stayed silent past the idle
+/// timeout, or the server shut down; the client is told about the last two.
+async fn ask<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    sender: &ClientSender,
+    config: &ServerConfig,
+    prompt: &ServerMessage,
+    shutdown: &mut watch::Receiver<bool>,
+) -> Option<Incoming> {
+    send_message(sender, config, prompt);
+    let idle_deadline = Instant::now() + config.idle_timeout.unwrap_or_default();
+    let notice = tokio::select! {
+        result = read_incoming(reader, config) => {
+            return result.ok().flatten().map(|received| received.incoming)
+        }
+        _ = tokio::time::sleep_until(idle_deadline), if config.idle_timeout.is_some() => IDLE_TIMEOUT_NOTICE,
+        _ = shutdown.changed() => SHUTDOWN_NOTICE,
+    };
+    send_message(
+        sender,
+        config,
+        &ServerMessage::System {
+            text: notice.to_string(),
+        },
+    );
+    None
+}
+
+/// Queues `message` for a client, dropping it if the client's queue is full.
+fn send_message(sender: &ClientSender, config: &ServerConfig, message: &ServerMessage) {
+    let _ = sender.try_send(config.protocol.encode(message).into());
+}
+
+/// Handles an individual client connection.
+///
+/// This function processes client messages and determines whether they should be
+/// broadcast to the client's room or sent privately to a specific client. The client
+/// starts in [`DEFAULT_ROOM`], where [`ChatServer::run`] registered it. Messages the server
+/// refuses, such as those longer than `config.max_message_bytes`, are answered with a
+/// [`ServerMessage::Rejected`] sent back to the client only.
+/// It returns when the client disconnects, when `disconnected` flips to `true` (see
+/// [`disconnect_client`]) or when `shutdown` is triggered, and removes the client from
+/// the shared list, its room and [`ServerState::client_info`] on the way out. A client
+/// cut off by `shutdown` receives [`SHUTDOWN_NOTICE`] first.
+///
+/// # Arguments
+/// - `buf_reader`: The buffered read half of the client connection.
+/// - `state`: The clients, settings and counters shared with the rest of the server.
+/// - `client_id`: A unique identifier for the client.
+/// - `disconnected`: A watch channel that flips to `true` to end this client's session.
+/// - `shutdown`: A watch channel that flips to `true` to cancel the connection.
+async fn handle_connection<R: AsyncRead + Unpin>(
+    mut buf_reader: BufReader<R>,
+    state: ServerState,
+    client_id: usize,
+    mut disconnected: watch::Receiver<bool>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let ServerState {
+        clients,
+        admins,
+        rooms,
+        nicknames,
+        client_info,
+        config,
+        events,
+        stats,
+        ..
+    } = state.clone();
+    let mut room = DEFAULT_ROOM.to_string();
+    let mut history = VecDeque::new();
+    let mut muted_until: Option<Instant> = None;
+    let mut rate_limiter = TokenBucket::new(config.rate_limit_per_sec, config.rate_limit_burst);
+    let mut auth_failures = 0;
+    let mut last_activity = Instant::now();
+
+    // The heartbeat task hears about pongs from this loop and finishes if they stop
+    let (pong_tx, pong_rx) = mpsc::channel(1);
+    let mut heartbeat_task = config.heartbeat_interval.map(|interval| {
+        tokio::spawn(heartbeat(
+            clients.clone(),
+            client_id,
+            config.protocol,
+            interval,
+            config.heartbeat_max_missed,
+            pong_rx,
+        ))
+    });
+
+    loop {
+        let idle_deadline = last_activity + config.idle_timeout.unwrap_or_default();
+        let Received {
+            incoming,
+            id: message_id,
+        } = tokio::select! {
+            result = read_incoming(&mut buf_reader, &config) => match result {
+                Ok(Some(received)) => received,
+                // Client disconnected
+                Ok(None) | Err(_) => break,
+            },
+            _ = tokio::time::sleep_until(idle_deadline), if config.idle_timeout.is_some() => {
+                events.emit(ServerEvent::ClientTimedOut { id: client_id });
+                send_notice(&clients, &config, client_id, IDLE_TIMEOUT_NOTICE).await;
+                break;
+            }
+            _ = tokio::time::sleep_until(muted_until.unwrap_or_else(Instant::now)), if muted_until.is_some() => {
+                muted_until = None;
+                events.emit(ServerEvent::ClientUnmuted { id: client_id });
+                let notice = config.protocol.encode(&ServerMessage::System {
+                    text: UNMUTED_NOTICE.to_string(),
+                });
+                send_to_client(clients.clone(), client_id, notice.into()).await;
+                continue;
+            }
+            _ = async { heartbeat_task.as_mut().unwrap().await }, if heartbeat_task.is_some() => {
+                events.emit(ServerEvent::ClientTimedOut { id: client_id });
+                break;
+            }
+            // The notice was queued by whoever disconnected the client
+            true = async { disconnected.wait_for(|&gone| gone).await.is_ok() } => break,
+            _ = shutdown.changed() => {
+                send_notice(&clients, &config, client_id, SHUTDOWN_NOTICE).await;
+                break;
+            }
+        };
+
+        // Heartbeat replies keep the client from idling out, but are not chat
+        last_activity = Instant::now();
+        if let Incoming::Message(message) = &incoming {
+            if let Some(nonce) = parse_pong(message) {
+                let _ = pong_tx.try_send(nonce);
+                continue;
+            }
+        }
+
+        // A client dropped for being too slow has lost its writer; stop serving it
+        if !clients.read().await.contains_key(&client_id) {
+            break;
+        }
+
+        // Messages sent while muted are read and refused
+        let incoming = if let Some(until) = muted_until {
+            Incoming::Rejected(muted(until))
+        } else if check_flood(&mut history, config.flood_limit, config.flood_window) {
+            history.clear();
+            let until = Instant::now() + config.mute_duration;
+            muted_until = Some(until);
+            events.emit(ServerEvent::ClientMuted { id: client_id });
+            Incoming::Rejected(muted(until))
+        } else {
+            match incoming {
+                Incoming::Message(_) if !rate_limiter.try_acquire() => {
+                    Incoming::Rejected(RejectionReason::RateLimited)
+                }
+                incoming => incoming,
+            }
+        };
+
+        let message = match incoming {
+            Incoming::Message(message) => message,
+            Incoming::Rejected(reason) => {
+                stats.message_rejected(reason);
+                events.emit(ServerEvent::MessageRejected {
+                    id: client_id,
+                    reason,
+                });
+                let rejection = config.protocol.encode(&reason.rejecting(message_id));
+                send_to_client(clients.clone(), client_id, rejection.into()).await;
+                continue;
+            }
+        };
+
+        match parse_command(&message) {
+            Command::Help(topic) => {
+                let mut reply = Vec::new();
+                for line in help_messages(topic.as_deref()) {
+                    reply.extend(config.protocol.encode(&line));
+                }
+                send_to_client(clients.clone(), client_id, reply.into()).await;
+            }
+            Command::List => {
+                let members: Vec<(String, usize)> = rooms
+                    .lock()
+                    .await
+                    .values()
+                    .flat_map(|room| room.members.iter().map(|&id| (room.name.clone(), id)))
+                    .collect();
+                let nicknames = nicknames.lock().await;
+                let listed: Vec<ListedClient> = {
+                    let client_info = client_info.lock().unwrap();
+                    members
+                        .iter()
+                        .map(|(room, id)| ListedClient {
+                            id: *id,
+                            nick: nicknames.get(*id),
+                            room,
+                            connected: client_info
+                                .get(id)
+                                .map_or(Duration::ZERO, |info| info.joined_at.elapsed()),
+                        })
+                        .collect()
+                };
+                let mut reply = Vec::new();
+                for text in client_listing(listed) {
+                    reply.extend(config.protocol.encode(&ServerMessage::System { text }));
+                }
+                drop(nicknames);
+                send_to_client(clients.clone(), client_id, reply.into()).await;
+            }
+            Command::WhoAmI => {
+                let info = client_info.lock().unwrap().get(&client_id).cloned();
+                let nick = nicknames.lock().await.get(client_id).map(str::to_string);
+                let mut reply = Vec::new();
+                for text in info
+                    .iter()
+                    .flat_map(|info| identity_lines(client_id, nick.as_deref(), info))
+                {
+                    reply.extend(config.protocol.encode(&ServerMessage::System { text }));
+                }
+                send_to_client(clients.clone(), client_id, reply.into()).await;
+            }
+            Command::Private { targets, body } => {
+                let mut found = Vec::new();
+                let mut missing = Vec::new();
+                for target in &targets {
+                    match find_recipient(&state, target).await {
+                        // Two names for the same client still send it one copy
+                        Some(id) if found.contains(&id) => {}
+                        Some(id) => found.push(id),
+                        None => missing.push(target.to_string()),
+                    }
+                }
+                // Each recipient is confirmed to the sender on its own
+                for target in found {
+                    relay_private(&state, client_id, target, body.clone()).await;
+                }
+                if !missing.is_empty() {
+                    send_notice(
+                        &clients,
+                        &config,
+                        client_id,
+                        format!("No such user: {}", missing.join(", ")),
+                    )
+                    .await;
+                }
+            }
+            Command::Key(key) => {
+                if let Some(info) = client_info.lock().unwrap().get_mut(&client_id) {
+                    info.public_key = Some(key.clone());
+                }
+                let reply = config.protocol.encode(&ServerMessage::PublicKey {
+                    target: client_id.to_string(),
+                    id: Some(client_id),
+                    key: Some(key),
+                });
+                send_to_client(clients.clone(), client_id, reply.into()).await;
+            }
+            Command::GetKey(targets) => {
+                let mut reply = Vec::new();
+                for target in targets {
+                    let id = find_recipient(&state, &target).await;
+                    let key = id.and_then(|id| {
+                        let client_info = client_info.lock().unwrap();
+                        client_info.get(&id)?.public_key.clone()
+                    });
+                    reply.extend(config.protocol.encode(&ServerMessage::PublicKey {
+                        target: target.to_string(),
+                        id,
+                        key,
+                    }));
+                }
+                send_to_client(clients.clone(), client_id, reply.into()).await;
+            }
+            Command::Reply(body) => {
+                let last = client_info
+                    .lock()
+                    .unwrap()
+                    .get(&client_id)
+                    .and_then(|info| info.last_private_from);
+                let message = match last {
+                    Some(target) if clients.read().await.contains_key(&target) => {
+                        relay_private(&state, client_id, target, body).await;
+                        continue;
+                    }
+                    Some(target) => format!("Client {} is no longer connected", target),
+                    None => "No one has sent you a private message to reply to".to_string(),
+                };
+                let error = config.protocol.encode(&ServerMessage::Error { message });
+                send_to_client(clients.clone(), client_id, error.into()).await;
+            }
+            Command::Motd => {
+                let client_count = clients.read().await.len();
+                let mut reply = Vec::new();
+                for line in motd_messages(&config, client_count, client_id) {
+                    reply.extend(config.protocol.encode(&line));
+                }
+                if reply.is_empty() {
+                    reply = config.protocol.encode(&ServerMessage::System {
+                        text: "No message of the day is set.".to_string(),
+                    });
+                }
+                send_to_client(clients.clone(), client_id, reply.into()).await;
+            }
+            Command::Auth(password) => {
+                let notice = match &config.admin_password {
+                    None => "[Auth] Admin access is disabled",
+                    Some(expected) if password == *expected => {
+                        admins.lock().await.insert(client_id);
+                        events.emit(ServerEvent::AdminGranted { id: client_id });
+                        "[Auth] Admin access granted"
+                    }
+                    Some(_) => {
+                        auth_failures += 1;
+                        if auth_failures >= MAX_AUTH_FAILURES {
+                            send_notice(
+                                &clients,
+                                &config,
+                                client_id,
+                                "[Auth] Too many failed attempts",
+                            )
+                            .await;
+                            break;
+                        }
+                        "[Auth] Wrong password"
+                    }
+                };
+                send_notice(&clients, &config, client_id, notice).await;
+            }
+            Command::Kick(target_id) => {
+                if !admins.lock().await.contains(&client_id) {
+                    send_notice(&clients, &config, client_id, "[Auth] Admin access required").await;
+                } else if target_id == client_id {
+                    // This session cannot wait for itself to end, so it just leaves
+                    events.emit(ServerEvent::ClientKicked {
+                        id: client_id,
+                        by: client_id,
+                    });
+                    send_notice(&clients, &config, client_id, KICKED_NOTICE).await;
+                    break;
+                } else if kick_client(&state, target_id).await {
+                    events.emit(ServerEvent::ClientKicked {
+                        id: target_id,
+                        by: client_id,
+                    });
+                    send_notice(
+                        &clients,
+                        &config,
+                        client_id,
+                        format!("Client {} was kicked", target_id),
+                    )
+                    .await;
+                } else {
+                    send_notice(
+                        &clients,
+                        &config,
+                        client_id,
+                        format!("Client {} not found", target_id),
+                    )
+                    .await;
+                }
+            }
+            Command::Stats => {
+                if !admins.lock().await.contains(&client_id) {
+                    send_notice(&clients, &config, client_id, "[Auth] Admin access required").await;
+                    continue;
+                }
+                let mut reply = Vec::new();
+                for text in stats.summary() {
+                    reply.extend(config.protocol.encode(&ServerMessage::System { text }));
+                }
+                send_to_client(clients.clone(), client_id, reply.into()).await;
+            }
+            Command::FilterAdd(word) => {
+                let change = async {
+                    Ok(match config.word_filter.add(&word).await? {
+                        true => format!("Now filtering {}", word),
+                        false => format!("{} is already filtered", word),
+                    })
+                };
+                change_word_filter(&state, client_id, change).await;
+            }
+            Command::FilterRemove(word) => {
+                let change = async {
+                    Ok(match config.word_filter.remove(&word).await? {
+                        true => format!("No longer filtering {}", word),
+                        false => format!("{} is not filtered", word),
+                    })
+                };
+                change_word_filter(&state, client_id, change).await;
+            }
+            Command::Echo(echo) => {
+                if let Some(info) = client_info.lock().unwrap().get_mut(&client_id) {
+                    info.echo = echo;
+                }
+                let notice = if echo {
+                    ECHO_ON_NOTICE
+                } else {
+                    ECHO_OFF_NOTICE
+                };
+                send_notice(&clients, &config, client_id, notice).await;
+            }
+            // An account's username is its identity, so it cannot be swapped for another
+            Command::Nick(_) if config.users.as_ref().is_some_and(|users| !users.is_empty()) => {
+                let error = config.protocol.encode(&ServerMessage::Error {
+                    message: "Logged-in users chat under their username".to_string(),
+                });
+                send_to_client(clients.clone(), client_id, error.into()).await;
+            }
+            Command::Nick(name) => {
+                let (claimed, old) = {
+                    let mut nicknames = nicknames.lock().await;
+                    let old = nicknames.get(client_id).map(str::to_string);
+                    (nicknames.claim(client_id, &name), old)
+                };
+                // The registry is locked for the whole claim, so of two clients asking
+                // for the same name at once exactly one gets it
+                if let Err(error) = claimed {
+                    let error = config.protocol.encode(&ServerMessage::Error {
+                        message: nickname_error(error),
+                    });
+                    send_to_client(clients.clone(), client_id, error.into()).await;
+                } else if old.as_deref() == Some(name.as_str()) {
+                    send_notice(
+                        &clients,
+                        &config,
+                        client_id,
+                        format!("You are already known as {}", name),
+                    )
+                    .await;
+                } else {
+                    events.emit(ServerEvent::NicknameChanged {
+                        id: client_id,
+                        nick: name.clone(),
+                    });
+                    let old = display_name(client_id, old.as_deref());
+                    announce_to_room(&state, &room, format!("{} is now known as {}", old, name))
+                        .await;
+                }
+            }
+            Command::Rooms => {
+                let listing = room_listing(&*rooms.lock().await);
+                let mut reply = Vec::new();
+                for text in listing {
+                    reply.extend(config.protocol.encode(&ServerMessage::System { text }));
+                }
+                send_to_client(clients.clone(), client_id, reply.into()).await;
+            }
+            Command::Join(target) if target == room => {
+                send_notice(
+                    &clients,
+                    &config,
+                    client_id,
+                    format!("You are already in {}", room),
+                )
+                .await;
+            }
+            Command::Join(target) => {
+                {
+                    let mut rooms = rooms.lock().await;
+                    remove_member(&mut rooms, &room, client_id);
+                    add_member(&mut rooms, &target, client_id);
+                }
+                send_notice(
+                    &clients,
+                    &config,
+                    client_id,
+                    format!("Joined room {}", target),
+                )
+                .await;
+                room = target;
+            }
+            Command::Leave(_) if room == DEFAULT_ROOM => {
+                send_notice(
+                    &clients,
+                    &config,
+                    client_id,
+                    format!("You are in {}, which cannot be left", DEFAULT_ROOM),
+                )
+                .await;
+            }
+            Command::Leave(Some(named)) if named != room => {
+                send_notice(
+                    &clients,
+                    &config,
+                    client_id,
+                    format!("You are not in room {}", named),
+                )
+                .await;
+            }
+            Command::Leave(_) => {
+                {
+                    let mut rooms = rooms.lock().await;
+                    remove_member(&mut rooms, &room, client_id);
+                    add_member(&mut rooms, DEFAULT_ROOM, client_id);
+                }
+                send_notice(
+                    &clients,
+                    &config,
+                    client_id,
+                    format!("Left room {}, back in {}", room, DEFAULT_ROOM),
+                )
+                .await;
+                room = DEFAULT_ROOM.to_string();
+            }
+            Command::Invalid(command) => {
+                send_notice(&clients, &config, client_id, usage(command)).await;
+            }
+            // A mistyped command must not reach the room, least of all a private message
+            Command::Unknown(command) => {
+                send_notice(
+                    &clients,
+                    &config,
+                    client_id,
+                    format!("Unknown command: {} — type /help", command),
+                )
+                .await;
+            }
+            Command::History(_) if config.message_store.is_none() => {
+                send_notice(&clients, &config, client_id, HISTORY_DISABLED_NOTICE).await;
+            }
+            Command::History(count) => {
+                let history = history_messages(&config, count.min(MAX_HISTORY_REQUEST)).await;
+                if history.is_empty() {
+                    send_notice(&clients, &config, client_id, "No messages yet.").await;
+                } else {
+                    let mut reply = Vec::new();
+                    for line in &history {
+                        reply.extend(config.protocol.encode(line));
+                    }
+                    send_to_client(clients.clone(), client_id, reply.into()).await;
+                }
+            }
+            // A broadcast may have had an escaping slash taken off
+            Command::Broadcast(text) => {
+                broadcast_chat(&state, client_id, &room, MessageKind::Chat, &text).await;
+            }
+            Command::Action(body) => {
+                broadcast_chat(&state, client_id, &room, MessageKind::Action, &body).await;
+            }
+        }
+    }
+
+    let name = display_name(client_id, nicknames.lock().await.get(client_id));
+    clients.write().await.remove(&client_id);
+    remove_member(&mut *rooms.lock().await, &room, client_id);
+    // Everyone is leaving when the server shuts down, which needs no announcement
+    if !*shutdown.borrow() {
+        announce_to_room(&state, &room, format!("{} left", name)).await;
+    }
+    client_info.lock().unwrap().remove(&client_id);
+    nicknames.lock().await.release(client_id);
+    admins.lock().await.remove(&client_id);
+    stats.client_left();
+    events.emit(ServerEvent::ClientDisconnected { id: client_id });
+}
+
+/// Sends a chat message or `/me` action from `client_id` to its `room`, with banned
+/// words masked (see [`ServerConfig::word_filter`]).
+///
+/// The masked text, without `/me`, is what subscribers to [`ServerEvent`]s see and
+/// what the history keeps, tagged with its `kind` so an action is replayed as one.
+/// The sender gets its own copy too, unless it turned that off with `/echo off`.
+async fn broadcast_chat(
+    state: &ServerState,
+    client_id: usize,
+    room: &str,
+    kind: MessageKind,
+    text: &str,
+) {
+    let config = &state.config;
+    let text = config.word_filter.apply(text);
+    let nick = state
+        .nicknames
+        .lock()
+        .await
+        .get(client_id)
+        .map(str::to_string);
+    let message = match kind {
+        MessageKind::Action => config.protocol.encode(&ServerMessage::Action {
+            from: client_id,
+            nick,
+            body: text.clone(),
+            timestamp: chat_timestamp(config),
+        }),
+        MessageKind::Chat => encode_chat(
+            config,
+            &ChatLine {
+                id: client_id,
+                nick: nick.as_deref(),
+                body: &text,
+                target: None,
+            },
+        ),
+    };
+    state.events.emit(ServerEvent::MessageBroadcast {
+        from: client_id,
+        text: text.clone(),
+    });
+
+    let echo = state
+        .client_info
+        .lock()
+        .unwrap()
+        .get(&client_id)
+        .is_none_or(|info| info.echo);
+
+    state.stats.message_broadcast();
+    let overflowed = broadcast_message(
+        state.clients.clone(),
+        &state.rooms,
+        Some(room),
+        (!echo).then_some(client_id),
+        message.into(),
+    )
+    .await;
+    handle_overflows(
+        &state.clients,
+        config,
+        &state.events,
+        &state.stats,
+        overflowed,
+    )
+    .await;
+
+    // The history is replayed to everyone, so it only keeps the default room
+    if let (Some(store), true) = (&config.message_store, room == DEFAULT_ROOM) {
+        let saved = ChatMessage {
+            kind,
+            ..ChatMessage::new(client_id, text)
+        };
+        if let Err(e) = store.save(&saved).await {
+            tracing::warn!("Failed to save message from Client {}: {}", client_id, e);
+        }
+    }
+}
+
+/// Returns the ID of the connected client `target` names, if there is one.
+async fn find_recipient(state: &ServerState, target: &Recipient) -> Option<usize> {
+    let nicknames = state.nicknames.lock().await;
+    // A nickname made of digits wins over the client ID it spells
+    match target {
+        Recipient::Id(id) => match nicknames.find(&id.to_string()) {
+            Some(holder) => Some(holder),
+            None if state.clients.read().await.contains_key(id) => Some(*id),
+            None => None,
+        },
+        Recipient::ClientId(id) => state.clients.read().await.contains_key(id).then_some(*id),
+        Recipient::Nick(name) => nicknames.find(name),
+    }
+}
+
+/// Delivers a private message from `client_id` to `target`, a client that was
+/// connected when it was looked up.
+///
+/// The sender gets a [`ServerMessage::PrivateSent`] copy once the message is on its
+/// way, or an error saying why it could not be delivered. The recipient can then
+/// answer with `/reply`.
+async fn relay_private(state: &ServerState, client_id: usize, target: usize, body: String) {
+    let (nick, target_nick) = {
+        let nicknames = state.nicknames.lock().await;
+        (
+            nicknames.get(client_id).map(str::to_string),
+            nicknames.get(target).map(str::to_string),
+        )
+    };
+    let message = encode_chat(
+        &state.config,
+        &ChatLine {
+            id: client_id,
+            nick: nick.as_deref(),
+            body: &body,
+            target: Some(target),
+        },
+    );
+    state.events.emit(ServerEvent::PrivateMessage {
+        from: client_id,
+        to: target,
+        text: body.clone(),
+    });
+
+    // Counted first, so the recipient never sees a message the stats miss
+    state.stats.private_message_sent();
+    let reply = match send_private_message(state.clients.clone(), target, message.into()).await {
+        Ok(()) => {
+            if let Some(info) = state.client_info.lock().unwrap().get_mut(&target) {
+                info.last_private_from = Some(client_id);
+            }
+            ServerMessage::PrivateSent {
+                to: target,
+                nick: target_nick,
+                body,
+                timestamp: chat_timestamp(&state.config),
+            }
+        }
+        Err(message) => ServerMessage::Error { message },
+    };
+    let reply = state.config.protocol.encode(&reply);
+    send_to_client(state.clients.clone(), client_id, reply.into()).await;
+}
+
+/// Encodes a broadcast or private message, rendered by [`ServerConfig::formatter`]
+/// on the text protocol and stamped with the time if [`ServerConfig::timestamps`] is on.
+fn encode_chat(config: &ServerConfig, line: &ChatLine) -> Vec<u8> {
+    let now = chat_timestamp(config);
+    match config.protocol {
+        Protocol::Text => match now {
+            Some(now) => format!("[{}] {}\n", now, config.formatter.format(line)),
+            None => format!("{}\n", config.formatter.format(line)),
+        }
+        .into_bytes(),
+        Protocol::Json => {
+            let mut message = chat_message(line);
+            if let ServerMessage::Broadcast { timestamp, .. }
+            | ServerMessage::Private { timestamp, .. } = &mut message
+            {
+                *timestamp = now;
+            }
+            config.protocol.encode(&message)
+        }
+    }
+}
+
+/// Returns the time to stamp a chat message with, in RFC 3339 format, or `None` if
+/// [`ServerConfig::timestamps`] is off.
+fn chat_timestamp(config: &ServerConfig) -> Option<String> {
+    config
+        .timestamps
+        .then(|| Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+/// Returns the name a client is announced under: its nickname if it has one,
+/// `Client <id>` if not.
+fn display_name(client_id: usize, nick: Option<&str>) -> String {
+    match nick {
+        Some(nick) => nick.to_string(),
+        None => format!("Client {}", client_id),
+    }
+}
+
+/// Sends a notice to every client in `room`, such as a client joining or leaving it.
+async fn announce_to_room(state: &ServerState, room: &str, text: String) {
+    let notice = state
+        .config
+        .protocol
+        .encode(&ServerMessage::System { text });
+    let overflowed = broadcast_message(
+        state.clients.clone(),
+        &state.rooms,
+        Some(room),
+        None,
+        notice.into(),
+    )
+    .await;
+    handle_overflows(
+        &state.clients,
+        &state.config,
+        &state.events,
+        &state.stats,
+        overflowed,
+    )
+    .await;
+}
+
+/// A client as shown by `/list`.
+struct ListedClient<'a> {
+    id: usize,
+    nick: Option<&'a str>,
+    /// The room the client is in.
+    room: &'a str,
+    /// How long ago the client joined.
+    connected: Duration,
+}
+
+/// Renders the `/list` reply for every connected client, grouped by room.
+///
+/// The first line counts the clients and the rooms, so a script knows how many lines
+/// follow. Each room with clients in it gets a line with its member count, in order of
+/// name as in `/rooms`, followed by a line for each of its members, in order of ID.
+fn client_listing(mut clients: Vec<ListedClient>) -> Vec<String> {
+    clients.sort_unstable_by(|a, b| (a.room, a.id).cmp(&(b.room, b.id)));
+    let groups: Vec<&[ListedClient]> = clients.chunk_by(|a, b| a.room == b.room).collect();
+    let mut lines = vec![format!(
+        "{} in {}:",
+        plural(clients.len(), "client"),
+        plural(groups.len(), "room")
+    )];
+    for group in groups {
+        lines.push(format!("  {} ({}):", group[0].room, group.len()));
+        for client in group {
+            let name = match client.nick {
+                Some(nick) => format!("Client {} ({})", client.id, nick),
+                None => format!("Client {}", client.id),
+            };
+            lines.push(format!(
+                "    {}, connected {}",
+                name,
+                format_uptime(client.connected)
+            ));
+        }
+    }
+    lines
+}
+
+/// Returns `count` followed by `noun`, with an `s` unless there is exactly one.
+fn plural(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {}", noun),
+        _ => format!("{} {}s", count, noun),
+    }
+}
+
+/// Renders the `/whoami` reply: `You are Client <id> (<nick>)`, or `(no nick)`, then
+/// the client's address and time connected.
+fn identity_lines(client_id: usize, nick: Option<&str>, info: &ClientInfo) -> Vec<String> {
+    vec![
+        format!(
+            "You are Client {} ({})",
+            client_id,
+            nick.unwrap_or("no nick")
+        ),
+        format!("  Address: {}", info.peer),
+        format!("  Connected: {}", format_uptime(info.joined_at.elapsed())),
+    ]
+}
+
+/// The most messages a single `/history` request replays.
+pub const MAX_HISTORY_REQUEST: usize = 100;
+
+/// The reply to `/history` on a server without a message store.
+pub const HISTORY_DISABLED_NOTICE: &str = "Message history is not enabled on this server.";
+
+/// Fetches up to `limit` recent messages from the configured store, oldest first.
+///
+/// Without a store, or if the store fails, there is no history to show; failures are
+/// logged to standard error.
+async fn history_messages(config: &ServerConfig, limit: usize) -> Vec<ServerMessage> {
+    let Some(store) = &config.message_store else {
+        return Vec::new();
+    };
+    if limit == 0 {
+        return Vec::new();
+    }
+    match store.recent(limit).await {
+        Ok(messages) => messages
+            .into_iter()
+            .map(|msg| {
+                let timestamp = msg.timestamp.to_rfc3339_opts(SecondsFormat::Secs, true);
+                match msg.kind {
+                    MessageKind::Chat => ServerMessage::History {
+                        from: msg.sender_id,
+                        body: msg.content,
+                        timestamp,
+                    },
+                    // Replayed the way it was first shown, stamped with when it was sent
+                    MessageKind::Action => ServerMessage::Action {
+                        from: msg.sender_id,
+                        nick: None,
+                        body: msg.content,
+                        timestamp: Some(timestamp),
+                    },
+                }
+            })
+            .collect(),
+        Err(e) => {
+            tracing::warn!("Failed to read message history: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Builds the `/help` reply, one message per line: every command, or how to use the
+/// one named by `topic`.
+fn help_messages(topic: Option<&str>) -> Vec<ServerMessage> {
+    let Some(name) = topic else {
+        return std::iter::once("Available commands:".to_string())
+            .chain(
+                COMMANDS
+                    .iter()
+                    .map(|info| format!("  {} - {}", info.usage, info.description)),
+            )
+            .map(|text| ServerMessage::System { text })
+            .collect();
+    };
+    match find_command(COMMANDS, name) {
+        Some(info) => info
+            .explain()
+            .into_iter()
+            .map(|text| ServerMessage::System { text })
+            .collect(),
+        None => vec![ServerMessage::Error {
+            message: format!("No such command: {} — type /help", name),
+        }],
+    }
+}
+
+/// Sends a one-line [`ServerMessage::System`] notice to a single client.
+async fn send_notice(
+    clients: &SharedClients,
+    config: &ServerConfig,
+    client_id: usize,
+    text: impl Into<String>,
+) {
+    let notice = config
+        .protocol
+        .encode(&ServerMessage::System { text: text.into() });
+    send_to_client(clients.clone(), client_id, notice.into()).await;
+}
+
+/// Makes an admin's change to [`ServerConfig::word_filter`] and tells the admin how
+/// it went.
+///
+/// `change` makes the change and returns the notice to send. It only runs if
+/// `client_id` is an admin.
+async fn change_word_filter(
+    state: &ServerState,
+    client_id: usize,
+    change: impl Future<Output = std::io::Result<String>>,
+) {
+    let (clients, config) = (&state.clients, &state.config);
+    if !state.admins.lock().await.contains(&client_id) {
+        send_notice(clients, config, client_id, "[Auth] Admin access required").await;
+        return;
+    }
+    match change.await {
+        Ok(text) => send_notice(clients, config, client_id, text).await,
+        Err(e) => {
+            tracing::warn!("Failed to update the word filter: {}", e);
+            let error = config.protocol.encode(&ServerMessage::Error {
+                message: format!("Could not update the word filter: {}", e),
+            });
+            send_to_client(clients.clone(), client_id, error.into()).await;
+        }
+    }
+}
+
+/// Disconnects a client on an admin's request.
+///
+/// # Returns
+/// `true` if the client was connected.
+async fn kick_client(state: &ServerState, target_id: usize) -> bool {
+    disconnect_client(state, target_id, KICKED_NOTICE).await
+}
+
+/// Disconnects a client after a final `notice`.
+///
+/// The client is removed from the shared list, so it receives no further messages,
+/// and its connection is closed after the notice. Its session is ended at once rather
+/// than at the next message it sends, and this returns once the session has left its
+/// room, announced its departure and given up its nickname.
+///
+/// # Returns
+/// `true` if the client was connected.
+async fn disconnect_client(state: &ServerState, target_id: usize, notice: &str) -> bool {
+    let Some(sender) = state.clients.write().await.remove(&target_id) else {
+        return false;
+    };
+    let notice = state.config.protocol.encode(&ServerMessage::System {
+        text: notice.to_string(),
+    });
+    // Dropping the last sender makes the writer task close the connection after the
+    // notice, if there was room to queue it
+    let _ = sender.try_send(notice.into());
+    drop(sender);
+
+    let info = state.client_info.lock().unwrap().get(&target_id).cloned();
+    if let Some(info) = info {
+        info.disconnect.send_replace(true);
+        info.disconnect.closed().await;
+    }
+    true
+}
+
+/// Pings a client every `interval` until it stops answering.
+///
+/// Each ping carries the next nonce, starting at 1, and a pong for an outstanding ping
+/// answers it and every ping before it. The task returns, and the connection should be
+/// dropped, at the first interval that finds `max_missed` pings unanswered, or when
+/// `pongs` is closed because the connection ended anyway.
+///
+/// # Arguments
+/// - `clients`: A shared collection of all connected clients.
+/// - `client_id`: The ID of the client to ping.
+/// - `protocol`: The wire format the pings are encoded in.
+/// - `interval`: The time between pings.
+/// - `max_missed`: How many pings in a row may go unanswered.
+/// - `pongs`: Receives the nonce of every pong the client sends.
+async fn heartbeat(
+    clients: SharedClients,
+    client_id: usize,
+    protocol: Protocol,
+    interval: Duration,
+    max_missed: u32,
+    mut pongs: mpsc::Receiver<u64>,
+) {
+    let mut ticker = tokio::time::interval_at(Instant::now() + interval, interval);
+    // The nonces of the latest ping sent and of the latest one answered
+    let mut sent = 0;
+    let mut answered = 0;
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                if sent - answered >= u64::from(max_missed.max(1)) {
+                    return;
+                }
+                sent += 1;
+                let ping = protocol.encode(&ServerMessage::Ping { nonce: sent });
+                send_to_client(clients.clone(), client_id, ping.into()).await;
+            }
+            pong = pongs.recv() => match pong {
+                // Pongs for pings never sent or already answered prove nothing
+                Some(nonce) if nonce > answered && nonce <= sent => answered = nonce,
+                Some(_) => {}
+                None => return,
+            },
+        }
+    }
+}
+
+/// The notice sent to a client that is disconnected for being idle too long.
+pub const IDLE_TIMEOUT_NOTICE: &str = "[Server] Connection timed out due to inactivity";
+
+/// The notice sent to a client right before an admin disconnects it.
+pub const KICKED_NOTICE: &str = "You have been kicked by an admin";
+
+/// Builds the MOTD messages for a client, one per line of the configured MOTD.
+///
+/// # Arguments
+/// - `config`: The server settings holding the MOTD and server name.
+/// - `client_count`: The number of connected clients, including this one.
+/// - `client_id`: The ID of the client receiving the MOTD.
+///
+/// # Returns
+/// The rendered lines, or an empty list if no MOTD is configured.
+fn motd_messages(
+    config: &ServerConfig,
+    client_count: usize,
+    client_id: usize,
+) -> Vec<ServerMessage> {
+    let Some(motd) = &config.motd else {
+        return Vec::new();
+    };
+
+    let rendered = motd
+        .replace("{server_name}", &config.server_name)
+        .replace("{client_count}", &client_count.to_string())
+        .replace("{client_id}", &client_id.to_string());
+    rendered
+        .lines()
+        .map(|line| ServerMessage::System {
+            text: line.to_string(),
+        })
+        .collect()
+}
+
+/// The notice confirming `/echo off`, after which a client's own messages are no
+/// longer sent back to it.
+pub const ECHO_OFF_NOTICE: &str = "[Echo] Your messages are no longer sent back to you";
+
+/// The notice confirming `/echo on`.
+pub const ECHO_ON_NOTICE: &str = "[Echo] Your messages are sent back to you";
+
+/// The notice sent to a client when its mute expires.
+pub const UNMUTED_NOTICE: &str = "[Unmuted] You can send messages again";
+
+/// Records a message sent now and reports whether the sender is flooding.
+///
+/// `history` holds the send times of the client's recent messages. Entries older
+/// than `window` are dropped before the new one is added.
+///
+/// # Arguments
+/// - `history`: The client's recent send times, oldest first.
+/// - `limit`: The number of messages allowed within the window.
+/// - `window`: The length of the sliding window.
+///
+/// # Returns
+/// `true` if more than `limit` messages were sent within `window`.
+pub fn check_flood(history: &mut VecDeque<Instant>, limit: usize, window: Duration) -> bool {
+    let now = Instant::now();
+    while history
+        .front()
+        .is_some_and(|sent| now.duration_since(*sent) >= window)
+    {
+        history.pop_front();
+    }
+    history.push_back(now);
+    history.len() > limit
+}
+
+/// The rejection for a message sent by a client muted until `until`.
+fn muted(until: Instant) -> RejectionReason {
+    let left = until.saturating_duration_since(Instant::now());
+    RejectionReason::Muted {
+        seconds: left.as_millis().div_ceil(1000) as u64,
+    }
+}
+
+/// A single message read from a client.
+#[derive(Debug, PartialEq, Eq)]
+enum Incoming {
+    /// A message that passed the server's checks, without its line terminator.
+    Message(String),
+    /// A message the server refuses to process.
+    Rejected(RejectionReason),
+}
+
+/// A message read from a client, with the ID its author gave it.
+#[derive(Debug, PartialEq, Eq)]
+struct Received {
+    incoming: Incoming,
+    /// The [`ClientMessage::id`] of a JSON client's message, echoed in its rejection.
+    id: Option<String>,
+}
+
+impl From<Incoming> for Received {
+    fn from(incoming: Incoming) -> Self {
+        Received { incoming, id: None }
     }
 }
 
-/// Handles an individual client connection.
+/// Reads the next message from a client using the configured protocol.
 ///
-/// This function processes client messages and determines whether they should be
-/// broadcast to all clients or sent privately to a specific client. It also removes
-/// the client from the shared list upon disconnection.
+/// Text clients send one message per line; JSON clients send one message per frame,
+/// so their messages may contain newlines, optionally wrapped in a [`ClientMessage`].
 ///
 /// # Arguments
-/// - `reader`: A read handle for the client connection.
-/// - `writer`: A write handle for the client connection.
-/// - `clients`: A shared collection of all connected clients.
-/// - `client_id`: A unique identifier for the client.
-async fn handle_connection(
-    reader: tokio::net::tcp::OwnedReadHalf,
-    writer: tokio::net::tcp::OwnedWriteHalf,
-    clients: SharedClients,
-    client_id: usize,
-) {
-    let mut buf_reader = BufReader::new(reader);
-    let mut line = String::new();
-
-    {
-        // Add the client to the shared list
-        clients.lock().await.push(writer);
-    }
+/// - `reader`: The buffered read handle for the client connection.
+/// - `config`: The server settings, including the protocol and message limits.
+///
+/// # Returns
+/// - `Ok(Some(received))` for every message received, accepted or rejected.
+/// - `Ok(None)` once the client has disconnected.
+///
+/// # Errors
+/// Returns an error if reading from the connection fails. Invalid UTF-8 is rejected
+/// like any other bad message rather than treated as an error.
+async fn read_incoming<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    config: &ServerConfig,
+) -> std::io::Result<Option<Received>> {
+    // Both readers stop buffering at the limit, so oversized input never piles up in memory
+    let frame = match config.protocol {
+        Protocol::Text => read_line_bounded(reader, config.max_message_bytes).await?,
+        Protocol::Json => read_frame(reader, max_frame_len(config.max_message_bytes)).await?,
+    };
 
-    while let Ok(bytes_read) = buf_reader.read_line(&mut line).await {
-        if bytes_read == 0 {
-            break; // Client disconnected
+    let message = match frame {
+        None => return Ok(None),
+        Some(Frame::TooLong(_)) => {
+            let reason = RejectionReason::TooLong {
+                max_bytes: config.max_message_bytes,
+            };
+            return Ok(Some(Incoming::Rejected(reason).into()));
         }
+        Some(Frame::Data(payload)) => match String::from_utf8(payload) {
+            Ok(message) => message,
+            // The frame has already been consumed whole, so the client can carry on
+            Err(_) => {
+                return Ok(Some(
+                    Incoming::Rejected(RejectionReason::InvalidUtf8).into(),
+                ))
+            }
+        },
+    };
+    let ClientMessage { id, body } = match config.protocol {
+        Protocol::Text => ClientMessage {
+            id: None,
+            body: message,
+        },
+        Protocol::Json => ClientMessage::parse(message),
+    };
 
-        let trimmed_line = line.trim();
-        if let Some((target_id, private_msg)) = parse_private_message(trimmed_line) {
-            let message = format!("[Private] Client {}: {}", client_id, private_msg);
-            println!(
-                "Private message from Client {} to Client {}: {}",
-                client_id, target_id, private_msg
-            );
-
-            send_private_message(clients.clone(), target_id, &message).await;
-        } else {
-            let message = format!("Client {}: {}", client_id, trimmed_line);
-            println!("{}", message);
-
-            broadcast_message(clients.clone(), &message).await;
-        }
+    let incoming = match check_message(&body, config) {
+        Some(reason) => Incoming::Rejected(reason),
+        None => Incoming::Message(body),
+    };
+    Ok(Some(Received { incoming, id }))
+}
 
-        line.clear();
-    }
+/// The bytes a JSON client's frame may use beyond twice the message limit, for the
+/// [`ClientMessage`] wrapper and its ID.
+const CLIENT_MESSAGE_OVERHEAD: usize = 256;
 
-    println!("Client {} disconnected.", client_id);
+/// The largest frame a JSON client may send when messages are limited to
+/// `max_message_bytes`.
+///
+/// It leaves room for a [`ClientMessage`] wrapping a body at the limit, even if
+/// escaping doubles its size; [`check_message`] still holds every body to the limit.
+fn max_frame_len(max_message_bytes: usize) -> usize {
+    max_message_bytes
+        .saturating_mul(2)
+        .saturating_add(CLIENT_MESSAGE_OVERHEAD)
 }
 
-/// Parses a private message command.
-///
-/// This function interprets a message with the `/msg` command format.
-/// Valid commands are of the format `/msg <client_id> <message>`.
+/// Checks an incoming message against the server's limits.
 ///
 /// # Arguments
-/// - `input`: The command string to parse.
+/// - `message`: The message read from the client, without any line terminator.
+/// - `config`: The server limits to enforce.
 ///
 /// # Returns
-/// - `Some((client_id, message))` if the input is valid.
-/// - `None` if the input is invalid.
-///
-/// # Example
-/// ```
-/// let result = parse_private_message("/msg 2 Hello!");
-/// assert_eq!(result, Some((2, "Hello!")));
-/// ```
-fn parse_private_message(input: &str) -> Option<(usize, &str)> {
-    if input.starts_with("/msg ") {
-        let parts: Vec<&str> = input.splitn(3, ' ').collect();
-        if parts.len() == 3 {
-            if let Ok(target_id) = parts[1].parse::<usize>() {
-                return Some((target_id, parts[2]));
-            }
-        }
+/// - `Some(reason)` if the message must not be delivered.
+/// - `None` if the message may be processed.
+fn check_message(message: &str, config: &ServerConfig) -> Option<RejectionReason> {
+    // Reject oversized messages instead of relaying them to everyone
+    if message.len() > config.max_message_bytes {
+        return Some(RejectionReason::TooLong {
+            max_bytes: config.max_message_bytes,
+        });
     }
     None
 }
 
 /// Sends a private message to a specific client.
 ///
-/// Retrieves the specified client by ID and sends the provided message. If the client
-/// does not exist or the message fails to send, it logs an error.
+/// Retrieves the specified client by ID and queues the provided message, without
+/// waiting for room in its queue.
 ///
 /// # Arguments
 /// - `clients`: A shared collection of all connected clients.
 /// - `target_id`: The ID of the target client.
-/// - `message`: The message to send.
+/// - `message`: The encoded message to send.
 ///
 /// # Errors
-/// Logs an error if the client does not exist or the message fails to send.
-async fn send_private_message(clients: SharedClients, target_id: usize, message: &str) {
-    let mut clients = clients.lock().await;
-    if let Some(writer) = clients.get_mut(target_id - 1) {
-        if writer
-            .write_all(format!("{}\n", message).as_bytes())
-            .await
-            .is_err()
-        {
-            println!("Failed to send private message to Client {}", target_id);
+/// Returns the reason to give the sender if the client has disconnected, including
+/// since it was looked up, or its queue is full. A warning is logged as well.
+async fn send_private_message(
+    clients: SharedClients,
+    target_id: usize,
+    message: Payload,
+) -> Result<(), String> {
+    let clients = clients.read().await;
+    let reason = match clients
+        .get(&target_id)
+        .map(|sender| sender.try_send(message))
+    {
+        Some(Ok(())) => return Ok(()),
+        Some(Err(TrySendError::Full(_))) => {
+            format!(
+                "Client {} is not keeping up; message not delivered",
+                target_id
+            )
         }
-    } else {
-        println!("Client {} not found.", target_id);
+        Some(Err(TrySendError::Closed(_))) | None => {
+            format!("Client {} is not connected", target_id)
+        }
+    };
+    tracing::warn!("Failed to send private message: {}", reason);
+    Err(reason)
+}
+
+/// Sends a single message to one client, such as an error reply.
+///
+/// Failures are ignored: if the client is gone, its connection task cleans up, and if
+/// its queue is full, the message is dropped as in [`broadcast_message`].
+///
+/// # Arguments
+/// - `clients`: A shared collection of all connected clients.
+/// - `client_id`: The ID of the client to write to.
+/// - `message`: The encoded message to send.
+async fn send_to_client(clients: SharedClients, client_id: usize, message: Payload) {
+    let clients = clients.read().await;
+    if let Some(sender) = clients.get(&client_id) {
+        let _ = sender.try_send(message);
     }
 }
 
-/// Broadcasts a message to all connected clients.
+/// Broadcasts a message to the members of a room, or to all connected clients.
+///
+/// Queues the message for every recipient in the shared list under a read guard,
+/// without ever waiting for a client.
 ///
-/// Sends the message to every client in the shared list. If a client
-/// is unreachable, it is removed from the list.
+/// # Drop Policy
+/// If a client's queue is full because it is not reading fast enough, the message is
+/// not queued for that client, and the client is reported in the returned list so the
+/// caller can apply the configured [`SlowClientPolicy`] (see [`handle_overflows`]).
+/// The queue's last slot is never used for broadcasts, so a final notice still fits.
+/// If a client's writer task has stopped because writing to it failed, it is removed
+/// from the list by client ID.
 ///
 /// # Arguments
 /// - `clients`: A shared collection of all connected clients.
-/// - `message`: The message to broadcast.
-async fn broadcast_message(clients: SharedClients, message: &str) {
+/// - `rooms`: The open rooms and their members.
+/// - `room`: The room to broadcast to, or `None` for every connected client.
+/// - `except`: A client to leave out, such as a sender with `/echo off`.
+/// - `message`: The encoded message to broadcast.
+///
+/// # Returns
+/// The IDs of the clients whose queues were full.
+async fn broadcast_message(
+    clients: SharedClients,
+    rooms: &SharedRooms,
+    room: Option<&str>,
+    except: Option<usize>,
+    message: Payload,
+) -> Vec<usize> {
+    let members = match room {
+        Some(room) => match rooms.lock().await.get(room) {
+            Some(room) => Some(room.members.clone()),
+            None => return Vec::new(),
+        },
+        None => None,
+    };
+
     let mut clients_to_remove = Vec::new();
+    let mut overflowed = Vec::new();
     {
-        let mut clients = clients.lock().await;
-        for (index, writer) in clients.iter_mut().enumerate() {
-            if writer
-                .write_all(format!("{}\n", message).as_bytes())
-                .await
-                .is_err()
-            {
-                clients_to_remove.push(index);
+        let clients = clients.read().await;
+        let recipients = clients
+            .iter()
+            .filter(|(id, _)| members.as_ref().is_none_or(|members| members.contains(id)))
+            .filter(|(id, _)| except != Some(**id));
+        for (&client_id, sender) in recipients {
+            if sender.is_closed() {
+                clients_to_remove.push(client_id);
+            } else if sender.capacity() <= 1 {
+                overflowed.push(client_id);
+            } else if let Err(TrySendError::Closed(_)) = sender.try_send(message.clone()) {
+                clients_to_remove.push(client_id);
             }
         }
     }
 
     // Remove disconnected clients
-    let mut clients = clients.lock().await;
-    for &index in clients_to_remove.iter().rev() {
-        clients.remove(index);
+    if !clients_to_remove.is_empty() {
+        let mut clients = clients.write().await;
+        for client_id in clients_to_remove {
+            if clients.remove(&client_id).is_some() {
+                tracing::debug!("Removed Client {}, whose connection is closed", client_id);
+            }
+        }
+    }
+    overflowed
+}
+
+/// Applies [`ServerConfig::slow_client_policy`] to clients whose queues overflowed.
+///
+/// Every overflow is counted in [`ServerStats::queue_overflows`] and reported as a
+/// [`ServerEvent`].
+async fn handle_overflows(
+    clients: &SharedClients,
+    config: &ServerConfig,
+    events: &EventBus,
+    stats: &ServerStats,
+    overflowed: Vec<usize>,
+) {
+    for id in overflowed {
+        stats.queue_overflows.fetch_add(1, Ordering::Relaxed);
+        match config.slow_client_policy {
+            SlowClientPolicy::DropNewest => events.emit(ServerEvent::MessageDropped { id }),
+            SlowClientPolicy::Disconnect => {
+                let Some(sender) = clients.write().await.remove(&id) else {
+                    continue;
+                };
+                events.emit(ServerEvent::ClientTooSlow { id });
+                let notice = config.protocol.encode(&ServerMessage::System {
+                    text: TOO_SLOW_NOTICE.to_string(),
+                });
+                // The slot kept free by broadcast_message leaves room for the notice
+                let _ = sender.try_send(notice.into());
+            }
+        }
+    }
+}
+
+/// Creates a client's send queue, with room for `capacity` messages plus one slot that
+/// broadcasts leave free for a final notice.
+fn client_queue(capacity: usize) -> (ClientSender, mpsc::Receiver<Payload>) {
+    mpsc::channel(capacity + 1)
+}
+
+/// Writes queued messages to a client until every sender for its queue is dropped.
+///
+/// Messages are collected in a `BufWriter` and flushed together: once the queue is
+/// empty and `flush_delay` has passed since the first unflushed message, or as soon as
+/// the queue is empty if `flush_delay` is zero. A burst of messages therefore costs
+/// a few large writes instead of one small write per message, and no message waits
+/// longer than `flush_delay` before it is flushed.
+///
+/// Afterwards the write half is flushed and shut down, so the client sees EOF. If a
+/// write fails, the task closes the queue at once, so the next broadcast sees the
+/// client is gone (see [`broadcast_message`]), and shuts the write half down without
+/// flushing what is left.
+///
+/// # Arguments
+/// - `writer`: The write half of the client connection.
+/// - `queue`: The receiving end of the client's [`ClientSender`].
+/// - `flush_delay`: How long to wait for more messages before flushing.
+/// - `stats`: The counters the written bytes are added to.
+async fn write_outgoing<W: AsyncWrite + Unpin>(
+    writer: W,
+    mut queue: mpsc::Receiver<Payload>,
+    flush_delay: Duration,
+    stats: Arc<ServerStats>,
+) {
+    let mut writer = BufWriter::new(writer);
+    match write_queued(&mut writer, &mut queue, flush_delay, &stats).await {
+        Ok(()) => {
+            let _ = writer.shutdown().await;
+        }
+        Err(e) => {
+            tracing::debug!("Failed to write to client: {}", e);
+            queue.close();
+            // The buffered messages cannot be flushed, so skip the BufWriter
+            let _ = writer.get_mut().shutdown().await;
+        }
+    }
+}
+
+/// Writes queued messages to `writer` until every sender for the queue is dropped.
+///
+/// # Errors
+/// Returns the first error from writing or flushing.
+async fn write_queued<W: AsyncWrite + Unpin>(
+    writer: &mut BufWriter<W>,
+    queue: &mut mpsc::Receiver<Payload>,
+    flush_delay: Duration,
+    stats: &ServerStats,
+) -> std::io::Result<()> {
+    while let Some(first) = queue.recv().await {
+        let mut deadline = Instant::now() + flush_delay;
+        let mut next = Some(first);
+        while let Some(message) = next {
+            writer.write_all(&message).await?;
+            stats.bytes_written(message.len());
+            next = match queue.try_recv() {
+                Ok(message) if Instant::now() < deadline => Some(message),
+                Ok(message) => {
+                    // Keep the latency bound even while messages keep arriving
+                    writer.flush().await?;
+                    deadline = Instant::now() + flush_delay;
+                    Some(message)
+                }
+                Err(_) if flush_delay.is_zero() => None,
+                Err(_) => tokio::time::timeout_at(deadline, queue.recv())
+                    .await
+                    .ok()
+                    .flatten(),
+            };
+        }
+        writer.flush().await?;
     }
+    Ok(())
 }
 
 /// Tests for the server module.
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio::net::TcpStream;
+    use crate::protocol::split_timestamp;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, DuplexStream, ReadHalf, WriteHalf},
+        net::TcpStream,
+    };
 
-    #[tokio::test]
-    async fn test_parse_private_message() {
-        // Valid private message
-        let input = "/msg 2 Hello, Client 2!";
-        let result = parse_private_message(input);
-        assert_eq!(result, Some((2, "Hello, Client 2!")));
+    /// Returns the state of a server with `clients`, all in the default room, and
+    /// `config` and nothing else.
+    async fn test_state(clients: &SharedClients, config: Arc<ServerConfig>) -> ServerState {
+        let rooms = SharedRooms::default();
+        for &id in clients.read().await.keys() {
+            add_member(&mut *rooms.lock().await, DEFAULT_ROOM, id);
+        }
+        ServerState {
+            clients: clients.clone(),
+            admins: SharedAdmins::default(),
+            rooms,
+            nicknames: SharedNicknames::default(),
+            client_info: SharedClientInfo::default(),
+            config,
+            events: EventBus::default(),
+            stats: Arc::default(),
+            admitting: Arc::default(),
+        }
+    }
 
-        // Invalid private message (missing client ID)
-        let invalid_input = "/msg Hello, Client!";
-        let invalid_result = parse_private_message(invalid_input);
-        assert_eq!(invalid_result, None);
+    /// Registers `writer` as client `client_id`, with its own writer task.
+    async fn register<W: AsyncWrite + Unpin + Send + 'static>(
+        clients: &SharedClients,
+        client_id: usize,
+        writer: W,
+    ) {
+        let (tx, rx) = client_queue(SEND_QUEUE_CAPACITY);
+        tokio::spawn(write_outgoing(writer, rx, Duration::ZERO, Arc::default()));
+        clients.write().await.insert(client_id, tx);
+    }
+
+    #[test]
+    fn test_format_uptime() {
+        assert_eq!(format_uptime(Duration::from_millis(4500)), "4s");
+        assert_eq!(format_uptime(Duration::from_secs(125)), "2m 5s");
+        assert_eq!(format_uptime(Duration::from_secs(3605)), "1h 0m 5s");
+        assert_eq!(format_uptime(Duration::from_secs(90_061)), "1d 1h 1m 1s");
+    }
+
+    #[test]
+    fn test_client_listing() {
+        let clients = vec![
+            ListedClient {
+                id: 7,
+                nick: None,
+                room: "general",
+                connected: Duration::from_secs(5),
+            },
+            ListedClient {
+                id: 3,
+                nick: None,
+                room: "rust",
+                connected: Duration::from_secs(60),
+            },
+            ListedClient {
+                id: 2,
+                nick: Some("alice"),
+                room: "general",
+                connected: Duration::from_secs(125),
+            },
+        ];
+        assert_eq!(
+            client_listing(clients),
+            [
+                "3 clients in 2 rooms:",
+                "  general (2):",
+                "    Client 2 (alice), connected 2m 5s",
+                "    Client 7, connected 5s",
+                "  rust (1):",
+                "    Client 3, connected 1m 0s",
+            ]
+        );
+        assert_eq!(client_listing(Vec::new()), ["0 clients in 0 rooms:"]);
+    }
+
+    #[test]
+    fn test_identity_lines() {
+        let info = ClientInfo {
+            peer: Peer::Local(PathBuf::from("/tmp/chat.sock")),
+            joined_at: Instant::now(),
+            last_private_from: None,
+            public_key: None,
+            echo: true,
+            disconnect: watch::channel(false).0,
+        };
+        assert_eq!(
+            identity_lines(3, None, &info),
+            [
+                "You are Client 3 (no nick)",
+                "  Address: /tmp/chat.sock (local)",
+                "  Connected: 0s",
+            ]
+        );
+        let info = ClientInfo {
+            peer: Peer::Tcp("[::1]:4000".parse().unwrap()),
+            ..info
+        };
+        assert_eq!(
+            identity_lines(3, Some("alice"), &info)[..2],
+            ["You are Client 3 (alice)", "  Address: [::1]:4000"]
+        );
+    }
+
+    #[test]
+    fn test_peak_connections_outlast_departures() {
+        let stats = ServerStats::default();
+        stats.client_joined();
+        stats.client_joined();
+        stats.client_left();
+        stats.client_joined();
+        assert_eq!(stats.current_connections(), 2);
+        assert_eq!(stats.peak_connections(), 2);
+    }
+
+    #[test]
+    fn test_rejections_are_counted_by_reason() {
+        let stats = ServerStats::default();
+        stats.message_rejected(RejectionReason::RateLimited);
+        stats.message_rejected(RejectionReason::RateLimited);
+        stats.message_rejected(RejectionReason::Muted { seconds: 5 });
+        assert_eq!(stats.messages_rejected("rate_limited"), 2);
+        assert_eq!(stats.messages_rejected("muted"), 1);
+        assert_eq!(stats.messages_rejected("too_long"), 0);
+        assert_eq!(stats.messages_rejected("unknown"), 0);
+        assert_eq!(
+            stats.summary().last().unwrap(),
+            "  Rejected: too_long 0, rate_limited 2, invalid_utf8 0, muted 1"
+        );
+    }
+
+    /// Opens an in-memory connection, returning the client's end and the server's
+    /// read and write halves.
+    fn duplex_connection() -> (
+        DuplexStream,
+        ReadHalf<DuplexStream>,
+        WriteHalf<DuplexStream>,
+    ) {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (reader, writer) = tokio::io::split(server);
+        (client, reader, writer)
+    }
+
+    #[tokio::test]
+    async fn test_ipv4_and_ipv6_wildcards_share_a_port() {
+        // Find a free port, then bind both wildcards to it
+        let port = std::net::TcpListener::bind("0.0.0.0:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let listeners = bind_listeners(&format!("0.0.0.0:{port}, [::]:{port}"))
+            .await
+            .unwrap();
+        assert_eq!(listeners.len(), 2);
 
-        // Invalid private message (missing command prefix)
-        let invalid_input = "msg 2 Hello!";
-        let invalid_result = parse_private_message(invalid_input);
-        assert_eq!(invalid_result, None);
+        let v4 = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let (_, from) = accept_any(&listeners).await.unwrap();
+        assert_eq!(from, v4.local_addr().unwrap());
+        let v6 = TcpStream::connect(("::1", port)).await.unwrap();
+        let (_, from) = accept_any(&listeners).await.unwrap();
+        assert_eq!(from, v6.local_addr().unwrap());
     }
 
     #[tokio::test]
@@ -260,17 +3590,45 @@ mod tests {
         let (_reader, writer) = socket.into_split();
 
         // Add the writer to the clients list
-        clients.lock().await.push(writer);
+        register(&clients, 1, writer).await;
 
         // Test sending a private message
         let message = "[Private] Client 1: Hello!";
-        send_private_message(clients.clone(), 1, message).await;
+        send_private_message(
+            clients.clone(),
+            1,
+            format!("{}\n", message).as_bytes().into(),
+        )
+        .await
+        .unwrap();
 
         // Assert that the client received the correct private message
         let received_message = client.await.unwrap();
         assert_eq!(received_message.trim(), message);
     }
 
+    #[tokio::test]
+    async fn test_failed_private_message_is_explained() {
+        let clients = SharedClients::default();
+        let message: Payload = b"[Private] Client 1: hi\n".as_slice().into();
+
+        // Client 2 has a full queue, and client 3's writer task has stopped
+        let (tx, _full) = client_queue(1);
+        while tx.try_send(message.clone()).is_ok() {}
+        clients.write().await.insert(2, tx);
+        let (tx, closed) = client_queue(1);
+        drop(closed);
+        clients.write().await.insert(3, tx);
+
+        let send = |id| send_private_message(clients.clone(), id, message.clone());
+        assert_eq!(
+            send(2).await.unwrap_err(),
+            "Client 2 is not keeping up; message not delivered"
+        );
+        assert_eq!(send(3).await.unwrap_err(), "Client 3 is not connected");
+        assert_eq!(send(4).await.unwrap_err(), "Client 4 is not connected");
+    }
+
     #[tokio::test]
     async fn test_broadcast_message() {
         let clients = SharedClients::default();
@@ -299,15 +3657,22 @@ mod tests {
         });
 
         // Accept two client connections and add their writers to the shared list
-        for _ in 0..2 {
+        for client_id in 1..=2 {
             let (socket, _) = listener.accept().await.unwrap();
             let (_reader, writer) = socket.into_split();
-            clients.lock().await.push(writer);
+            register(&clients, client_id, writer).await;
         }
 
         // Broadcast a message
         let message = "Hello, everyone!";
-        broadcast_message(clients.clone(), message).await;
+        broadcast_message(
+            clients.clone(),
+            &SharedRooms::default(),
+            None,
+            None,
+            format!("{}\n", message).as_bytes().into(),
+        )
+        .await;
 
         // Assert that both clients received the broadcast message
         let response1 = client1.await.unwrap();
@@ -315,4 +3680,698 @@ mod tests {
         assert_eq!(response1.trim(), message);
         assert_eq!(response2.trim(), message);
     }
+
+    #[tokio::test]
+    async fn test_oversized_message_is_rejected() {
+        let clients = SharedClients::default();
+        let config = Arc::new(ServerConfig::default());
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        // Connect the sender (Client 1) and a bystander (Client 2)
+        let (sender, sender_reader, sender_writer) = duplex_connection();
+        let (bystander, _bystander_reader, bystander_writer) = duplex_connection();
+        register(&clients, 1, sender_writer).await;
+        register(&clients, 2, bystander_writer).await;
+
+        tokio::spawn(handle_connection(
+            BufReader::new(sender_reader),
+            test_state(&clients, config).await,
+            1,
+            watch::channel(false).1,
+            shutdown_rx,
+        ));
+
+        // Send a 5000-byte message followed by a normal one
+        let (sender_read, mut sender_write) = tokio::io::split(sender);
+        let oversized = format!("{}\n", "a".repeat(5000));
+        sender_write.write_all(oversized.as_bytes()).await.unwrap();
+        sender_write.write_all(b"hello\n").await.unwrap();
+
+        // The sender gets the error, then the echo of the normal message
+        let mut sender_lines = BufReader::new(sender_read).lines();
+        assert_eq!(
+            sender_lines.next_line().await.unwrap().unwrap(),
+            "[Rejected: too_long] Message too long (max 4096 bytes)"
+        );
+        let echo = sender_lines.next_line().await.unwrap().unwrap();
+        assert_eq!(split_timestamp(&echo).1, "Client 1: hello");
+
+        // The bystander never sees the oversized message
+        let mut bystander_lines = BufReader::new(bystander).lines();
+        let line = bystander_lines.next_line().await.unwrap().unwrap();
+        assert_eq!(split_timestamp(&line).1, "Client 1: hello");
+    }
+
+    #[test]
+    fn test_motd_messages() {
+        let config = ServerConfig {
+            server_name: "Test Server".to_string(),
+            motd: Some(
+                "Welcome to {server_name}!\nYou are Client {client_id} of {client_count}."
+                    .to_string(),
+            ),
+            ..ServerConfig::default()
+        };
+        assert_eq!(
+            motd_messages(&config, 3, 2),
+            vec![
+                ServerMessage::System {
+                    text: "Welcome to Test Server!".to_string()
+                },
+                ServerMessage::System {
+                    text: "You are Client 2 of 3.".to_string()
+                },
+            ]
+        );
+
+        assert!(motd_messages(&ServerConfig::default(), 1, 1).is_empty());
+    }
+
+    #[test]
+    fn test_check_flood() {
+        let window = Duration::from_secs(1);
+        let mut history = VecDeque::new();
+
+        // Up to the limit is fine, one more is flooding
+        for _ in 0..3 {
+            assert!(!check_flood(&mut history, 3, window));
+        }
+        assert!(check_flood(&mut history, 3, window));
+
+        // Messages older than the window no longer count
+        let old = Instant::now() - Duration::from_secs(2);
+        let mut history = VecDeque::from(vec![old; 5]);
+        assert!(!check_flood(&mut history, 3, window));
+        assert_eq!(history.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_connection_says_goodbye() {
+        let clients = SharedClients::default();
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        let (client, reader, writer) = duplex_connection();
+        register(&clients, 1, writer).await;
+
+        let connection = tokio::spawn(handle_connection(
+            BufReader::new(reader),
+            test_state(&clients, Arc::new(ServerConfig::default())).await,
+            1,
+            watch::channel(false).1,
+            shutdown_rx,
+        ));
+
+        // The client is still connected and silent when the connection is cancelled
+        shutdown_tx.send(true).unwrap();
+        connection.await.unwrap();
+        assert!(clients.read().await.is_empty());
+
+        let mut lines = BufReader::new(client).lines();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), SHUTDOWN_NOTICE);
+        assert_eq!(lines.next_line().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_serve_connection_over_any_stream() {
+        let clients = SharedClients::default();
+        let state = test_state(&clients, Arc::new(ServerConfig::default())).await;
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        // Any reader and writer will do; here both halves of an in-memory pipe
+        let (client, reader, writer) = duplex_connection();
+        let peer = Peer::Tcp(([127, 0, 0, 1], 4000).into());
+        let admission = Admission::start(&state.admitting);
+        let connection = tokio::spawn(serve_connection(
+            reader,
+            writer,
+            state,
+            1,
+            peer,
+            shutdown_rx,
+            admission,
+        ));
+
+        let (client_reader, mut client_writer) = tokio::io::split(client);
+        let mut lines = BufReader::new(client_reader).lines();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "Your ID: 1");
+        client_writer.write_all(b"hello\n").await.unwrap();
+        let echo = lines.next_line().await.unwrap().unwrap();
+        assert_eq!(split_timestamp(&echo).1, "Client 1: hello");
+
+        // Hanging up ends the connection and unregisters the client
+        client_writer.shutdown().await.unwrap();
+        assert_eq!(lines.next_line().await.unwrap(), None);
+        connection.await.unwrap();
+        assert!(clients.read().await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_client_is_disconnected() {
+        let clients = SharedClients::default();
+        let config = Arc::new(ServerConfig {
+            idle_timeout: Some(Duration::from_secs(60)),
+            heartbeat_interval: None,
+            ..ServerConfig::default()
+        });
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        let (client, reader, writer) = duplex_connection();
+        register(&clients, 1, writer).await;
+
+        let start = Instant::now();
+        let connection = tokio::spawn(handle_connection(
+            BufReader::new(reader),
+            test_state(&clients, config).await,
+            1,
+            watch::channel(false).1,
+            shutdown_rx,
+        ));
+
+        // The paused clock jumps ahead while the client stays silent
+        let mut lines = BufReader::new(client).lines();
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            IDLE_TIMEOUT_NOTICE
+        );
+        assert_eq!(lines.next_line().await.unwrap(), None);
+        assert!(start.elapsed() >= Duration::from_secs(60));
+
+        connection.await.unwrap();
+        assert!(clients.read().await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_timeout_can_be_disabled() {
+        let clients = SharedClients::default();
+        let config = Arc::new(ServerConfig {
+            idle_timeout: None,
+            heartbeat_interval: None,
+            ..ServerConfig::default()
+        });
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        let (_client, reader, writer) = duplex_connection();
+        register(&clients, 1, writer).await;
+
+        let connection = tokio::spawn(handle_connection(
+            BufReader::new(reader),
+            test_state(&clients, config).await,
+            1,
+            watch::channel(false).1,
+            shutdown_rx,
+        ));
+
+        // A silent day later the client is still connected
+        tokio::time::sleep(Duration::from_secs(24 * 60 * 60)).await;
+        assert!(!connection.is_finished());
+        assert!(clients.read().await.contains_key(&1));
+        connection.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_client_that_never_answers_pings_is_reaped() {
+        let clients = SharedClients::default();
+        let config = Arc::new(ServerConfig {
+            idle_timeout: None,
+            heartbeat_interval: Some(Duration::from_secs(30)),
+            ..ServerConfig::default()
+        });
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        let (client, reader, writer) = duplex_connection();
+        register(&clients, 1, writer).await;
+
+        let start = Instant::now();
+        let connection = tokio::spawn(handle_connection(
+            BufReader::new(reader),
+            test_state(&clients, config).await,
+            1,
+            watch::channel(false).1,
+            shutdown_rx,
+        ));
+
+        // The pipe stays open but nothing ever answers the ping
+        let mut lines = BufReader::new(client).lines();
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            ServerMessage::Ping { nonce: 1 }.to_string()
+        );
+        assert_eq!(lines.next_line().await.unwrap(), None);
+        assert!(start.elapsed() >= Duration::from_secs(60));
+
+        connection.await.unwrap();
+        assert!(clients.read().await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_heartbeat_replies_count_as_activity() {
+        let clients = SharedClients::default();
+        let config = Arc::new(ServerConfig {
+            idle_timeout: Some(Duration::from_secs(60)),
+            heartbeat_interval: Some(Duration::from_secs(30)),
+            ..ServerConfig::default()
+        });
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        let (client, reader, writer) = duplex_connection();
+        register(&clients, 1, writer).await;
+
+        let connection = tokio::spawn(handle_connection(
+            BufReader::new(reader),
+            test_state(&clients, config).await,
+            1,
+            watch::channel(false).1,
+            shutdown_rx,
+        ));
+
+        // The client answers every ping and sends nothing else
+        let (client_reader, mut client_writer) = tokio::io::split(client);
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(client_reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let nonce = crate::protocol::parse_ping(&line).unwrap();
+                let reply = format!("{}\n", crate::protocol::pong(nonce));
+                client_writer.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+
+        tokio::time::sleep(Duration::from_secs(10 * 60)).await;
+        assert!(!connection.is_finished());
+        assert!(clients.read().await.contains_key(&1));
+        connection.abort();
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_reaches_only_the_room() {
+        let clients = SharedClients::default();
+        let rooms = SharedRooms::default();
+        let mut queues = Vec::new();
+        for (id, room) in [(1, DEFAULT_ROOM), (2, "rust"), (3, "rust")] {
+            let (tx, rx) = client_queue(SEND_QUEUE_CAPACITY);
+            clients.write().await.insert(id, tx);
+            add_member(&mut *rooms.lock().await, room, id);
+            queues.push(rx);
+        }
+
+        let message: Payload = b"Client 2: hi\n".as_slice().into();
+        broadcast_message(clients.clone(), &rooms, Some("rust"), None, message.clone()).await;
+        assert!(queues[0].try_recv().is_err());
+        assert_eq!(queues[1].try_recv().unwrap(), message);
+        assert_eq!(queues[2].try_recv().unwrap(), message);
+
+        // A sender with /echo off is left out of its own room
+        broadcast_message(
+            clients.clone(),
+            &rooms,
+            Some("rust"),
+            Some(2),
+            message.clone(),
+        )
+        .await;
+        assert!(queues[1].try_recv().is_err());
+        assert_eq!(queues[2].try_recv().unwrap(), message);
+
+        // A room nobody is in reaches nobody, while no room reaches everyone
+        broadcast_message(
+            clients.clone(),
+            &rooms,
+            Some("empty"),
+            None,
+            message.clone(),
+        )
+        .await;
+        assert!(queues.iter_mut().all(|queue| queue.try_recv().is_err()));
+        broadcast_message(clients.clone(), &rooms, None, None, message.clone()).await;
+        assert!(queues
+            .iter_mut()
+            .all(|queue| queue.try_recv().unwrap() == message));
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_removes_client_whose_write_failed() {
+        let clients = SharedClients::default();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Clients 1 and 2 read two messages each
+        let readers: Vec<_> = (1..=2)
+            .map(|_| {
+                tokio::spawn(async move {
+                    let stream = TcpStream::connect(addr).await.unwrap();
+                    let mut lines = BufReader::new(stream).lines();
+                    let first = lines.next_line().await.unwrap().unwrap();
+                    let second = lines.next_line().await.unwrap().unwrap();
+                    (first, second)
+                })
+            })
+            .collect();
+        for client_id in 1..=2 {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (_reader, writer) = socket.into_split();
+            register(&clients, client_id, writer).await;
+        }
+
+        // Client 3 has closed its end, so the first write to it fails
+        let (writer, closed) = tokio::io::duplex(64);
+        drop(closed);
+        register(&clients, 3, writer).await;
+
+        let rooms = SharedRooms::default();
+        let broadcast = |text: &str| {
+            broadcast_message(clients.clone(), &rooms, None, None, text.as_bytes().into())
+        };
+        assert!(broadcast("first\n").await.is_empty());
+        // The writer task closes the queue as soon as the write fails
+        let dead = clients.read().await[&3].clone();
+        tokio::time::timeout(Duration::from_secs(1), dead.closed())
+            .await
+            .unwrap();
+        assert!(broadcast("second\n").await.is_empty());
+
+        assert!(!clients.read().await.contains_key(&3));
+        for reader in readers {
+            let expected = ("first".to_string(), "second".to_string());
+            assert_eq!(reader.await.unwrap(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_skips_client_that_stops_reading() {
+        let clients = SharedClients::default();
+
+        // Client 1's socket buffer is tiny and nobody reads from it
+        let (writer, _stalled) = tokio::io::duplex(64);
+        let (tx, rx) = client_queue(SEND_QUEUE_CAPACITY);
+        tokio::spawn(write_outgoing(writer, rx, Duration::ZERO, Arc::default()));
+        clients.write().await.insert(1, tx);
+
+        // Client 2 keeps up
+        let (tx, mut rx) = client_queue(SEND_QUEUE_CAPACITY);
+        clients.write().await.insert(2, tx);
+
+        let total = SEND_QUEUE_CAPACITY * 3;
+        let mut overflows = 0;
+        for i in 0..total {
+            let message: Payload = format!("message {}\n", i).as_bytes().into();
+            let overflowed = broadcast_message(
+                clients.clone(),
+                &SharedRooms::default(),
+                None,
+                None,
+                message.clone(),
+            )
+            .await;
+            assert!(overflowed.iter().all(|&id| id == 1));
+            overflows += overflowed.len();
+            assert_eq!(rx.recv().await.unwrap(), message);
+        }
+
+        // Client 1 missed the overflow but is still registered, with its spare slot free
+        // The writer task takes a few messages before the pipe fills up
+        assert!(overflows >= SEND_QUEUE_CAPACITY);
+        let clients = clients.read().await;
+        assert_eq!(clients.len(), 2);
+        assert_eq!(clients[&1].capacity(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_overflow_policies() {
+        let clients = SharedClients::default();
+        let events = EventBus::default();
+        let mut log = events.subscribe();
+        let stats = ServerStats::default();
+
+        let (tx, _rx_1) = client_queue(1);
+        clients.write().await.insert(1, tx);
+        let (tx, mut rx_2) = client_queue(1);
+        clients.write().await.insert(2, tx);
+
+        // Dropping keeps the client
+        let config = ServerConfig::default();
+        handle_overflows(&clients, &config, &events, &stats, vec![1]).await;
+        assert_eq!(
+            log.recv().await,
+            Some(ServerEvent::MessageDropped { id: 1 })
+        );
+        assert!(clients.read().await.contains_key(&1));
+
+        // Disconnecting removes the client after queuing a notice in the spare slot
+        let config = ServerConfig {
+            slow_client_policy: SlowClientPolicy::Disconnect,
+            ..ServerConfig::default()
+        };
+        clients.read().await[&2]
+            .try_send(b"queued\n".as_slice().into())
+            .unwrap();
+        handle_overflows(&clients, &config, &events, &stats, vec![2]).await;
+        assert_eq!(log.recv().await, Some(ServerEvent::ClientTooSlow { id: 2 }));
+        assert!(!clients.read().await.contains_key(&2));
+        assert_eq!(&*rx_2.recv().await.unwrap(), b"queued\n");
+        assert_eq!(
+            &*rx_2.recv().await.unwrap(),
+            format!("{}\n", TOO_SLOW_NOTICE).as_bytes()
+        );
+        assert_eq!(rx_2.recv().await, None);
+
+        assert_eq!(stats.queue_overflows(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_writes_are_batched_within_flush_delay() {
+        let flush_delay = Duration::from_millis(5);
+        let (writer, mut client) = tokio::io::duplex(1024);
+        let (tx, rx) = mpsc::channel::<Payload>(SEND_QUEUE_CAPACITY);
+        tokio::spawn(write_outgoing(writer, rx, flush_delay, Arc::default()));
+
+        // A burst queued at once arrives together, no later than the delay
+        let start = Instant::now();
+        for line in ["one\n", "two\n", "three\n"] {
+            tx.send(line.as_bytes().into()).await.unwrap();
+        }
+        let mut received = vec![0u8; 14];
+        client.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, b"one\ntwo\nthree\n");
+        assert!(start.elapsed() <= flush_delay);
+
+        // A lone message is not held back beyond the delay either
+        let start = Instant::now();
+        tx.send(b"four\n".as_slice().into()).await.unwrap();
+        let mut received = vec![0u8; 5];
+        client.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, b"four\n");
+        assert!(start.elapsed() <= flush_delay);
+
+        // Dropping the sender closes the stream
+        drop(tx);
+        assert_eq!(client.read(&mut received).await.unwrap(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_zero_flush_delay_writes_immediately() {
+        let (writer, mut client) = tokio::io::duplex(1024);
+        let (tx, rx) = mpsc::channel::<Payload>(SEND_QUEUE_CAPACITY);
+        tokio::spawn(write_outgoing(writer, rx, Duration::ZERO, Arc::default()));
+
+        let start = Instant::now();
+        tx.send(b"hello\n".as_slice().into()).await.unwrap();
+        let mut received = vec![0u8; 6];
+        client.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, b"hello\n");
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_heartbeat_gives_up_without_pongs() {
+        let clients = SharedClients::default();
+        let (tx, mut rx) = mpsc::channel(SEND_QUEUE_CAPACITY);
+        clients.write().await.insert(1, tx);
+        let (_pong_tx, pong_rx) = mpsc::channel(1);
+
+        let interval = Duration::from_secs(30);
+        let start = Instant::now();
+        heartbeat(clients, 1, Protocol::Text, interval, 1, pong_rx).await;
+
+        // One unanswered ping, then the heartbeat gives up at the second interval
+        assert_eq!(start.elapsed(), interval * 2);
+        assert_eq!(&*rx.recv().await.unwrap(), b"[Ping] 1\n");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_heartbeat_tolerates_missed_pings() {
+        let clients = SharedClients::default();
+        let (tx, mut rx) = mpsc::channel(SEND_QUEUE_CAPACITY);
+        clients.write().await.insert(1, tx);
+        let (pong_tx, pong_rx) = mpsc::channel(1);
+
+        let interval = Duration::from_secs(30);
+        let start = Instant::now();
+        let task = tokio::spawn(heartbeat(clients, 1, Protocol::Text, interval, 3, pong_rx));
+
+        // Answering the second ping also covers the first; a stale nonce does not count
+        assert_eq!(&*rx.recv().await.unwrap(), b"[Ping] 1\n");
+        assert_eq!(&*rx.recv().await.unwrap(), b"[Ping] 2\n");
+        pong_tx.send(2).await.unwrap();
+        assert_eq!(&*rx.recv().await.unwrap(), b"[Ping] 3\n");
+        pong_tx.send(1).await.unwrap();
+
+        // Pings 3, 4 and 5 go unanswered, so the sixth interval ends the heartbeat
+        task.await.unwrap();
+        assert_eq!(start.elapsed(), interval * 6);
+        assert_eq!(&*rx.recv().await.unwrap(), b"[Ping] 4\n");
+        assert_eq!(&*rx.recv().await.unwrap(), b"[Ping] 5\n");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_heartbeat_continues_while_client_answers() {
+        let clients = SharedClients::default();
+        let (tx, mut rx) = mpsc::channel(SEND_QUEUE_CAPACITY);
+        clients.write().await.insert(1, tx);
+        let (pong_tx, pong_rx) = mpsc::channel(1);
+
+        let interval = Duration::from_secs(30);
+        let task = tokio::spawn(heartbeat(clients, 1, Protocol::Text, interval, 1, pong_rx));
+
+        // A client that answers every ping is never dropped
+        for nonce in 1..=5 {
+            assert_eq!(
+                *rx.recv().await.unwrap(),
+                *format!("[Ping] {}\n", nonce).as_bytes()
+            );
+            pong_tx.send(nonce).await.unwrap();
+        }
+        assert!(!task.is_finished());
+
+        // Closing the pong channel ends the task
+        drop(pong_tx);
+        task.await.unwrap();
+    }
+
+    #[test]
+    fn test_ip_slots_are_counted_per_address() {
+        let counts = SharedIpCounts::default();
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        let other: IpAddr = "::1".parse().unwrap();
+
+        let first = IpSlot::acquire(&counts, local, Some(2)).unwrap();
+        let _second = IpSlot::acquire(&counts, local, Some(2)).unwrap();
+        assert!(IpSlot::acquire(&counts, local, Some(2)).is_none());
+        // The IPv4-mapped form of the same address shares its count
+        let mapped: IpAddr = "::ffff:127.0.0.1".parse().unwrap();
+        assert!(IpSlot::acquire(&counts, mapped, Some(2)).is_none());
+        // Other addresses are unaffected
+        let other_slot = IpSlot::acquire(&counts, other, Some(2)).unwrap();
+
+        // Dropping a slot frees it, and unused addresses are forgotten
+        drop(first);
+        let _third = IpSlot::acquire(&counts, local, Some(2)).unwrap();
+        drop(other_slot);
+        assert!(!counts.lock().unwrap().contains_key(&other));
+        assert!(IpSlot::acquire(&counts, local, None).is_some());
+    }
+
+    #[test]
+    fn test_help_lists_every_command() {
+        let help = help_messages(None);
+        assert_eq!(help.len(), COMMANDS.len() + 1);
+        for info in COMMANDS {
+            assert!(help
+                .iter()
+                .any(|line| line.to_string().contains(info.usage)));
+        }
+
+        let msg = help_messages(Some("msg"));
+        assert_eq!(
+            msg[0].to_string(),
+            "Usage: /msg <client_id|nickname>[,...] <message>"
+        );
+        assert_eq!(msg.len(), 3);
+        assert!(matches!(
+            help_messages(Some("/dance")).as_slice(),
+            [ServerMessage::Error { .. }]
+        ));
+    }
+
+    #[test]
+    fn test_check_message() {
+        let config = ServerConfig {
+            max_message_bytes: 5,
+            ..ServerConfig::default()
+        };
+
+        assert_eq!(check_message("hello", &config), None);
+        assert_eq!(
+            check_message("hello!", &config),
+            Some(RejectionReason::TooLong { max_bytes: 5 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_incoming_text() {
+        let config = ServerConfig::default();
+        let mut reader: &[u8] = b"hello\r\n\xff\xfe\nworld\n";
+
+        // Line terminators are stripped from each message, and a line of invalid
+        // UTF-8 is rejected without losing the line after it
+        assert_eq!(
+            read_incoming(&mut reader, &config).await.unwrap(),
+            Some(Incoming::Message("hello".to_string()).into())
+        );
+        assert_eq!(
+            read_incoming(&mut reader, &config).await.unwrap(),
+            Some(Incoming::Rejected(RejectionReason::InvalidUtf8).into())
+        );
+        assert_eq!(
+            read_incoming(&mut reader, &config).await.unwrap(),
+            Some(Incoming::Message("world".to_string()).into())
+        );
+        assert_eq!(read_incoming(&mut reader, &config).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_incoming_framed() {
+        let config = ServerConfig {
+            max_message_bytes: 32,
+            protocol: Protocol::Json,
+            ..ServerConfig::default()
+        };
+        let mut stream = crate::framing::encode_frame(b"line one\nline two").unwrap();
+        stream.extend(crate::framing::encode_frame(&[b'a'; 33]).unwrap());
+        stream.extend(crate::framing::encode_frame(br#"{"id":"m1","body":"hi"}"#).unwrap());
+        stream
+            .extend(crate::framing::encode_frame(br#"{"id":"m2","body":"hello there"}"#).unwrap());
+        let mut reader = stream.as_slice();
+
+        // Newlines survive inside a frame, and oversized frames are rejected
+        assert_eq!(
+            read_incoming(&mut reader, &config).await.unwrap(),
+            Some(Incoming::Message("line one\nline two".to_string()).into())
+        );
+        assert_eq!(
+            read_incoming(&mut reader, &config).await.unwrap(),
+            Some(Incoming::Rejected(RejectionReason::TooLong { max_bytes: 32 }).into())
+        );
+
+        // Messages with an ID are unwrapped, and the limit applies to their body
+        let config = ServerConfig {
+            max_message_bytes: 8,
+            ..config
+        };
+        assert_eq!(
+            read_incoming(&mut reader, &config).await.unwrap(),
+            Some(Received {
+                incoming: Incoming::Message("hi".to_string()),
+                id: Some("m1".to_string()),
+            })
+        );
+        assert_eq!(
+            read_incoming(&mut reader, &config).await.unwrap(),
+            Some(Received {
+                incoming: Incoming::Rejected(RejectionReason::TooLong { max_bytes: 8 }),
+                id: Some("m2".to_string()),
+            })
+        );
+        assert_eq!(read_incoming(&mut reader, &config).await.unwrap(), None);
+    }
 }
@@ -0,0 +1,304 @@
+//! The settings module moves what the client keeps on disk to another machine.
+//!
+//! ## Overview
+//! [`export_settings`] writes the client's saved state into a single versioned JSON
+//! [`SettingsBundle`], and [`import_settings`] restores one, either replacing what is
+//! already there or adding to it (see [`ImportPolicy`]). The only state the client
+//! saves today is its input history (see [`crate::input`]). It keeps no secrets on
+//! disk, since end-to-end keys last only as long as a session, so a bundle never
+//! holds any.
+//!
+//! ## Key Features
+//! - **Versioned**: Every bundle carries [`SETTINGS_VERSION`], and bundles written
+//!   by another version are refused.
+//! - **All or Nothing**: A bundle with any damaged section is refused as a whole,
+//!   with a report naming every section that failed, so nothing is half-imported.
+
+use std::{io, path::Path};
+
+use rustyline::history::{FileHistory, History};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ChatError;
+
+/// The version written into every bundle, and the only one [`SettingsBundle::from_json`]
+/// accepts.
+pub const SETTINGS_VERSION: u32 = 1;
+
+/// The sections a bundle holds, besides its version.
+const SECTIONS: &[&str] = &["input_history"];
+
+/// Everything the client keeps on disk, as moved between machines.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SettingsBundle {
+    /// The [`SETTINGS_VERSION`] the bundle was written with.
+    pub version: u32,
+    /// The lines the user sent before, oldest first.
+    pub input_history: Vec<String>,
+}
+
+/// How [`import_settings`] treats state that is already on the machine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImportPolicy {
+    /// Replace it with the bundle's.
+    Overwrite,
+    /// Keep it and add whatever the bundle has that it lacks. The bundle's history
+    /// comes first, as the older of the two.
+    #[default]
+    Merge,
+}
+
+impl SettingsBundle {
+    /// Writes the bundle as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a settings bundle is plain JSON")
+    }
+
+    /// Reads a bundle written by [`SettingsBundle::to_json`].
+    ///
+    /// # Errors
+    /// Returns [`ChatError::Config`] if `text` is not a JSON object, or listing every
+    /// section that is missing, unknown or damaged, and the version if it is not
+    /// [`SETTINGS_VERSION`].
+    ///
+    /// # Example
+    /// ```
+    /// use chat_app::settings::SettingsBundle;
+    ///
+    /// let bundle = SettingsBundle::from_json(r#"{"version":1,"input_history":["hi"]}"#).unwrap();
+    /// assert_eq!(bundle.input_history, ["hi"]);
+    ///
+    /// let error = SettingsBundle::from_json(r#"{"version":2,"input_history":[1]}"#).unwrap_err();
+    /// assert!(error.to_string().contains("version 2"));
+    /// assert!(error.to_string().contains("input_history"));
+    /// ```
+    pub fn from_json(text: &str) -> Result<Self, ChatError> {
+        let serde_json::Value::Object(mut sections) =
+            serde_json::from_str(text).map_err(|e| damaged(vec![e.to_string()]))?
+        else {
+            return Err(damaged(vec!["not a JSON object".to_string()]));
+        };
+
+        let mut failures = Vec::new();
+        match sections
+            .remove("version")
+            .map(serde_json::from_value::<u32>)
+        {
+            Some(Ok(SETTINGS_VERSION)) => {}
+            Some(Ok(version)) => failures.push(format!(
+                "version {} is not supported (expected {})",
+                version, SETTINGS_VERSION
+            )),
+            Some(Err(e)) => failures.push(format!("version: {}", e)),
+            None => failures.push("version is missing".to_string()),
+        }
+        let input_history = match sections.remove("input_history") {
+            Some(value) => serde_json::from_value(value)
+                .map_err(|e| failures.push(format!("input_history: {}", e)))
+                .ok(),
+            None => {
+                failures.push("input_history is missing".to_string());
+                None
+            }
+        };
+        for unknown in sections.keys() {
+            failures.push(format!(
+                "unknown section {} (expected {})",
+                unknown,
+                SECTIONS.join(", ")
+            ));
+        }
+
+        match input_history {
+            Some(input_history) if failures.is_empty() => Ok(SettingsBundle {
+                version: SETTINGS_VERSION,
+                input_history,
+            }),
+            _ => Err(damaged(failures)),
+        }
+    }
+}
+
+/// Writes the client's saved state to a bundle at `path`.
+///
+/// # Arguments
+/// - `path`: Where to write the bundle.
+/// - `history_file`: The input history to include; a missing file counts as empty.
+///
+/// # Errors
+/// Returns [`ChatError::Io`] if the history cannot be read or the bundle written.
+pub fn export_settings(path: &Path, history_file: &Path) -> Result<(), ChatError> {
+    let bundle = SettingsBundle {
+        version: SETTINGS_VERSION,
+        input_history: read_history(history_file)?,
+    };
+    std::fs::write(path, bundle.to_json())?;
+    Ok(())
+}
+
+/// Restores the client's saved state from the bundle at `path`.
+///
+/// The whole bundle is checked before anything is written, so a damaged one changes
+/// nothing.
+///
+/// # Arguments
+/// - `path`: The bundle written by [`export_settings`].
+/// - `history_file`: The input history to restore into.
+/// - `policy`: Whether to replace the state already there or add to it.
+///
+/// # Errors
+/// Returns [`ChatError::Io`] if a file cannot be read or written, or
+/// [`ChatError::Config`] if the bundle is damaged (see [`SettingsBundle::from_json`]).
+pub fn import_settings(
+    path: &Path,
+    history_file: &Path,
+    policy: ImportPolicy,
+) -> Result<(), ChatError> {
+    let bundle = SettingsBundle::from_json(&std::fs::read_to_string(path)?)?;
+    let history = match policy {
+        ImportPolicy::Overwrite => bundle.input_history,
+        ImportPolicy::Merge => {
+            let current = read_history(history_file)?;
+            let mut history: Vec<String> = bundle
+                .input_history
+                .into_iter()
+                .filter(|line| !current.contains(line))
+                .collect();
+            history.extend(current);
+            history
+        }
+    };
+    write_history(history_file, &history)?;
+    Ok(())
+}
+
+/// Reads the lines in a history file kept by [`crate::input`], oldest first.
+fn read_history(path: &Path) -> io::Result<Vec<String>> {
+    let mut history = FileHistory::new();
+    match history.load(path) {
+        Ok(()) => Ok(history.iter().cloned().collect()),
+        Err(rustyline::error::ReadlineError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+            Ok(Vec::new())
+        }
+        Err(e) => Err(io::Error::other(e)),
+    }
+}
+
+/// Replaces the lines in a history file kept by [`crate::input`].
+fn write_history(path: &Path, lines: &[String]) -> io::Result<()> {
+    // An empty history is not saved, so it would leave the old file in place
+    if lines.is_empty() {
+        return std::fs::write(path, "");
+    }
+    let mut history = FileHistory::new();
+    for line in lines {
+        history.add(line).map_err(io::Error::other)?;
+    }
+    history.save(path).map_err(io::Error::other)
+}
+
+/// The error for a bundle that cannot be imported, listing what is wrong with it.
+fn damaged(failures: Vec<String>) -> ChatError {
+    ChatError::Config(format!(
+        "refusing damaged settings bundle: {}",
+        failures.join("; ")
+    ))
+}
+
+/// Tests for the settings module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A file in the temp directory whose name is unique to this test run.
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("chat-settings-{}-{}", std::process::id(), name))
+    }
+
+    /// Owned copies of `lines`, to compare with a history.
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (history, bundle, restored) = (
+            temp_file("round-history"),
+            temp_file("round-bundle"),
+            temp_file("round-restored"),
+        );
+        let sent = lines(&["hello", "/nick alice", "two\nlines"]);
+        write_history(&history, &sent).unwrap();
+
+        export_settings(&bundle, &history).unwrap();
+        import_settings(&bundle, &restored, ImportPolicy::Overwrite).unwrap();
+        assert_eq!(read_history(&restored).unwrap(), sent);
+
+        // A machine with no history yet exports an empty one
+        std::fs::remove_file(&history).unwrap();
+        export_settings(&bundle, &history).unwrap();
+        import_settings(&bundle, &restored, ImportPolicy::Overwrite).unwrap();
+        assert!(read_history(&restored).unwrap().is_empty());
+
+        for path in [bundle, restored] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_import_policies() {
+        let (history, bundle) = (temp_file("policy-history"), temp_file("policy-bundle"));
+        let exported = SettingsBundle {
+            version: SETTINGS_VERSION,
+            input_history: lines(&["old", "shared"]),
+        };
+        std::fs::write(&bundle, exported.to_json()).unwrap();
+
+        // Merging keeps every line once, with the imported ones first
+        write_history(&history, &lines(&["shared", "new"])).unwrap();
+        import_settings(&bundle, &history, ImportPolicy::Merge).unwrap();
+        assert_eq!(
+            read_history(&history).unwrap(),
+            lines(&["old", "shared", "new"])
+        );
+
+        // Overwriting leaves only the bundle's lines
+        import_settings(&bundle, &history, ImportPolicy::Overwrite).unwrap();
+        assert_eq!(read_history(&history).unwrap(), lines(&["old", "shared"]));
+
+        for path in [history, bundle] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_damaged_bundles_are_refused_whole() {
+        let (history, bundle) = (temp_file("damaged-history"), temp_file("damaged-bundle"));
+        write_history(&history, &lines(&["keep me"])).unwrap();
+
+        // Every failure is reported, and nothing is imported
+        std::fs::write(
+            &bundle,
+            r#"{"version":1,"input_history":["fine",7],"profiles":{}}"#,
+        )
+        .unwrap();
+        let error = import_settings(&bundle, &history, ImportPolicy::Overwrite)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("input_history: invalid type"), "{}", error);
+        assert!(error.contains("unknown section profiles"), "{}", error);
+        assert_eq!(read_history(&history).unwrap(), lines(&["keep me"]));
+
+        let error = |text: &str| SettingsBundle::from_json(text).unwrap_err().to_string();
+        assert!(error(r#"{"input_history":[]}"#).contains("version is missing"));
+        assert!(error(r#"{"version":9,"input_history":[]}"#).contains("version 9"));
+        assert!(error(r#"{"version":1}"#).contains("input_history is missing"));
+        assert!(error(r#"["hi"]"#).contains("not a JSON object"));
+        assert!(error(r#"{"version":1,"#).contains("EOF"));
+
+        for path in [history, bundle] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! The socket module tunes the TCP connections the server and client open.
+//!
+//! ## Overview
+//! Chat traffic is a trickle of small, latency-sensitive writes, which is the worst
+//! case for Nagle's algorithm, and a peer that vanishes without closing its
+//! connection is never noticed by the OS unless keepalive probes are sent.
+//! [`SocketOptions`] turns both on by default, and is applied to every accepted
+//! socket (see [`crate::server::ServerConfig::socket_options`]) and to the client's
+//! connection (see [`crate::client::ClientOptions::socket_options`]).
+//!
+//! ## Key Features
+//! - **Low Latency**: `TCP_NODELAY` sends each message as soon as it is written.
+//! - **Dead Peer Detection**: TCP keepalive probes an idle connection, configured
+//!   through `socket2` since tokio does not expose it.
+
+use std::{io, time::Duration};
+
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::TcpStream;
+
+/// The TCP options set on a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// Whether to set `TCP_NODELAY`, sending small writes without waiting to batch them.
+    pub nodelay: bool,
+    /// How long a connection may sit idle before keepalive probes are sent, and how
+    /// long to wait between probes, or `None` to send no probes.
+    pub keepalive: Option<Duration>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+impl SocketOptions {
+    /// Applies the options to a connected socket.
+    ///
+    /// # Errors
+    /// Returns an error if the OS refuses one of the options.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use chat_app::socket::SocketOptions;
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> std::io::Result<()> {
+    ///     let stream = TcpStream::connect("127.0.0.1:8080").await?;
+    ///     SocketOptions::default().apply(&stream)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        let socket = SockRef::from(stream);
+        match self.keepalive {
+            Some(interval) => {
+                let keepalive = TcpKeepalive::new().with_time(interval);
+                #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+                let keepalive = keepalive.with_interval(interval);
+                socket.set_tcp_keepalive(&keepalive)
+            }
+            None => socket.set_keepalive(false),
+        }
+    }
+}
+
+/// Tests for the socket module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Connects a client socket to a server socket over loopback.
+    async fn connected_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn test_options_are_applied() {
+        let (client, server) = connected_pair().await;
+        let options = SocketOptions {
+            nodelay: true,
+            keepalive: Some(Duration::from_secs(42)),
+        };
+        options.apply(&client).unwrap();
+        options.apply(&server).unwrap();
+
+        for stream in [&client, &server] {
+            assert!(stream.nodelay().unwrap());
+            let socket = SockRef::from(stream);
+            assert!(socket.keepalive().unwrap());
+            #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+            assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(42));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_options_can_be_turned_off() {
+        let (client, _server) = connected_pair().await;
+        SocketOptions::default().apply(&client).unwrap();
+
+        let options = SocketOptions {
+            nodelay: false,
+            keepalive: None,
+        };
+        options.apply(&client).unwrap();
+        assert!(!client.nodelay().unwrap());
+        assert!(!SockRef::from(&client).keepalive().unwrap());
+    }
+}
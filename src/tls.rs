@@ -0,0 +1,143 @@
+//! The tls module loads the certificates that encrypt connections.
+//!
+//! ## Overview
+//! A server given a [`TlsConfig`] (see [`crate::server::ServerConfig::tls`]) performs a
+//! TLS handshake on every connection before anything else, and speaks its usual
+//! protocol inside the encrypted stream. [`load_config`] builds that configuration
+//! from PEM files, such as those issued by Let's Encrypt or made with `openssl`.
+//! The client connects with TLS to `tls://` addresses, trusting the authorities
+//! loaded by [`load_client_config`].
+//!
+//! ## Key Features
+//! - **Optional**: Available with the `tls` feature, enabled by default. Servers
+//!   without a [`TlsConfig`] keep accepting plaintext connections.
+//! - **Pure Rust**: Uses `rustls` with the `ring` crypto backend, and the Mozilla root
+//!   certificates from `webpki-roots` rather than the system's store.
+
+use std::{fs::File, io, io::BufReader, path::Path, sync::Arc};
+use tokio_rustls::rustls::RootCertStore;
+
+pub use tokio_rustls::rustls::ClientConfig as ClientTlsConfig;
+pub use tokio_rustls::rustls::ServerConfig as TlsConfig;
+
+/// Loads a certificate chain and its private key from PEM files.
+///
+/// # Arguments
+/// - `cert_path`: The certificate chain, leaf certificate first.
+/// - `key_path`: The private key for the leaf certificate, in PKCS#8, PKCS#1 or SEC1
+///   format.
+///
+/// # Errors
+/// Returns an error if either file cannot be read, holds no certificate or key, or
+/// if the key does not match the certificate.
+///
+/// # Example
+/// ```no_run
+/// use chat_app::{error::ChatError, server::ChatServer, tls};
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), ChatError> {
+///     let tls = tls::load_config("cert.pem", "key.pem")?;
+///     let server = ChatServer::builder().tls(tls).build().await?;
+///     server.run().await
+/// }
+/// ```
+pub fn load_config(
+    cert_path: impl AsRef<Path>,
+    key_path: impl AsRef<Path>,
+) -> io::Result<Arc<TlsConfig>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no certificate found",
+        ));
+    }
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key_path)?))?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no private key found"))?;
+
+    let config = TlsConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(Arc::new(config))
+}
+
+/// Loads the certificate authorities a client trusts when connecting with TLS.
+///
+/// # Arguments
+/// - `ca_path`: A PEM file of CA certificates to trust, such as the certificate of a
+///   self-signed server, or `None` to trust the well-known web authorities.
+///
+/// # Errors
+/// Returns an error if the file cannot be read or holds no valid certificate.
+///
+/// # Example
+/// ```
+/// use chat_app::tls;
+///
+/// let config = tls::load_client_config(None).unwrap();
+/// assert!(config.alpn_protocols.is_empty());
+/// ```
+pub fn load_client_config(ca_path: Option<&Path>) -> io::Result<Arc<ClientTlsConfig>> {
+    let mut roots = RootCertStore::empty();
+    match ca_path {
+        Some(path) => {
+            for cert in rustls_pemfile::certs(&mut BufReader::new(File::open(path)?)) {
+                roots
+                    .add(cert?)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            }
+            if roots.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "no certificate found",
+                ));
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+
+    let config = ClientTlsConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// Tests for the tls module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Returns an empty scratch directory unique to `name`.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("chat-tls-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_load_generated_certificate() {
+        let dir = scratch_dir("load");
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(dir.join("cert.pem"), cert.cert.pem()).unwrap();
+        std::fs::write(dir.join("key.pem"), cert.key_pair.serialize_pem()).unwrap();
+
+        assert!(load_config(dir.join("cert.pem"), dir.join("key.pem")).is_ok());
+        assert!(load_client_config(Some(&dir.join("cert.pem"))).is_ok());
+
+        // The certificate is not a key, and an empty file holds nothing
+        let error = load_config(dir.join("cert.pem"), dir.join("cert.pem")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        std::fs::write(dir.join("empty.pem"), "").unwrap();
+        let error = load_config(dir.join("empty.pem"), dir.join("key.pem")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = load_client_config(Some(&dir.join("empty.pem"))).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
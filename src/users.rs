@@ -0,0 +1,180 @@
+//! The users module holds the accounts clients log in with.
+//!
+//! ## Overview
+//! A [`UserStore`] maps usernames to bcrypt password hashes, loaded from a TOML file
+//! with one `username = "hash"` entry per line. When the server is given a store
+//! (see [`crate::server::ServerConfig::users`]), every client must log in with a
+//! username and password before it can chat, and chats under its username. Accounts
+//! are added with `chat adduser <name> <password>` (see [`add_user`]).
+//!
+//! ## Key Features
+//! - **Hashed**: Only bcrypt hashes are stored, never passwords.
+//! - **Guest Mode**: A missing users file gives an empty store, in which case the
+//!   server lets clients pick any free nickname instead.
+
+use std::{collections::BTreeMap, fs, io, io::Write, path::Path};
+
+use crate::nicknames::is_valid_nickname;
+
+/// The users file read by the `server` and `adduser` commands unless told otherwise.
+pub const DEFAULT_USERS_FILE: &str = "users.toml";
+
+/// The bcrypt cost used for new passwords.
+pub const HASH_COST: u32 = bcrypt::DEFAULT_COST;
+
+/// Usernames and their password hashes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserStore {
+    /// The bcrypt hash of each user's password, by username.
+    users: BTreeMap<String, String>,
+}
+
+impl UserStore {
+    /// Loads the users file at `path`.
+    ///
+    /// # Returns
+    /// An empty store if the file does not exist.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or is not a valid users file.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::from_toml(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Parses the contents of a users file.
+    ///
+    /// # Errors
+    /// Returns an error if `text` is not a TOML table of strings, or names a user that
+    /// is not a valid nickname.
+    ///
+    /// # Example
+    /// ```
+    /// use chat_app::users::UserStore;
+    ///
+    /// let hash = bcrypt::hash("hunter2", 4).unwrap();
+    /// let store = UserStore::from_toml(&format!("alice = {:?}", hash)).unwrap();
+    /// assert!(store.verify("alice", "hunter2"));
+    /// ```
+    pub fn from_toml(text: &str) -> io::Result<Self> {
+        let users: BTreeMap<String, String> =
+            toml::from_str(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(name) = users.keys().find(|name| !is_valid_nickname(name)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid username: {}", name),
+            ));
+        }
+        Ok(Self { users })
+    }
+
+    /// Returns whether the store has no users, which puts the server in guest mode.
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    /// Returns whether `username` has an account.
+    pub fn contains(&self, username: &str) -> bool {
+        self.users.contains_key(username)
+    }
+
+    /// Checks `password` against the hash stored for `username`.
+    ///
+    /// This is deliberately slow, so callers on the async runtime should run it with
+    /// [`tokio::task::spawn_blocking`].
+    ///
+    /// # Returns
+    /// `false` if the user is unknown or the password is wrong.
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        self.users
+            .get(username)
+            .is_some_and(|hash| bcrypt::verify(password, hash).unwrap_or(false))
+    }
+}
+
+/// Adds an account to the users file at `path`, creating the file if needed.
+///
+/// The entry is appended, so the rest of the file is kept as it is.
+///
+/// # Errors
+/// Returns an error if `username` is not a valid nickname or already has an account,
+/// or if the file cannot be read or written.
+pub fn add_user(path: impl AsRef<Path>, username: &str, password: &str) -> io::Result<()> {
+    let hash = bcrypt::hash(password, HASH_COST).map_err(io::Error::other)?;
+    append_user(path.as_ref(), username, &hash)
+}
+
+/// Appends `username` with an already computed `hash` to the users file at `path`.
+fn append_user(path: &Path, username: &str, hash: &str) -> io::Result<()> {
+    if !is_valid_nickname(username) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid username: {}", username),
+        ));
+    }
+    if UserStore::load(path)?.contains(username) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("user {} already exists", username),
+        ));
+    }
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    // Valid usernames are bare TOML keys, and the value is quoted by `toml` itself
+    writeln!(file, "{} = {}", username, toml::Value::from(hash))
+}
+
+/// Tests for the users module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cheap bcrypt cost, so the tests do not spend seconds hashing.
+    const TEST_COST: u32 = 4;
+
+    #[test]
+    fn test_verify_passwords() {
+        let hash = bcrypt::hash("hunter2", TEST_COST).unwrap();
+        let store = UserStore::from_toml(&format!("alice = {:?}", hash)).unwrap();
+
+        assert!(store.verify("alice", "hunter2"));
+        assert!(!store.verify("alice", "hunter3"));
+        assert!(!store.verify("bob", "hunter2"));
+    }
+
+    #[test]
+    fn test_append_users() {
+        let path = std::env::temp_dir().join(format!("chat-users-{}.toml", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        // A missing file is an empty store
+        assert!(UserStore::load(&path).unwrap().is_empty());
+
+        let hash = bcrypt::hash("secret", TEST_COST).unwrap();
+        append_user(&path, "alice", &hash).unwrap();
+        append_user(&path, "bob", &hash).unwrap();
+        let store = UserStore::load(&path).unwrap();
+        assert!(store.verify("alice", "secret"));
+        assert!(store.verify("bob", "secret"));
+
+        let error = append_user(&path, "alice", &hash).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        let error = append_user(&path, "not valid", &hash).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_users_file() {
+        assert!(UserStore::from_toml("alice = 1").is_err());
+        assert!(UserStore::from_toml("\"Client 1\" = \"hash\"").is_err());
+        assert!(UserStore::from_toml("").unwrap().is_empty());
+    }
+}
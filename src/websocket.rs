@@ -0,0 +1,112 @@
+//! The websocket module lets clients that cannot open raw TCP sockets, such as
+//! browsers, chat over WebSocket.
+//!
+//! ## Overview
+//! A server whose [`crate::server::ServerConfig::transport`] is
+//! [`Transport::WebSocket`](crate::server::Transport::WebSocket) looks at the start of
+//! every plaintext TCP connection. One that opens with an HTTP `GET` is upgraded to a
+//! WebSocket; any other is served as raw TCP, so both kinds of client share a port.
+//! [`bridge`] then joins the WebSocket to the server's usual byte stream through a
+//! [`Codec`]: every message the server writes goes out as one text message, and every
+//! text message received is read as one line of input. The client uses the same bridge
+//! for `ws://` URLs (see [`crate::client::run_ws_client`]).
+//!
+//! ## Key Features
+//! - **Optional**: Available with the `websocket` feature, enabled by default.
+//! - **Per Connection**: The transport is detected from each connection's first bytes
+//!   rather than set for the whole server.
+//! - **Same Protocol**: A WebSocket client receives the server's [`crate::protocol::Protocol`]
+//!   one message at a time: text lines, or JSON envelopes with `framed-json`.
+
+use crate::codec::Codec;
+use futures_util::{SinkExt, StreamExt};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream},
+    net::TcpStream,
+};
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+
+/// How long the server waits for a new connection's first bytes before serving it as
+/// raw TCP. Raw clients that wait for the server to speak first are greeted this much
+/// later.
+pub const UPGRADE_WAIT: Duration = Duration::from_millis(200);
+
+/// How long a client has to complete the WebSocket handshake.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The size of the in-memory pipe between a WebSocket and the chat.
+pub const BRIDGE_BUFFER: usize = 64 * 1024;
+
+/// Returns whether `socket` opens with an HTTP `GET`, the start of a WebSocket
+/// upgrade request, without consuming any of its bytes.
+///
+/// A connection that sends nothing within [`UPGRADE_WAIT`] is not an upgrade.
+pub async fn is_upgrade_request(socket: &TcpStream) -> bool {
+    let mut start = [0; 4];
+    matches!(
+        tokio::time::timeout(UPGRADE_WAIT, socket.peek(&mut start)).await,
+        Ok(Ok(4)) if &start == b"GET "
+    )
+}
+
+/// Relays messages between a WebSocket and one end of a byte stream until either
+/// side closes.
+///
+/// Bytes written to the far end of `stream` are split into messages by `codec` and
+/// sent as text messages; text messages received are encoded by `codec` and written
+/// to `stream`. Binary messages are read as text, and messages too large to encode
+/// are dropped. When the stream ends, the WebSocket is closed, and the other way
+/// around.
+///
+/// # Arguments
+/// - `websocket`: An upgraded connection.
+/// - `stream`: The end of the pipe the chat is not using.
+/// - `codec`: The protocol the chat speaks over the pipe.
+pub async fn bridge<S>(websocket: WebSocketStream<S>, stream: DuplexStream, mut codec: Codec)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut sink, mut source) = websocket.split();
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let mut buf = vec![0; 8 * 1024];
+
+    'relay: loop {
+        tokio::select! {
+            received = source.next() => {
+                let text = match received {
+                    Some(Ok(Message::Text(text))) => text.to_string(),
+                    Some(Ok(Message::Binary(bytes))) => String::from_utf8_lossy(&bytes).into_owned(),
+                    // Pings are answered by the WebSocket itself
+                    Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => continue,
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                };
+                let Ok(bytes) = codec.encode(&text) else { continue };
+                if writer.write_all(&bytes).await.is_err() {
+                    break;
+                }
+            }
+            read = reader.read(&mut buf) => {
+                let read = match read {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => read,
+                };
+                codec.push(&buf[..read]);
+                loop {
+                    match codec.next_message() {
+                        Ok(Some(message)) => {
+                            if sink.send(Message::text(message)).await.is_err() {
+                                break 'relay;
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(_) => break 'relay,
+                    }
+                }
+            }
+        }
+    }
+
+    let _ = writer.shutdown().await;
+    let _ = sink.close().await;
+}